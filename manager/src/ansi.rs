@@ -0,0 +1,262 @@
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Style {
+    fg: Option<String>,
+    bg: Option<String>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+    hidden: bool,
+    strike: bool,
+}
+
+impl Style {
+    fn css(&self) -> String {
+        let (mut fg, mut bg) = (self.fg.clone(), self.bg.clone());
+        if self.inverse {
+            let t = fg.unwrap_or_else(|| "var(--term-fg)".into());
+            fg = Some(bg.unwrap_or_else(|| "var(--term-bg)".into()));
+            bg = Some(t);
+        }
+        let mut css = String::new();
+        if let Some(c) = fg {
+            let _ = write!(css, "color:{};", c);
+        }
+        if let Some(c) = bg {
+            let _ = write!(css, "background:{};", c);
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.7;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        match (self.underline, self.strike) {
+            (true, true) => css.push_str("text-decoration:underline line-through;"),
+            (true, false) => css.push_str("text-decoration:underline;"),
+            (false, true) => css.push_str("text-decoration:line-through;"),
+            (false, false) => {}
+        }
+        if self.hidden {
+            css.push_str("visibility:hidden;");
+        }
+        css
+    }
+
+    fn apply_sgr(&mut self, params: &[u32]) {
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                8 => self.hidden = true,
+                9 => self.strike = true,
+                21 | 24 => self.underline = false,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                27 => self.inverse = false,
+                28 => self.hidden = false,
+                29 => self.strike = false,
+                n @ 30..=37 => self.fg = Some(palette(n - 30)),
+                39 => self.fg = None,
+                n @ 40..=47 => self.bg = Some(palette(n - 40)),
+                49 => self.bg = None,
+                n @ 90..=97 => self.fg = Some(palette(n - 90 + 8)),
+                n @ 100..=107 => self.bg = Some(palette(n - 100 + 8)),
+                n @ (38 | 48) => {
+                    let color = match params.get(i + 1) {
+                        Some(5) => {
+                            let c = params.get(i + 2).copied().unwrap_or(0);
+                            i += 2;
+                            Some(palette(c))
+                        }
+                        Some(2) => {
+                            let rgb = |k: usize| params.get(i + k).copied().unwrap_or(0).min(255);
+                            let c = format!("rgb({},{},{})", rgb(2), rgb(3), rgb(4));
+                            i += 4;
+                            Some(c)
+                        }
+                        _ => None,
+                    };
+                    if n == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+/// xterm 256-colour palette, with the 16 base colours matching
+/// `static/terminal.js` so both views look the same
+fn palette(n: u32) -> String {
+    const BASE16: [&str; 16] = [
+        "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+        "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+    ];
+    match n {
+        0..=15 => BASE16[n as usize].to_string(),
+        16..=231 => {
+            const STEPS: [u32; 6] = [0, 95, 135, 175, 215, 255];
+            let n = n - 16;
+            format!(
+                "rgb({},{},{})",
+                STEPS[(n / 36) as usize],
+                STEPS[((n / 6) % 6) as usize],
+                STEPS[(n % 6) as usize]
+            )
+        }
+        _ => {
+            let v = 8 + (n.min(255) - 232) * 10;
+            format!("rgb({},{},{})", v, v, v)
+        }
+    }
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+/// Converts `tmux capture-pane -e` output to HTML one line at a time.
+///
+/// capture-pane only emits SGR sequences (colours and text attributes), so
+/// those are all that is interpreted; other escape sequences are dropped.
+/// Attributes carry over from one line to the next, but every returned line
+/// is self-contained HTML, so lines can be replaced individually.
+#[derive(Default)]
+pub struct Converter {
+    style: Style,
+}
+
+impl Converter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line_to_html(&mut self, line: &str) -> String {
+        let mut html = String::new();
+        self.parse_line(line, |text, style| push_run(&mut html, text, style));
+        html
+    }
+
+    /// Split `line` into runs of text sharing one style, updating the
+    /// carried-over style as SGR sequences are seen
+    fn parse_line(&mut self, line: &str, mut emit: impl FnMut(&str, &Style)) {
+        let mut run = String::new();
+        let mut run_style = self.style.clone();
+
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                if c >= ' ' || c == '\t' {
+                    run.push(c);
+                }
+                continue;
+            }
+            match chars.next() {
+                Some('[') => {
+                    let mut seq = String::new();
+                    let mut fin = None;
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            fin = Some(c);
+                            break;
+                        }
+                        seq.push(c);
+                    }
+                    if fin == Some('m') && !seq.starts_with(['?', '>', '<', '=']) {
+                        let params: Vec<u32> = if seq.is_empty() {
+                            Vec::new()
+                        } else {
+                            seq.split([';', ':'])
+                                .map(|p| p.parse().unwrap_or(0))
+                                .collect()
+                        };
+                        self.style.apply_sgr(&params);
+                    }
+                }
+                // OSC (e.g. hyperlinks): skip to BEL or ST
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if self.style != run_style {
+                if !run.is_empty() {
+                    emit(&run, &run_style);
+                }
+                run.clear();
+                run_style = self.style.clone();
+            }
+        }
+        if !run.is_empty() {
+            emit(&run, &run_style);
+        }
+    }
+}
+
+fn push_run(html: &mut String, text: &str, style: &Style) {
+    let css = style.css();
+    if css.is_empty() {
+        escape_html(text, html);
+    } else {
+        let _ = write!(html, "<span style=\"{}\">", css);
+        escape_html(text, html);
+        html.push_str("</span>");
+    }
+}
+
+/// Convert a whole multi-line ANSI capture to HTML lines
+pub fn to_html_lines(ansi: &str) -> Vec<String> {
+    let mut conv = Converter::new();
+    ansi.lines().map(|l| conv.line_to_html(l)).collect()
+}
+
+/// Drop all escape sequences, keeping only the text
+pub fn strip(ansi: &str) -> Vec<String> {
+    let mut conv = Converter::new();
+    ansi.lines()
+        .map(|l| {
+            let mut text = String::new();
+            conv.parse_line(l, |run, _| text.push_str(run));
+            text
+        })
+        .collect()
+}
@@ -1,10 +1,17 @@
 use askama::Template;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Json, Redirect, Response};
+use serde::Serialize;
+use std::collections::HashMap;
 
-use crate::metrics::{self, ClaudeMetrics, SystemMetrics};
-use crate::sandbox;
+use crate::audit::{AuditAction, AuditFilter, Caller};
+use crate::cost::CostLabels;
+use crate::input::{self, SendInputRequest};
+use crate::metrics::{ClaudeMetrics, SystemMetrics};
+use crate::resources::{human_bytes, ResourceUsage};
+use crate::sandbox::{self, CreateError};
+use crate::screenshot;
 use crate::state::{CreateSandboxRequest, Sandbox, SharedState};
 
 // ---------------------------------------------------------------------------
@@ -15,6 +22,8 @@ use crate::state::{CreateSandboxRequest, Sandbox, SharedState};
 #[template(path = "index.html")]
 pub struct IndexTemplate {
     pub sandboxes: Vec<Sandbox>,
+    pub usage: HashMap<String, ResourceUsage>,
+    pub costs: CostLabels,
 }
 
 #[derive(Template)]
@@ -26,6 +35,7 @@ pub struct NewTemplate;
 pub struct SandboxDetailTemplate {
     pub sandbox: Sandbox,
     pub metrics: Option<ClaudeMetrics>,
+    pub resources: Option<ResourceUsage>,
 }
 
 // ---------------------------------------------------------------------------
@@ -33,10 +43,38 @@ pub struct SandboxDetailTemplate {
 // ---------------------------------------------------------------------------
 
 pub async fn index(State(state): State<SharedState>) -> impl IntoResponse {
-    let manager = state.manager.read().await;
-    let mut sandboxes: Vec<Sandbox> = manager.sandboxes.values().cloned().collect();
-    sandboxes.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-    IndexTemplate { sandboxes }
+    let mut sandboxes: Vec<Sandbox> = {
+        let manager = state.manager.read().await;
+        manager.sandboxes.values().cloned().collect()
+    };
+    sandboxes.sort_by_key(|sb| std::cmp::Reverse(sb.created_at));
+    let usage = sample_resources(&state, sandboxes.clone()).await;
+    let costs = CostLabels::new(&state, sandboxes.clone()).await;
+    IndexTemplate {
+        sandboxes,
+        usage,
+        costs,
+    }
+}
+
+/// Resource usage of the given sandboxes, sampled off the async runtime
+pub async fn sample_resources(
+    state: &SharedState,
+    sandboxes: Vec<Sandbox>,
+) -> HashMap<String, ResourceUsage> {
+    let s = state.clone();
+    tokio::task::spawn_blocking(move || s.resources.sample(&sandboxes))
+        .await
+        .unwrap_or_default()
+}
+
+/// Claude session totals of a sandbox, read off the async runtime
+pub async fn claude_totals(state: &SharedState, sandbox: Sandbox) -> Option<ClaudeMetrics> {
+    let s = state.clone();
+    tokio::task::spawn_blocking(move || s.sessions.totals(&sandbox))
+        .await
+        .ok()
+        .flatten()
 }
 
 pub async fn new_sandbox_form() -> impl IntoResponse {
@@ -47,13 +85,15 @@ pub async fn sandbox_detail(
     State(state): State<SharedState>,
     Path(id): Path<String>,
 ) -> Response {
-    let manager = state.manager.read().await;
-    match manager.sandboxes.get(&id) {
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    match sandbox {
         Some(sb) => {
-            let claude_metrics = metrics::parse_claude_metrics(&sb.project_dir);
+            let claude_metrics = claude_totals(&state, sb.clone()).await;
+            let resources = sample_resources(&state, vec![sb.clone()]).await.remove(&id);
             SandboxDetailTemplate {
-                sandbox: sb.clone(),
+                sandbox: sb,
                 metrics: claude_metrics,
+                resources,
             }
             .into_response()
         }
@@ -68,7 +108,7 @@ pub async fn sandbox_detail(
 pub async fn list_sandboxes(State(state): State<SharedState>) -> impl IntoResponse {
     let manager = state.manager.read().await;
     let mut sandboxes: Vec<Sandbox> = manager.sandboxes.values().cloned().collect();
-    sandboxes.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    sandboxes.sort_by_key(|sb| std::cmp::Reverse(sb.created_at));
     Json(sandboxes)
 }
 
@@ -83,32 +123,64 @@ pub async fn get_sandbox(
     }
 }
 
+/// Create a sandbox and record the attempt in the audit log
+async fn create_audited(
+    state: &SharedState,
+    caller: &Caller,
+    req: CreateSandboxRequest,
+) -> Result<Sandbox, CreateError> {
+    let params = serde_json::to_value(&req).unwrap_or_default();
+    let result = sandbox::create_sandbox(state, req).await;
+    let id = result.as_ref().ok().map(|sb| sb.id.as_str());
+    let logged = result.as_ref().map(|_| ()).map_err(|e| e.to_string());
+    state.audit.record(caller, AuditAction::Create, id, params, &logged);
+    result
+}
+
+fn create_error(e: CreateError) -> Response {
+    match e {
+        CreateError::Invalid(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+        CreateError::Failed(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
 pub async fn create_sandbox_api(
     State(state): State<SharedState>,
+    caller: Caller,
     Json(req): Json<CreateSandboxRequest>,
 ) -> Response {
-    match sandbox::create_sandbox(&state, req).await {
+    match create_audited(&state, &caller, req).await {
         Ok(sb) => (StatusCode::CREATED, Json(sb)).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        Err(e) => create_error(e),
     }
 }
 
 /// Handle the HTML form POST (application/x-www-form-urlencoded)
 pub async fn create_sandbox_form(
     State(state): State<SharedState>,
+    caller: Caller,
     axum::extract::Form(req): axum::extract::Form<CreateSandboxRequest>,
 ) -> Response {
-    match sandbox::create_sandbox(&state, req).await {
+    match create_audited(&state, &caller, req).await {
         Ok(sb) => Redirect::to(&format!("/sandboxes/{}", sb.id)).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        Err(e) => create_error(e),
     }
 }
 
 pub async fn stop_sandbox_api(
     State(state): State<SharedState>,
+    caller: Caller,
     Path(id): Path<String>,
 ) -> Response {
-    match sandbox::stop_sandbox(&state, &id).await {
+    let result = sandbox::stop_sandbox(&state, &id).await;
+    state.audit.record(
+        &caller,
+        AuditAction::Stop,
+        Some(&id),
+        serde_json::Value::Null,
+        &result,
+    );
+    match result {
         Ok(()) => StatusCode::NO_CONTENT.into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
     }
@@ -116,47 +188,155 @@ pub async fn stop_sandbox_api(
 
 pub async fn delete_sandbox_api(
     State(state): State<SharedState>,
+    caller: Caller,
     Path(id): Path<String>,
 ) -> Response {
-    match sandbox::delete_sandbox(&state, &id).await {
+    let result = sandbox::delete_sandbox(&state, &id).await;
+    state.audit.record(
+        &caller,
+        AuditAction::Delete,
+        Some(&id),
+        serde_json::Value::Null,
+        &result,
+    );
+    match result {
         Ok(()) => StatusCode::NO_CONTENT.into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
     }
 }
 
+pub async fn send_input_api(
+    State(state): State<SharedState>,
+    caller: Caller,
+    Path(id): Path<String>,
+    Json(req): Json<SendInputRequest>,
+) -> Response {
+    if req.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Nothing to send: set text, keys or enter").into_response();
+    }
+    let session = {
+        let manager = state.manager.read().await;
+        match manager.sandboxes.get(&id) {
+            Some(sb) if sb.is_running() => sb.tmux_session.clone(),
+            Some(_) => return (StatusCode::CONFLICT, "Sandbox is not running").into_response(),
+            None => return (StatusCode::NOT_FOUND, "Sandbox not found").into_response(),
+        }
+    };
+    let Some(session) = session else {
+        return (StatusCode::CONFLICT, "Sandbox has no tmux session").into_response();
+    };
+
+    let result = input::send_input(&session, &req).await;
+    let params = serde_json::to_value(&req).unwrap_or_default();
+    state
+        .audit
+        .record(&caller, AuditAction::Input, Some(&id), params, &result);
+    match result {
+        Ok(resp) => Json(resp).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
 pub async fn get_screenshot(
     State(state): State<SharedState>,
     Path(id): Path<String>,
+    headers: HeaderMap,
 ) -> Response {
-    let screenshots = state.screenshots.read().await;
-    match screenshots.get(&id) {
-        Some(png) => (
+    state.screenshots.touch(&id).await;
+
+    // Nobody was watching before this, so there may be no frame yet
+    if state.screenshots.etag(&id).await.is_none() {
+        let target = {
+            let manager = state.manager.read().await;
+            manager
+                .sandboxes
+                .get(&id)
+                .filter(|sb| sb.is_running())
+                .cloned()
+        };
+        if let Some(sandbox) = target {
+            let s = state.clone();
+            let raw = tokio::task::spawn_blocking(move || s.screenshots.capture(&sandbox))
+            .await
+            .ok()
+            .flatten();
+            if let Some(raw) = raw {
+                state.screenshots.update(&id, raw).await;
+            }
+        }
+    }
+
+    match state.screenshots.get(&id).await {
+        Some(frame) if screenshot::etag_matches(&headers, &frame.etag) => (
+            StatusCode::NOT_MODIFIED,
+            [("etag", frame.etag), ("cache-control", "no-cache".into())],
+        )
+            .into_response(),
+        Some(frame) => (
             StatusCode::OK,
-            [("content-type", "image/png")],
-            png.clone(),
+            [
+                ("content-type", frame.format.content_type().to_string()),
+                ("etag", frame.etag),
+                ("cache-control", "no-cache".into()),
+            ],
+            frame.data,
         )
             .into_response(),
         None => StatusCode::NOT_FOUND.into_response(),
     }
 }
 
+/// Token counts at the top level (as before), plus resource usage while
+/// the sandbox is running
+#[derive(Serialize)]
+pub struct SandboxMetrics {
+    #[serde(flatten)]
+    pub claude: ClaudeMetrics,
+    pub resources: Option<ResourceUsage>,
+}
+
 pub async fn get_sandbox_metrics(
     State(state): State<SharedState>,
     Path(id): Path<String>,
 ) -> Response {
-    let manager = state.manager.read().await;
-    match manager.sandboxes.get(&id) {
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    match sandbox {
+        Some(sb) => {
+            let claude = claude_totals(&state, sb.clone()).await.unwrap_or_default();
+            let resources = sample_resources(&state, vec![sb]).await.remove(&id);
+            Json(SandboxMetrics { claude, resources }).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "Sandbox not found").into_response(),
+    }
+}
+
+pub async fn get_sandbox_sessions(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Response {
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    match sandbox {
         Some(sb) => {
-            let m = metrics::parse_claude_metrics(&sb.project_dir)
+            let s = state.clone();
+            let sessions = tokio::task::spawn_blocking(move || s.sessions.sessions(&sb))
+                .await
                 .unwrap_or_default();
-            Json(m).into_response()
+            Json(sessions).into_response()
         }
         None => (StatusCode::NOT_FOUND, "Sandbox not found").into_response(),
     }
 }
 
-pub async fn get_system_metrics() -> impl IntoResponse {
-    Json(metrics::collect_system_metrics())
+pub async fn get_system_metrics(State(state): State<SharedState>) -> impl IntoResponse {
+    Json(sample_system(&state).await)
+}
+
+/// Sample host metrics off the async runtime
+pub async fn sample_system(state: &SharedState) -> SystemMetrics {
+    let s = state.clone();
+    tokio::task::spawn_blocking(move || s.system.sample())
+        .await
+        .unwrap_or_default()
 }
 
 pub async fn get_logs(
@@ -175,6 +355,13 @@ pub async fn get_logs(
     }
 }
 
+pub async fn get_audit(
+    State(state): State<SharedState>,
+    Query(filter): Query<AuditFilter>,
+) -> impl IntoResponse {
+    Json(state.audit.query(&filter))
+}
+
 // ---------------------------------------------------------------------------
 // System metrics type re-export for templates
 // ---------------------------------------------------------------------------
@@ -192,4 +379,17 @@ impl SystemMetrics {
     pub fn disk_total_gb(&self) -> f64 {
         self.disk_total as f64 / 1_073_741_824.0
     }
+    pub fn net_rate_human(&self) -> String {
+        rate_pair(self.net_rx_rate, self.net_tx_rate)
+    }
+    pub fn disk_rate_human(&self) -> String {
+        rate_pair(self.disk_read_rate, self.disk_write_rate)
+    }
+}
+
+fn rate_pair(a: Option<f64>, b: Option<f64>) -> String {
+    match (a, b) {
+        (Some(a), Some(b)) => format!("{}/s / {}/s", human_bytes(a as u64), human_bytes(b as u64)),
+        _ => "-".to_string(),
+    }
 }
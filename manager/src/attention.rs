@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::session;
+use crate::state::{Sandbox, SharedState};
+use crate::webhooks::WebhookEvent;
+
+/// Questions Claude asks in its permission prompts, e.g. "Do you want to
+/// proceed?" or "Do you want to make this edit to main.rs?"
+const PROMPT_QUESTIONS: &[&str] = &["Do you want to", "Would you like to"];
+
+/// First choice of a permission prompt, shown below the question
+const PROMPT_FIRST_OPTION: &str = "1. Yes";
+
+/// Status line Claude shows while it works on a turn
+const WORKING_MARKER: &str = "esc to interrupt";
+
+/// How much of the end of a session file to read for its last message
+const TAIL_BYTES: u64 = 256 * 1024;
+
+/// Longest detail kept, e.g. of Claude's last reply
+const MAX_DETAIL: usize = 300;
+
+/// Why a sandbox is waiting on someone
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttentionReason {
+    /// Claude asks whether it may run a tool
+    Permission,
+    /// Claude finished its turn and waits for the next prompt
+    Idle,
+}
+
+impl AttentionReason {
+    /// Badge text in the dashboard
+    pub fn label(&self) -> &'static str {
+        match self {
+            AttentionReason::Permission => "needs permission",
+            AttentionReason::Idle => "waiting for input",
+        }
+    }
+
+    /// Start of a notification
+    pub fn description(&self) -> &'static str {
+        match self {
+            AttentionReason::Permission => "Waiting for permission",
+            AttentionReason::Idle => "Waiting for a new prompt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Attention {
+    pub reason: AttentionReason,
+    /// The prompt's question, or the end of Claude's last reply
+    pub detail: String,
+    pub since: DateTime<Utc>,
+    /// Whether notifications about it went out
+    #[serde(default)]
+    pub notified: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AttentionConfig {
+    /// How long Claude must have been done with its turn before a sandbox
+    /// counts as waiting for a prompt; `None` to never count it
+    pub idle_after: Option<chrono::Duration>,
+    /// How long a sandbox must wait before notifications are sent, so
+    /// prompts answered right away don't cause any
+    pub notify_after: chrono::Duration,
+}
+
+impl Default for AttentionConfig {
+    fn default() -> Self {
+        Self {
+            idle_after: Some(chrono::Duration::seconds(120)),
+            notify_after: chrono::Duration::seconds(60),
+        }
+    }
+}
+
+impl AttentionConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(idle) = std::env::var("MANAGER_ATTENTION_IDLE") {
+            match idle.parse::<i64>() {
+                Ok(0) => config.idle_after = None,
+                Ok(secs) if secs > 0 => config.idle_after = Some(chrono::Duration::seconds(secs)),
+                _ => tracing::warn!("MANAGER_ATTENTION_IDLE must be a number of seconds, got {:?}", idle),
+            }
+        }
+        if let Ok(after) = std::env::var("MANAGER_NOTIFY_AFTER") {
+            match after.parse::<i64>() {
+                Ok(secs) if secs >= 0 => config.notify_after = chrono::Duration::seconds(secs),
+                _ => tracing::warn!("MANAGER_NOTIFY_AFTER must be a number of seconds, got {:?}", after),
+            }
+        }
+        config
+    }
+}
+
+/// Update the attention state of every sandbox and notify about the ones
+/// that have been waiting long enough
+pub async fn check(state: &SharedState) {
+    let running: Vec<Sandbox> = {
+        let mgr = state.manager.read().await;
+        mgr.sandboxes
+            .values()
+            .filter(|sb| sb.is_running() && sb.tmux_session.is_some())
+            .cloned()
+            .collect()
+    };
+    let s = state.clone();
+    let detected: HashMap<String, (AttentionReason, String)> = tokio::task::spawn_blocking(move || {
+        running
+            .iter()
+            .filter_map(|sb| Some((sb.id.clone(), detect(&s, sb)?)))
+            .collect()
+    })
+    .await
+    .unwrap_or_default();
+
+    let now = Utc::now();
+    let notify = !state.notifier.is_empty() || state.webhooks.wants(WebhookEvent::Attention);
+    let mut due = Vec::new();
+    {
+        let mut mgr = state.manager.write().await;
+        let mut changed = false;
+        for sb in mgr.sandboxes.values_mut() {
+            let mut next = match (detected.get(&sb.id).cloned(), sb.attention.clone()) {
+                (None, _) => None,
+                (Some((reason, detail)), Some(prev)) if prev.reason == reason => Some(Attention { detail, ..prev }),
+                (Some((reason, detail)), _) => Some(Attention {
+                    reason,
+                    detail,
+                    since: now,
+                    notified: false,
+                }),
+            };
+            if let Some(attention) = next.as_mut() {
+                if notify && !attention.notified && now - attention.since >= state.attention.notify_after {
+                    attention.notified = true;
+                    due.push((sb.clone(), attention.clone()));
+                }
+            }
+            if next != sb.attention {
+                match (&sb.attention, &next) {
+                    (None, Some(a)) => tracing::info!("Sandbox {} needs attention ({})", sb.short_id(), a.reason.label()),
+                    (Some(_), None) => tracing::info!("Sandbox {} no longer waits", sb.short_id()),
+                    _ => {}
+                }
+                sb.attention = next;
+                changed = true;
+            }
+        }
+        if changed {
+            let _ = mgr.save(&state.state_path);
+        }
+    }
+
+    for (sb, attention) in due {
+        state.webhooks.emit(WebhookEvent::Attention, &sb, serde_json::json!({ "attention": attention }));
+        let s = state.clone();
+        tokio::spawn(async move { s.notifier.attention(&sb, &attention).await });
+    }
+}
+
+/// What the sandbox waits for, if anything: a permission prompt on its
+/// pane, or a turn Claude finished a while ago
+fn detect(state: &SharedState, sandbox: &Sandbox) -> Option<(AttentionReason, String)> {
+    let screen = session::capture_screen(sandbox.tmux_session.as_deref()?, false).ok()?;
+    if let Some(question) = permission_prompt(&screen) {
+        return Some((AttentionReason::Permission, question));
+    }
+    if screen.contains(WORKING_MARKER) {
+        return None;
+    }
+    let idle_after = state.attention.idle_after?;
+    let latest = state
+        .sessions
+        .session_files(sandbox)
+        .into_iter()
+        .max_by_key(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())?;
+    let (ended, text) = finished_turn(&latest)?;
+    (Utc::now() - ended >= idle_after).then_some((AttentionReason::Idle, text))
+}
+
+/// The question of a permission prompt on the screen
+fn permission_prompt(screen: &str) -> Option<String> {
+    let lines: Vec<&str> = screen.lines().map(|l| l.trim_matches(|c: char| c == '│' || c.is_whitespace())).collect();
+    let question = lines
+        .iter()
+        .rposition(|l| PROMPT_QUESTIONS.iter().any(|q| l.starts_with(q)))?;
+    lines[question + 1..]
+        .iter()
+        .any(|l| l.trim_start_matches(['❯', '>', ' ']).starts_with(PROMPT_FIRST_OPTION))
+        .then(|| lines[question].to_string())
+}
+
+/// When Claude ended its last turn in a session, and what it said last;
+/// `None` while a turn is under way
+fn finished_turn(path: &Path) -> Option<(DateTime<Utc>, String)> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    let start = len.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).ok()?;
+
+    // The last user or assistant message decides; other entries such as
+    // summaries don't take a turn
+    let last = buf
+        .split(|&b| b == b'\n')
+        .rev()
+        .filter_map(|line| serde_json::from_slice::<serde_json::Value>(line).ok())
+        .find(|v| {
+            matches!(v.get("type").and_then(|t| t.as_str()), Some("user" | "assistant"))
+                && v.get("isMeta").and_then(|m| m.as_bool()) != Some(true)
+        })?;
+    if last.get("type").and_then(|t| t.as_str()) != Some("assistant") {
+        return None;
+    }
+    let blocks = last.pointer("/message/content")?.as_array()?;
+    if blocks.iter().any(|b| b.get("type").and_then(|t| t.as_str()) == Some("tool_use")) {
+        return None;
+    }
+    let ended = last.get("timestamp")?.as_str()?.parse().ok()?;
+    let text = blocks
+        .iter()
+        .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    // Thinking alone comes before the reply of a turn still under way
+    if text.trim().is_empty() {
+        return None;
+    }
+    Some((ended, tail_of(text.trim())))
+}
+
+/// The end of `text`, where a reply usually asks its question
+fn tail_of(text: &str) -> String {
+    let chars = text.chars().count();
+    if chars <= MAX_DETAIL {
+        return text.to_string();
+    }
+    let cut: String = text.chars().skip(chars - MAX_DETAIL).collect();
+    format!("…{}", cut)
+}
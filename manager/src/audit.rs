@@ -0,0 +1,161 @@
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Header carrying the name of the user on whose behalf a request is made.
+/// `claude-remote` sets it to the local `$USER`; it is not authenticated.
+const ACTOR_HEADER: &str = "x-remote-user";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Stop,
+    Delete,
+    Attach,
+    Input,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub action: AuditAction,
+    pub sandbox_id: Option<String>,
+    pub actor: String,
+    pub source: String,
+    pub params: serde_json::Value,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+/// Who issued a request: the self-reported actor and the peer address
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub actor: String,
+    pub source: String,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Caller {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let actor = parts
+            .headers
+            .get(ACTOR_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .unwrap_or("anonymous")
+            .to_string();
+        let source = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ci| ci.0.to_string())
+            .unwrap_or_else(|| "unknown".into());
+        Ok(Caller { actor, source })
+    }
+}
+
+/// Append-only audit trail, one JSON object per line
+pub struct AuditLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Record the outcome of a lifecycle action. Failures to write are logged,
+    /// never propagated — the action itself has already happened.
+    pub fn record<T>(
+        &self,
+        caller: &Caller,
+        action: AuditAction,
+        sandbox_id: Option<&str>,
+        params: serde_json::Value,
+        result: &Result<T, String>,
+    ) {
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            action,
+            sandbox_id: sandbox_id.map(str::to_string),
+            actor: caller.actor.clone(),
+            source: caller.source.clone(),
+            params,
+            ok: result.is_ok(),
+            error: result.as_ref().err().cloned(),
+        };
+        if let Err(e) = self.append(&entry) {
+            tracing::warn!("Failed to write audit entry: {}", e);
+        }
+    }
+
+    fn append(&self, entry: &AuditEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())
+    }
+
+    /// Read back entries matching `filter`, oldest first
+    pub fn query(&self, filter: &AuditFilter) -> Vec<AuditEntry> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(_) => return Vec::new(),
+        };
+        contents
+            .lines()
+            .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+            .filter(|e| filter.matches(e))
+            .collect()
+    }
+}
+
+/// Query parameters for `GET /api/audit`
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    /// Full sandbox id or an id prefix
+    pub sandbox: Option<String>,
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        if let Some(ref prefix) = self.sandbox {
+            match entry.sandbox_id {
+                Some(ref id) if id.starts_with(prefix.as_str()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(ref actor) = self.actor {
+            if &entry.actor != actor {
+                return false;
+            }
+        }
+        if self.since.is_some_and(|since| entry.timestamp < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| entry.timestamp > until) {
+            return false;
+        }
+        true
+    }
+}
@@ -0,0 +1,585 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::cost::PriceTable;
+use crate::metrics::{self, ClaudeMetrics};
+use crate::state::Sandbox;
+
+/// Longest encoded project directory name; longer ones are cut to this and
+/// get a hash of the path appended
+const MAX_ENCODED_LEN: usize = 200;
+
+/// Read at most this much of a file at a time, so the first parse of a huge
+/// session doesn't hold a single buffer of its whole size
+const CHUNK: usize = 8 * 1024 * 1024;
+
+/// One Claude session of a sandbox, counting only what happened after the
+/// sandbox was created
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    /// The session file's name without `.jsonl`
+    pub id: String,
+    /// Timestamps of the first and last counted entries
+    pub started: Option<DateTime<Utc>>,
+    pub ended: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub metrics: ClaudeMetrics,
+}
+
+/// Totals of Claude's session JSONL files, kept per file and cutoff time
+/// along with how far each file has been read. Sessions only ever grow by
+/// appended lines, so a call parses just what was written since the
+/// previous one.
+pub struct SessionCache {
+    files: Mutex<HashMap<FileKey, Arc<Mutex<FileState>>>>,
+    prices: PriceTable,
+    /// Claude data directory of sandboxes that don't record their own
+    claude_dir: PathBuf,
+}
+
+/// A session file as seen by sandboxes created at a given time
+type FileKey = (PathBuf, DateTime<Utc>);
+
+struct FileState {
+    tail: Tail,
+    session: SessionState,
+}
+
+/// What a session file's lines since a cutoff add up to
+struct SessionState {
+    /// Entries timestamped earlier are skipped, e.g. the history a resumed
+    /// session carries over from before the sandbox existed
+    since: DateTime<Utc>,
+    entries: u64,
+    started: Option<DateTime<Utc>>,
+    ended: Option<DateTime<Utc>>,
+    metrics: ClaudeMetrics,
+    /// Messages whose usage was counted, by `metrics::message_key`
+    messages: HashSet<String>,
+}
+
+/// How far a file that only grows by appended lines, such as a session
+/// JSONL file, has been read
+#[derive(Debug, Default)]
+pub struct Tail {
+    /// Identity of the file read so far; a new inode means it was replaced
+    dev: u64,
+    ino: u64,
+    /// Bytes consumed, always just past a newline
+    offset: u64,
+}
+
+impl Tail {
+    /// Start reading the file `meta` describes at `offset`, which must be
+    /// 0, its length or just past a newline for whole lines to come out
+    pub fn at(meta: &std::fs::Metadata, offset: u64) -> Self {
+        Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            offset,
+        }
+    }
+
+    /// Open `path` where the last read stopped. `true` with it means the
+    /// file was replaced or truncated and is read from the start again.
+    pub fn open(&mut self, path: &Path) -> std::io::Result<(File, bool)> {
+        let mut file = File::open(path)?;
+        let meta = file.metadata()?;
+        let restarted = (meta.dev(), meta.ino()) != (self.dev, self.ino) || meta.len() < self.offset;
+        if restarted {
+            *self = Tail::at(&meta, 0);
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok((file, restarted))
+    }
+
+    /// Hand each complete line appended since the last read to `line`. A
+    /// partial last line waits for its newline. Stops after about `max`
+    /// bytes, but always gets past at least one line, however long.
+    pub fn read_lines(&mut self, file: File, max: u64, mut line: impl FnMut(&[u8])) -> std::io::Result<()> {
+        let mut remaining = file.metadata()?.len().saturating_sub(self.offset);
+        let mut reader = file.take(remaining);
+        let mut buf = Vec::new();
+        let mut read = 0;
+        while remaining > 0 {
+            // Only as much as is left, so a few appended lines don't cost a
+            // whole chunk of zeroed memory
+            let start = buf.len();
+            buf.resize(start + CHUNK.min(remaining as usize), 0);
+            let n = reader.read(&mut buf[start..])?;
+            buf.truncate(start + n);
+            if n == 0 {
+                break;
+            }
+            remaining -= n as u64;
+            read += n as u64;
+            let Some(end) = buf.iter().rposition(|&b| b == b'\n') else {
+                continue;
+            };
+            for l in buf[..end].split(|&b| b == b'\n') {
+                line(l);
+            }
+            self.offset += end as u64 + 1;
+            buf.drain(..=end);
+            if read >= max {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SessionCache {
+    pub fn new(prices: PriceTable, claude_dir: PathBuf) -> Self {
+        Self {
+            files: Mutex::default(),
+            prices,
+            claude_dir,
+        }
+    }
+
+    pub fn prices(&self) -> &PriceTable {
+        &self.prices
+    }
+
+    pub fn default_claude_dir(&self) -> &Path {
+        &self.claude_dir
+    }
+
+    /// The Claude data directory a sandbox's Claude writes to
+    pub fn claude_dir(&self, sandbox: &Sandbox) -> PathBuf {
+        sandbox
+            .claude_dir
+            .as_ref()
+            .map_or_else(|| self.claude_dir.clone(), PathBuf::from)
+    }
+
+    /// The sandbox's sessions with activity since it was created, oldest
+    /// first
+    pub fn sessions(&self, sandbox: &Sandbox) -> Vec<SessionSummary> {
+        let mut sessions: Vec<SessionSummary> = self
+            .session_files(sandbox)
+            .into_iter()
+            .filter_map(|path| self.summary(&path, sandbox.created_at))
+            .collect();
+        sessions.sort_by_key(|s| s.started);
+        sessions
+    }
+
+    /// The sandbox's session files modified since it was created
+    pub fn session_files(&self, sandbox: &Sandbox) -> Vec<PathBuf> {
+        find_project_dir(&self.claude_dir(sandbox), &sandbox.project_dir)
+            .map(|dir| session_files(&dir, sandbox.created_at))
+            .unwrap_or_default()
+    }
+
+    /// Path of one of the sandbox's session files, by session ID
+    pub fn session_file(&self, sandbox: &Sandbox, id: &str) -> Option<PathBuf> {
+        let dir = find_project_dir(&self.claude_dir(sandbox), &sandbox.project_dir)?;
+        let path = dir.join(format!("{}.jsonl", id));
+        path.is_file().then_some(path)
+    }
+
+    /// Totals over the sandbox's sessions, if it has any
+    pub fn totals(&self, sandbox: &Sandbox) -> Option<ClaudeMetrics> {
+        let sessions = self.sessions(sandbox);
+        if sessions.is_empty() {
+            return None;
+        }
+        let mut totals = ClaudeMetrics::default();
+        for session in &sessions {
+            totals.merge(&session.metrics);
+        }
+        self.prices.apply(&mut totals);
+        Some(totals)
+    }
+
+    /// Drop what was cached for a sandbox's sessions
+    pub fn forget(&self, sandbox: &Sandbox) {
+        if let Some(dir) = find_project_dir(&self.claude_dir(sandbox), &sandbox.project_dir) {
+            self.files
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .retain(|(path, since), _| *since != sandbox.created_at || !path.starts_with(&dir));
+        }
+    }
+
+    /// One session file, reading only what was appended since the last
+    /// call; `None` if it has nothing since `since`
+    fn summary(&self, path: &Path, since: DateTime<Utc>) -> Option<SessionSummary> {
+        let key = (path.to_path_buf(), since);
+        let entry = self
+            .files
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(FileState::new(since))))
+            .clone();
+        // Concurrent callers for the same file wait for one read rather than
+        // each parsing the new lines
+        let mut state = entry.lock().unwrap_or_else(|e| {
+            // A read that panicked may have counted lines without moving the
+            // offset past them, so start the file over
+            entry.clear_poison();
+            let mut state = e.into_inner();
+            *state = FileState::new(since);
+            state
+        });
+        if state.update(path).is_err() {
+            drop(state);
+            self.files.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+            return None;
+        }
+        let session = &state.session;
+        if session.entries == 0 {
+            return None;
+        }
+        let mut metrics = session.metrics.clone();
+        self.prices.apply(&mut metrics);
+        Some(SessionSummary {
+            id: path.file_stem()?.to_string_lossy().into_owned(),
+            started: session.started,
+            ended: session.ended,
+            metrics,
+        })
+    }
+}
+
+impl FileState {
+    fn new(since: DateTime<Utc>) -> Self {
+        Self {
+            tail: Tail::default(),
+            session: SessionState {
+                since,
+                entries: 0,
+                started: None,
+                ended: None,
+                metrics: ClaudeMetrics::default(),
+                messages: HashSet::new(),
+            },
+        }
+    }
+
+    fn update(&mut self, path: &Path) -> std::io::Result<()> {
+        let (file, restarted) = self.tail.open(path)?;
+        if restarted {
+            self.session = FileState::new(self.session.since).session;
+        }
+        let session = &mut self.session;
+        self.tail.read_lines(file, u64::MAX, |line| {
+            if let Ok(v) = serde_json::from_slice::<serde_json::Value>(line) {
+                session.add_entry(&v);
+            }
+        })
+    }
+}
+
+impl SessionState {
+    fn add_entry(&mut self, v: &serde_json::Value) {
+        let time = v
+            .get("timestamp")
+            .and_then(|t| t.as_str())
+            .and_then(|t| t.parse::<DateTime<Utc>>().ok());
+        if let Some(time) = time {
+            if time < self.since {
+                return;
+            }
+            self.started.get_or_insert(time);
+            self.ended = Some(time);
+        }
+        self.entries += 1;
+        let first = metrics::message_key(v).is_none_or(|key| self.messages.insert(key));
+        self.metrics.add_entry(v, first);
+    }
+}
+
+/// Claude data directory of sandboxes created without one: `MANAGER_CLAUDE_DIR`,
+/// or `~/.claude` of the manager's user, which every backend binds into the
+/// sandbox
+pub fn default_claude_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("MANAGER_CLAUDE_DIR").filter(|v| !v.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    home_dir().join(".claude")
+}
+
+/// `$HOME`, or the home directory in the user database when a service
+/// manager didn't set it
+fn home_dir() -> PathBuf {
+    if let Some(home) = std::env::var_os("HOME").filter(|v| !v.is_empty()) {
+        return PathBuf::from(home);
+    }
+    // SAFETY: getpwuid returns null or a pointer to static storage, read
+    // right away at startup before other threads could call it
+    unsafe {
+        let pw = libc::getpwuid(libc::getuid());
+        if !pw.is_null() && !(*pw).pw_dir.is_null() {
+            let dir = std::ffi::CStr::from_ptr((*pw).pw_dir);
+            return PathBuf::from(std::ffi::OsStr::from_bytes(dir.to_bytes()));
+        }
+    }
+    PathBuf::from("/")
+}
+
+/// Name Claude gives the directory of a project's sessions under
+/// `<claude dir>/projects/`: every character but ASCII letters and digits
+/// becomes `-`, once per UTF-16 code unit as in JavaScript, so
+/// `/home/me/my_app.v2` is `-home-me-my-app-v2`. Names longer than
+/// `MAX_ENCODED_LEN` are cut and get a hash suffix, which only Claude knows.
+pub fn encode_project_dir(project_dir: &str) -> String {
+    let mut encoded = String::with_capacity(project_dir.len());
+    for c in project_dir.chars() {
+        if c.is_ascii_alphanumeric() {
+            encoded.push(c);
+        } else {
+            encoded.extend(std::iter::repeat_n('-', c.len_utf16()));
+        }
+    }
+    encoded
+}
+
+/// Where Claude keeps the sessions of `project_dir`, if it has any
+pub fn find_project_dir(claude_dir: &Path, project_dir: &str) -> Option<PathBuf> {
+    let projects = claude_dir.join("projects");
+    let encoded = encode_project_dir(project_dir);
+    if encoded.len() <= MAX_ENCODED_LEN {
+        let dir = projects.join(encoded);
+        return dir.is_dir().then_some(dir);
+    }
+    // Cut to the limit, then `-<hash>`: match on the part we can compute
+    let prefix = format!("{}-", &encoded[..MAX_ENCODED_LEN]);
+    std::fs::read_dir(&projects)
+        .ok()?
+        .flatten()
+        .find(|entry| {
+            entry.file_name().to_str().is_some_and(|name| {
+                name.strip_prefix(&prefix)
+                    .is_some_and(|hash| !hash.is_empty() && !hash.contains('-'))
+            })
+        })
+        .map(|entry| entry.path())
+        .filter(|dir| dir.is_dir())
+}
+
+/// Session files in `dir` modified since `since`; older ones can't have
+/// anything newer
+fn session_files(dir: &Path, since: DateTime<Utc>) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "jsonl"))
+        .filter(|path| {
+            std::fs::metadata(path)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| DateTime::<Utc>::from(modified) >= since)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Fresh empty directory under the system temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("claude-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    fn assistant(time: &str, input_tokens: u64) -> String {
+        format!(
+            "{{\"type\":\"assistant\",\"timestamp\":\"{}\",\"message\":{{\"model\":\"claude-test\",\"usage\":{{\"input_tokens\":{}}}}}}}\n",
+            time, input_tokens
+        )
+    }
+
+    fn since() -> DateTime<Utc> {
+        "2026-01-01T00:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn encodes_every_non_alphanumeric_character() {
+        assert_eq!(encode_project_dir("/home/me/project"), "-home-me-project");
+        assert_eq!(encode_project_dir("/home/me/my_app.v2"), "-home-me-my-app-v2");
+        assert_eq!(encode_project_dir("/srv/a b/c~d"), "-srv-a-b-c-d");
+        assert_eq!(encode_project_dir("/home/me/.config"), "-home-me--config");
+    }
+
+    #[test]
+    fn encodes_non_ascii_per_utf16_unit() {
+        assert_eq!(encode_project_dir("/tmp/café"), "-tmp-caf-");
+        // Outside the BMP: a surrogate pair, so two dashes
+        assert_eq!(encode_project_dir("/tmp/🦀"), "-tmp---");
+    }
+
+    #[test]
+    fn finds_project_dir() {
+        let claude = temp_dir("find");
+        assert_eq!(find_project_dir(&claude, "/work/my_app"), None);
+        let dir = claude.join("projects").join("-work-my-app");
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(find_project_dir(&claude, "/work/my_app"), Some(dir));
+        std::fs::remove_dir_all(&claude).unwrap();
+    }
+
+    #[test]
+    fn finds_long_project_dir_by_prefix() {
+        let claude = temp_dir("long");
+        let project = format!("/work/{}", "x".repeat(250));
+        let encoded = encode_project_dir(&project);
+        let prefix = &encoded[..MAX_ENCODED_LEN];
+        // Same prefix but a longer path, not ours
+        std::fs::create_dir_all(claude.join("projects").join(format!("{}x-y-1a2b", prefix))).unwrap();
+        assert_eq!(find_project_dir(&claude, &project), None);
+        let dir = claude.join("projects").join(format!("{}-1a2b3c", prefix));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(find_project_dir(&claude, &project), Some(dir));
+        std::fs::remove_dir_all(&claude).unwrap();
+    }
+
+    #[test]
+    fn reads_appended_lines_only_once_complete() {
+        let dir = temp_dir("append");
+        let path = dir.join("session.jsonl");
+        let cache = SessionCache::new(PriceTable::default(), dir.clone());
+
+        append(&path, &assistant("2026-01-01T00:00:01Z", 10));
+        assert_eq!(cache.summary(&path, since()).unwrap().metrics.input_tokens, 10);
+
+        // Half a line is left for the next read
+        let line = assistant("2026-01-01T00:00:02Z", 5);
+        let (head, tail) = line.split_at(20);
+        append(&path, head);
+        assert_eq!(cache.summary(&path, since()).unwrap().metrics.input_tokens, 10);
+        append(&path, tail);
+        let summary = cache.summary(&path, since()).unwrap();
+        assert_eq!(summary.metrics.input_tokens, 15);
+        assert_eq!(summary.id, "session");
+        assert_eq!(summary.ended, Some("2026-01-01T00:00:02Z".parse().unwrap()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn starts_over_after_truncation_or_replacement() {
+        let dir = temp_dir("truncate");
+        let path = dir.join("session.jsonl");
+        let cache = SessionCache::new(PriceTable::default(), dir.clone());
+
+        append(&path, &assistant("2026-01-01T00:00:01Z", 10));
+        append(&path, &assistant("2026-01-01T00:00:02Z", 10));
+        assert_eq!(cache.summary(&path, since()).unwrap().metrics.input_tokens, 20);
+
+        std::fs::write(&path, assistant("2026-01-01T00:00:03Z", 7)).unwrap();
+        assert_eq!(cache.summary(&path, since()).unwrap().metrics.input_tokens, 7);
+
+        // Replaced by a file at least as long: a new inode
+        let other = dir.join("other.jsonl");
+        std::fs::write(&other, assistant("2026-01-01T00:00:04Z", 3).repeat(3)).unwrap();
+        std::fs::rename(&other, &path).unwrap();
+        assert_eq!(cache.summary(&path, since()).unwrap().metrics.input_tokens, 9);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(cache.summary(&path, since()).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_entries_before_cutoff() {
+        let dir = temp_dir("cutoff");
+        let path = dir.join("resumed.jsonl");
+        let cache = SessionCache::new(PriceTable::default(), dir.clone());
+
+        append(&path, &assistant("2025-12-31T23:59:59Z", 100));
+        assert!(cache.summary(&path, since()).is_none());
+        append(&path, &assistant("2026-01-01T00:00:01Z", 1));
+        let summary = cache.summary(&path, since()).unwrap();
+        assert_eq!(summary.metrics.input_tokens, 1);
+        assert_eq!(summary.started, Some("2026-01-01T00:00:01Z".parse().unwrap()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn counts_usage_once_per_message() {
+        let dir = temp_dir("blocks");
+        let path = dir.join("session.jsonl");
+        let cache = SessionCache::new(PriceTable::default(), dir.clone());
+
+        // One response written as a line per content block, each repeating
+        // the usage, then a second response
+        let block = |id: &str, request: &str, content: &str| {
+            format!(
+                "{{\"type\":\"assistant\",\"timestamp\":\"2026-01-01T00:00:01Z\",\"requestId\":\"{}\",\"message\":{{\"id\":\"{}\",\"model\":\"claude-test\",\"content\":[{}],\"usage\":{{\"input_tokens\":10,\"output_tokens\":20}}}}}}\n",
+                request, id, content
+            )
+        };
+        append(&path, &block("msg_1", "req_1", "{\"type\":\"thinking\",\"thinking\":\"hm\"}"));
+        append(&path, &block("msg_1", "req_1", "{\"type\":\"text\",\"text\":\"Running it\"}"));
+        append(
+            &path,
+            &block(
+                "msg_1",
+                "req_1",
+                "{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"Bash\",\"input\":{\"command\":\"ls\"}}",
+            ),
+        );
+        let metrics = cache.summary(&path, since()).unwrap().metrics;
+        assert_eq!(metrics.input_tokens, 10);
+        assert_eq!(metrics.output_tokens, 20);
+        assert_eq!(metrics.models["claude-test"].output_tokens, 20);
+        assert_eq!(metrics.tool_use_count, 1);
+
+        append(&path, &block("msg_2", "req_2", "{\"type\":\"text\",\"text\":\"Done\"}"));
+        let metrics = cache.summary(&path, since()).unwrap().metrics;
+        assert_eq!(metrics.input_tokens, 20);
+        assert_eq!(metrics.models["claude-test"].output_tokens, 40);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tail_gets_past_lines_longer_than_max() {
+        let dir = temp_dir("tail");
+        let path = dir.join("session.jsonl");
+        // Longer than a chunk and than the read limit
+        let long = "x".repeat(CHUNK + 1024 * 1024);
+        append(&path, &format!("{}\nshort\nlast\n", long));
+
+        let mut tail = Tail::default();
+        let read = |tail: &mut Tail| {
+            let (file, _) = tail.open(&path).unwrap();
+            let mut lines = Vec::new();
+            tail.read_lines(file, 1024 * 1024, |l| lines.push(String::from_utf8_lossy(l).into_owned())).unwrap();
+            lines
+        };
+        // The limit is reached within the long line; it's read to its end,
+        // along with the complete lines that came in the same chunk
+        assert_eq!(read(&mut tail), vec![long, "short".to_string(), "last".to_string()]);
+        assert!(read(&mut tail).is_empty());
+        append(&path, "partial");
+        assert!(read(&mut tail).is_empty());
+        append(&path, " line\n");
+        assert_eq!(read(&mut tail), vec!["partial line".to_string()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
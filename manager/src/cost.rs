@@ -0,0 +1,415 @@
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+use crate::audit::{AuditAction, Caller};
+use crate::metrics::{ClaudeMetrics, TokenUsage};
+use crate::sandbox;
+use crate::state::{Sandbox, SharedState};
+use crate::webhooks::WebhookEvent;
+
+/// Prices of one model in USD per million tokens
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelPrices {
+    pub input: f64,
+    pub output: f64,
+    /// Writing to the prompt cache; 1.25 times `input` if not given
+    #[serde(default)]
+    pub cache_creation: Option<f64>,
+    /// Reading from the prompt cache; a tenth of `input` if not given
+    #[serde(default)]
+    pub cache_read: Option<f64>,
+}
+
+impl ModelPrices {
+    fn cost(&self, usage: &TokenUsage) -> f64 {
+        let per_token = |price: f64, tokens: u64| price * tokens as f64 / 1_000_000.0;
+        per_token(self.input, usage.input_tokens)
+            + per_token(self.output, usage.output_tokens)
+            + per_token(
+                self.cache_creation.unwrap_or(self.input * 1.25),
+                usage.cache_creation_tokens,
+            )
+            + per_token(self.cache_read.unwrap_or(self.input / 10.0), usage.cache_read_tokens)
+    }
+}
+
+/// Model prices read from the JSON file in `MANAGER_PRICES`, keyed by model
+/// name or a prefix of it, e.g. `claude-sonnet-4` for every Sonnet 4 release
+#[derive(Debug, Clone, Default)]
+pub struct PriceTable {
+    /// Longest name first, so the most specific entry wins
+    models: Vec<(String, ModelPrices)>,
+}
+
+impl PriceTable {
+    pub fn from_env() -> Self {
+        let Some(path) = std::env::var("MANAGER_PRICES").ok().filter(|v| !v.is_empty()) else {
+            return Self::default();
+        };
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| {
+                serde_json::from_str::<HashMap<String, ModelPrices>>(&s).map_err(|e| e.to_string())
+            });
+        match parsed {
+            Ok(models) => {
+                tracing::info!("Loaded prices of {} models from {}", models.len(), path);
+                Self::new(models)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to read price table {}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    fn new(models: HashMap<String, ModelPrices>) -> Self {
+        let mut models: Vec<_> = models.into_iter().collect();
+        models.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        Self { models }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+
+    fn get(&self, model: &str) -> Option<&ModelPrices> {
+        self.models
+            .iter()
+            .find(|(name, _)| model.starts_with(name.as_str()))
+            .map(|(_, prices)| prices)
+    }
+
+    /// Fill in `cost_usd` and `unpriced_models` from the per-model usage
+    pub fn apply(&self, metrics: &mut ClaudeMetrics) {
+        metrics.cost_usd = None;
+        metrics.unpriced_models.clear();
+        if self.is_empty() {
+            return;
+        }
+        let mut cost = 0.0;
+        let mut priced = false;
+        for (model, usage) in &metrics.models {
+            match self.get(model) {
+                Some(prices) => {
+                    cost += prices.cost(usage);
+                    priced = true;
+                }
+                // Claude's own `<synthetic>` messages use no tokens
+                None if usage.total() > 0 => metrics.unpriced_models.push(model.clone()),
+                None => {}
+            }
+        }
+        if priced || metrics.unpriced_models.is_empty() {
+            metrics.cost_usd = Some(cost);
+        }
+    }
+}
+
+/// `$1.23`, or `<$0.01` for a cost that rounds to nothing
+pub fn usd(amount: f64) -> String {
+    if amount > 0.0 && amount < 0.005 {
+        "<$0.01".to_string()
+    } else {
+        format!("${:.2}", amount)
+    }
+}
+
+/// Accept a number, a numeric string, or an empty string (an empty form
+/// field) for an optional amount of dollars
+pub fn optional_amount<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Number(f64),
+        Text(String),
+    }
+    let amount = match Option::<Raw>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Raw::Number(n)) => n,
+        Some(Raw::Text(s)) if s.trim().is_empty() => return Ok(None),
+        Some(Raw::Text(s)) => s
+            .trim()
+            .trim_start_matches('$')
+            .parse()
+            .map_err(|_| serde::de::Error::custom("expected an amount of dollars"))?,
+    };
+    if amount.is_finite() && amount > 0.0 {
+        Ok(Some(amount))
+    } else {
+        Err(serde::de::Error::custom("budget must be more than zero"))
+    }
+}
+
+/// Budget every sandbox gets unless it asks for its own, from
+/// `MANAGER_SANDBOX_BUDGET`
+pub fn default_budget_from_env(prices: &PriceTable) -> Option<f64> {
+    let value = std::env::var("MANAGER_SANDBOX_BUDGET").ok().filter(|v| !v.is_empty())?;
+    let budget = match value.trim_start_matches('$').parse::<f64>() {
+        Ok(b) if b.is_finite() && b > 0.0 => b,
+        _ => {
+            tracing::warn!("MANAGER_SANDBOX_BUDGET must be an amount of dollars, got {:?}", value);
+            return None;
+        }
+    };
+    if prices.is_empty() {
+        tracing::warn!("MANAGER_SANDBOX_BUDGET is ignored without a price table (MANAGER_PRICES)");
+        return None;
+    }
+    Some(budget)
+}
+
+/// Stop running sandboxes whose estimated cost reached their budget.
+/// `warned` holds the unpriced models last logged for each sandbox, so a
+/// budget that can't see all of the spending is warned about once rather
+/// than on every check
+pub async fn enforce_budgets(state: &SharedState, warned: &mut HashMap<String, Vec<String>>) {
+    let limited: Vec<Sandbox> = {
+        let mgr = state.manager.read().await;
+        mgr.sandboxes
+            .values()
+            .filter(|sb| sb.is_running() && sb.budget_usd.is_some())
+            .cloned()
+            .collect()
+    };
+    warned.retain(|id, _| limited.iter().any(|sb| &sb.id == id));
+    if limited.is_empty() {
+        return;
+    }
+    let s = state.clone();
+    let totals = tokio::task::spawn_blocking(move || {
+        limited
+            .into_iter()
+            .filter_map(|sb| {
+                let metrics = s.sessions.totals(&sb)?;
+                Some((sb, metrics.cost_usd, metrics.unpriced_models))
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+
+    let mut costs = Vec::new();
+    for (sb, cost, unpriced) in totals {
+        if unpriced.is_empty() {
+            warned.remove(&sb.id);
+        } else if warned.get(&sb.id) != Some(&unpriced) {
+            tracing::warn!(
+                "Sandbox {} has a budget but the price table has no price for {}; its estimate leaves them out",
+                sb.short_id(),
+                unpriced.join(", ")
+            );
+            warned.insert(sb.id.clone(), unpriced);
+        }
+        if let Some(cost) = cost {
+            costs.push((sb, cost));
+        }
+    }
+
+    for (sb, cost) in costs {
+        let Some(budget) = sb.budget_usd else {
+            continue;
+        };
+        if cost < budget {
+            continue;
+        }
+        tracing::warn!(
+            "Sandbox {} spent an estimated {} of its {} budget, stopping it",
+            sb.short_id(),
+            usd(cost),
+            usd(budget)
+        );
+        state.webhooks.emit(
+            WebhookEvent::BudgetExceeded,
+            &sb,
+            serde_json::json!({ "cost_usd": cost, "budget_usd": budget }),
+        );
+        let result = sandbox::stop_sandbox(state, &sb.id).await;
+        let caller = Caller {
+            actor: "manager".to_string(),
+            source: "budget".to_string(),
+        };
+        let params = serde_json::json!({ "cost_usd": cost, "budget_usd": budget });
+        state.audit.record(&caller, AuditAction::Stop, Some(&sb.id), params, &result);
+    }
+}
+
+#[derive(Serialize)]
+pub struct CostSummary {
+    /// Sum over all sandboxes; `null` without a price table
+    pub total_usd: Option<f64>,
+    pub sandboxes: Vec<SandboxCost>,
+}
+
+#[derive(Serialize)]
+pub struct SandboxCost {
+    pub id: String,
+    pub name: String,
+    pub cost_usd: Option<f64>,
+    pub budget_usd: Option<f64>,
+}
+
+/// Formatted costs for the sandbox list
+#[derive(Default)]
+pub struct CostLabels {
+    pub sandboxes: HashMap<String, String>,
+    /// Over all sandboxes, if there is a price table
+    pub total: Option<String>,
+}
+
+impl CostLabels {
+    pub async fn new(state: &SharedState, sandboxes: Vec<Sandbox>) -> Self {
+        if state.sessions.prices().is_empty() {
+            return Self::default();
+        }
+        let costs = sandbox_costs(state, sandboxes).await;
+        Self {
+            total: Some(usd(costs.values().sum())),
+            sandboxes: costs.into_iter().map(|(id, cost)| (id, usd(cost))).collect(),
+        }
+    }
+}
+
+/// Estimated cost of each sandbox, sampled off the async runtime
+pub async fn sandbox_costs(state: &SharedState, sandboxes: Vec<Sandbox>) -> HashMap<String, f64> {
+    let s = state.clone();
+    tokio::task::spawn_blocking(move || {
+        sandboxes
+            .iter()
+            .filter_map(|sb| {
+                let cost = s.sessions.totals(sb)?.cost_usd?;
+                Some((sb.id.clone(), cost))
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+pub async fn get_cost(State(state): State<SharedState>) -> impl IntoResponse {
+    let mut sandboxes: Vec<Sandbox> = {
+        let manager = state.manager.read().await;
+        manager.sandboxes.values().cloned().collect()
+    };
+    sandboxes.sort_by_key(|sb| std::cmp::Reverse(sb.created_at));
+    let costs = sandbox_costs(&state, sandboxes.clone()).await;
+    let priced = !state.sessions.prices().is_empty();
+    Json(CostSummary {
+        total_usd: priced.then(|| costs.values().sum()),
+        sandboxes: sandboxes
+            .into_iter()
+            .map(|sb| SandboxCost {
+                cost_usd: costs.get(&sb.id).copied(),
+                id: sb.id,
+                name: sb.name,
+                budget_usd: sb.budget_usd,
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(json: serde_json::Value) -> PriceTable {
+        PriceTable::new(serde_json::from_value(json).unwrap())
+    }
+
+    fn usage(input: u64, output: u64, cache_creation: u64, cache_read: u64) -> TokenUsage {
+        TokenUsage {
+            input_tokens: input,
+            output_tokens: output,
+            cache_creation_tokens: cache_creation,
+            cache_read_tokens: cache_read,
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let prices = table(serde_json::json!({
+            "claude": { "input": 1.0, "output": 1.0 },
+            "claude-opus-4": { "input": 15.0, "output": 75.0 },
+            "claude-opus-4-1": { "input": 20.0, "output": 80.0 },
+        }));
+        assert_eq!(prices.get("claude-opus-4-1-20250805").unwrap().input, 20.0);
+        assert_eq!(prices.get("claude-opus-4-20250514").unwrap().input, 15.0);
+        assert_eq!(prices.get("claude-sonnet-4-20250514").unwrap().input, 1.0);
+        assert!(prices.get("gpt-4").is_none());
+    }
+
+    #[test]
+    fn cache_prices_default_from_input() {
+        let prices: ModelPrices =
+            serde_json::from_value(serde_json::json!({ "input": 3.0, "output": 15.0 })).unwrap();
+        // A million tokens of each: 3 + 15 + 3.75 written to the cache + 0.3 read from it
+        assert!(close(prices.cost(&usage(1_000_000, 1_000_000, 1_000_000, 1_000_000)), 22.05));
+
+        let prices: ModelPrices = serde_json::from_value(serde_json::json!({
+            "input": 3.0, "output": 15.0, "cache_creation": 6.0, "cache_read": 1.0
+        }))
+        .unwrap();
+        assert!(close(prices.cost(&usage(0, 0, 1_000_000, 1_000_000)), 7.0));
+    }
+
+    #[test]
+    fn unpriced_models_are_listed_and_left_out() {
+        let prices = table(serde_json::json!({ "claude-sonnet-4": { "input": 3.0, "output": 15.0 } }));
+        let mut metrics = ClaudeMetrics::default();
+        metrics.models.insert("claude-sonnet-4-5".to_string(), usage(1_000_000, 0, 0, 0));
+        metrics.models.insert("claude-mystery".to_string(), usage(1_000_000, 0, 0, 0));
+        metrics.models.insert("<synthetic>".to_string(), usage(0, 0, 0, 0));
+        prices.apply(&mut metrics);
+        assert!(close(metrics.cost_usd.unwrap(), 3.0));
+        assert_eq!(metrics.unpriced_models, vec!["claude-mystery".to_string()]);
+
+        metrics.models.remove("claude-sonnet-4-5");
+        prices.apply(&mut metrics);
+        assert_eq!(metrics.cost_usd, None);
+
+        PriceTable::default().apply(&mut metrics);
+        assert_eq!(metrics.cost_usd, None);
+        assert!(metrics.unpriced_models.is_empty());
+    }
+
+    #[test]
+    fn optional_amount_parses_numbers_and_form_fields() {
+        #[derive(Deserialize)]
+        struct Form {
+            #[serde(default, deserialize_with = "optional_amount")]
+            budget: Option<f64>,
+        }
+        let parse = |json: serde_json::Value| {
+            serde_json::from_value::<Form>(json).map(|f| f.budget).map_err(|e| e.to_string())
+        };
+        assert_eq!(parse(serde_json::json!({})), Ok(None));
+        assert_eq!(parse(serde_json::json!({ "budget": null })), Ok(None));
+        assert_eq!(parse(serde_json::json!({ "budget": "" })), Ok(None));
+        assert_eq!(parse(serde_json::json!({ "budget": "  " })), Ok(None));
+        assert_eq!(parse(serde_json::json!({ "budget": 2.5 })), Ok(Some(2.5)));
+        assert_eq!(parse(serde_json::json!({ "budget": "2.5" })), Ok(Some(2.5)));
+        assert_eq!(parse(serde_json::json!({ "budget": " $10 " })), Ok(Some(10.0)));
+        assert!(parse(serde_json::json!({ "budget": "ten" })).is_err());
+        assert!(parse(serde_json::json!({ "budget": 0 })).is_err());
+        assert!(parse(serde_json::json!({ "budget": "-1" })).is_err());
+    }
+
+    #[test]
+    fn usd_rounds_to_cents() {
+        assert_eq!(usd(0.0), "$0.00");
+        assert_eq!(usd(0.004), "<$0.01");
+        assert_eq!(usd(0.005), "$0.01");
+        assert_eq!(usd(1.234), "$1.23");
+        assert_eq!(usd(1234.5), "$1234.50");
+    }
+}
@@ -1,33 +1,453 @@
-use std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::Duration;
 
-/// Check if an X display number is already in use
-pub fn is_display_in_use(display_num: u32) -> bool {
-    let lock_file = format!("/tmp/.X{}-lock", display_num);
-    std::path::Path::new(&lock_file).exists()
+/// Authorization protocol understood by Xvfb and every X client
+pub const COOKIE_PROTOCOL: &[u8] = b"MIT-MAGIC-COOKIE-1";
+
+/// Xauthority family matching any host, so the entry works over the local
+/// socket from inside a sandbox whose hostname differs from ours
+const FAMILY_WILD: u16 = 0xffff;
+
+/// How long Xvfb may take to start listening
+const XVFB_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long Xvfb gets to exit after SIGTERM before it is killed
+const XVFB_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Descriptor Xvfb writes its display number to
+const DISPLAYFD: libc::c_int = 3;
+
+/// First display number given to a sandbox, leaving the low ones to the
+/// host's own X servers, which may start after the manager
+const DISPLAY_BASE: u32 = 50;
+
+/// How many displays to try when others are taken between the check and
+/// Xvfb claiming one
+const DISPLAY_ATTEMPTS: u32 = 10;
+
+/// Pixel depths offered for a screen: those Xvfb accepts that screenshots
+/// can read, i.e. not 8, whose pixels are colormap indices
+const DEPTHS: [u8; 4] = [15, 16, 24, 30];
+
+/// Size and depth of an Xvfb screen
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Geometry {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u8,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            depth: 24,
+        }
+    }
+}
+
+impl std::fmt::Display for Geometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}x{}", self.width, self.height, self.depth)
+    }
 }
 
-/// Find the next free display number starting from `start`
-pub fn allocate_display(start: u32) -> u32 {
-    let mut num = start;
-    while is_display_in_use(num) {
-        num += 1;
+impl Geometry {
+    /// Build from a `WIDTHxHEIGHT` resolution and a depth, defaulting
+    /// whichever is missing
+    pub fn parse(resolution: Option<&str>, depth: Option<u8>) -> Result<Self, String> {
+        let mut geometry = Self::default();
+        if let Some(res) = resolution.filter(|r| !r.is_empty()) {
+            let (w, h) = res
+                .split_once('x')
+                .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                .ok_or_else(|| format!("Resolution must be WIDTHxHEIGHT, got {:?}", res))?;
+            if !(64..=8192).contains(&w) || !(64..=8192).contains(&h) {
+                return Err("Resolution must be between 64x64 and 8192x8192".into());
+            }
+            geometry.width = w;
+            geometry.height = h;
+        }
+        if let Some(depth) = depth {
+            if !DEPTHS.contains(&depth) {
+                return Err(format!("Depth must be one of {:?}", DEPTHS));
+            }
+            geometry.depth = depth;
+        }
+        Ok(geometry)
     }
-    num
 }
 
-/// Start Xvfb on the given display number, return its PID
-pub fn start_xvfb(display_num: u32) -> std::io::Result<u32> {
-    let display = format!(":{}", display_num);
-    let child = Command::new("Xvfb")
-        .args([&display, "-screen", "0", "1920x1080x24", "-ac"])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .spawn()?;
-    // Child::drop does NOT kill the process on Unix — it continues running
-    Ok(child.id())
+/// A fresh random cookie for an Xauthority file
+fn new_cookie() -> std::io::Result<[u8; 16]> {
+    let mut cookie = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut cookie)?;
+    Ok(cookie)
 }
 
-/// Kill an Xvfb process by PID
-pub fn stop_xvfb(pid: u32) {
-    let _ = Command::new("kill").arg(pid.to_string()).output();
+/// Write an Xauthority file holding `cookie` for `display_num`, or for any
+/// display if it is not known yet.
+///
+/// The file is created owner-only; only holders of it (the sandbox it is
+/// passed to, and the manager) can connect to the display.
+fn write_xauthority(path: &Path, display_num: Option<u32>, cookie: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    // Entry layout: family, then address, display number, protocol name and
+    // cookie, each as a big-endian u16 length followed by the bytes
+    let number = display_num.map(|n| n.to_string()).unwrap_or_default();
+    let mut entry = FAMILY_WILD.to_be_bytes().to_vec();
+    for field in [&b""[..], number.as_bytes(), COOKIE_PROTOCOL, cookie] {
+        entry.extend_from_slice(&(field.len() as u16).to_be_bytes());
+        entry.extend_from_slice(field);
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let _ = std::fs::remove_file(path);
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(&entry)
+}
+
+/// Read the MIT-MAGIC-COOKIE-1 cookie back out of an Xauthority file
+pub fn read_cookie(path: &Path) -> Option<Vec<u8>> {
+    let data = std::fs::read(path).ok()?;
+    let mut rest = &data[..];
+    let mut take = |n: usize| -> Option<&[u8]> {
+        let (head, tail) = rest.split_at_checked(n)?;
+        rest = tail;
+        Some(head)
+    };
+    loop {
+        take(2)?; // family
+        let mut fields = [&[][..]; 4];
+        for field in &mut fields {
+            let len = take(2)?;
+            *field = take(u16::from_be_bytes([len[0], len[1]]) as usize)?;
+        }
+        let [_address, _number, protocol, cookie] = fields;
+        if protocol == COOKIE_PROTOCOL {
+            return Some(cookie.to_vec());
+        }
+    }
+}
+
+/// A running Xvfb server
+pub struct Xvfb {
+    pub pid: u32,
+    pub display_num: u32,
+    /// From `/proc/<pid>/stat`; with the PID, identifies this process even
+    /// after the PID is reused
+    pub start_time: Option<u64>,
+    pub child: Child,
+}
+
+/// Start Xvfb on the first free display from `DISPLAY_BASE` up and wait
+/// until it accepts connections.
+///
+/// Xvfb searches from display 0 when it picks one itself, so it is given
+/// the number, and still reports on a pipe (`-displayfd`) once it listens.
+/// Should another server take the display first, the next free one is
+/// tried. Only clients holding the cookie written to `xauthority` may
+/// connect. Xvfb's output goes to `log`, whose tail is returned if the
+/// server fails to start.
+pub async fn start_xvfb(geometry: Geometry, xauthority: &Path, log: &Path) -> Result<Xvfb, String> {
+    let cookie = new_cookie().map_err(|e| format!("Failed to generate X cookie: {}", e))?;
+    write_xauthority(xauthority, None, &cookie)
+        .map_err(|e| format!("Failed to write Xauthority: {}", e))?;
+
+    let mut display_num = DISPLAY_BASE;
+    for _ in 0..DISPLAY_ATTEMPTS {
+        while display_taken(display_num) {
+            display_num += 1;
+        }
+        match spawn_xvfb(display_num, geometry, xauthority, log).await {
+            Ok(mut child) => {
+                if let Err(e) = write_xauthority(xauthority, Some(display_num), &cookie) {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!("Failed to write Xauthority: {}", e));
+                }
+                return Ok(Xvfb {
+                    pid: child.id(),
+                    display_num,
+                    start_time: process_start_time(child.id()),
+                    child,
+                });
+            }
+            // Lost the display to a server started since the check. Ours
+            // is reaped by now, so a live lock holder is someone else.
+            Err(e) if lock_holder_alive(display_num) => {
+                tracing::debug!("Display :{} was taken meanwhile: {}", display_num, e);
+                display_num += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(format!("No free display found after {} attempts", DISPLAY_ATTEMPTS))
+}
+
+/// Whether the process named in a display's lock file is running
+fn lock_holder_alive(display_num: u32) -> bool {
+    let Ok(lock) = std::fs::read_to_string(format!("/tmp/.X{}-lock", display_num)) else {
+        return false;
+    };
+    let Ok(pid) = lock.trim().parse::<libc::pid_t>() else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists
+    pid > 0
+        && (unsafe { libc::kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+/// Whether an X server holds, or left behind, the lock or socket of a display
+fn display_taken(display_num: u32) -> bool {
+    Path::new(&format!("/tmp/.X{}-lock", display_num)).exists()
+        || Path::new(&format!("/tmp/.X11-unix/X{}", display_num)).exists()
+}
+
+/// Run Xvfb on `display_num` and wait until it accepts connections
+async fn spawn_xvfb(
+    display_num: u32,
+    geometry: Geometry,
+    xauthority: &Path,
+    log: &Path,
+) -> Result<Child, String> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    let log_file = std::fs::File::create(log)
+        .map_err(|e| format!("Failed to create {}: {}", log.display(), e))?;
+    let (reader, writer) = std::io::pipe().map_err(|e| e.to_string())?;
+    let writer_fd = writer.as_raw_fd();
+    let mut cmd = Command::new("Xvfb");
+    cmd.arg(format!(":{}", display_num))
+        .args(["-displayfd", &DISPLAYFD.to_string()])
+        .args(["-screen", "0", &geometry.to_string(), "-auth"])
+        .arg(xauthority)
+        .stdout(log_file.try_clone().map_err(|e| e.to_string())?)
+        .stderr(log_file);
+    // SAFETY: dup2 is async-signal-safe; it clears close-on-exec on the copy
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::dup2(writer_fd, DISPLAYFD) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+    // Only Xvfb holds the write end now, so its exit closes the pipe
+    drop(writer);
+
+    match wait_for_display(&mut child, reader).await {
+        Ok(_) => Ok(child),
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            let output = std::fs::read_to_string(log).unwrap_or_default();
+            let tail: Vec<&str> = output.lines().rev().take(5).collect();
+            let tail: Vec<&str> = tail.into_iter().rev().collect();
+            if tail.is_empty() {
+                Err(e)
+            } else {
+                Err(format!("{}: {}", e, tail.join(" / ")))
+            }
+        }
+    }
+}
+
+async fn wait_for_display(child: &mut Child, reader: std::io::PipeReader) -> Result<u32, String> {
+    use tokio::io::AsyncReadExt;
+
+    let started = std::time::Instant::now();
+    let mut pipe = tokio::net::unix::pipe::Receiver::from_owned_fd(reader.into())
+        .map_err(|e| e.to_string())?;
+    let mut reported = Vec::new();
+    let read = tokio::time::timeout(XVFB_STARTUP_TIMEOUT, async {
+        // Xvfb writes the number and a newline once it is listening
+        while !reported.contains(&b'\n') {
+            if pipe.read_buf(&mut reported).await? == 0 {
+                break;
+            }
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+    match read {
+        Err(_) => return Err("Xvfb did not report a display in time".into()),
+        Ok(Err(e)) => return Err(format!("Failed to read display from Xvfb: {}", e)),
+        Ok(Ok(())) => {}
+    }
+    let display_num: u32 = match String::from_utf8_lossy(&reported).trim().parse() {
+        Ok(num) => num,
+        Err(_) => {
+            // The pipe closed without a number: Xvfb is most likely on its
+            // way out, so give it a moment to be reaped
+            for _ in 0..20 {
+                if let Ok(Some(status)) = child.try_wait() {
+                    return Err(format!("Xvfb exited: {}", status));
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            return Err("Xvfb did not report a display".into());
+        }
+    };
+
+    let socket = format!("/tmp/.X11-unix/X{}", display_num);
+    while std::os::unix::net::UnixStream::connect(&socket).is_err() {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("Xvfb exited: {}", status));
+        }
+        if started.elapsed() > XVFB_STARTUP_TIMEOUT {
+            return Err(format!("{} did not accept connections in time", socket));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    Ok(display_num)
+}
+
+/// Fields 3 onwards of `/proc/<pid>/stat`. The command name in field 2 may
+/// contain spaces and parentheses, so split after its closing parenthesis.
+fn proc_stat(pid: u32) -> Option<Vec<String>> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields = &stat[stat.rfind(')')? + 1..];
+    Some(fields.split_whitespace().map(str::to_string).collect())
+}
+
+/// Start time of a process in clock ticks since boot (field 22)
+pub fn process_start_time(pid: u32) -> Option<u64> {
+    proc_stat(pid)?.get(19)?.parse().ok()
+}
+
+/// Whether `pid` is still the Xvfb we started: a live (not zombie) `Xvfb`
+/// process, with the same start time if we recorded one
+fn is_our_xvfb(pid: u32, start_time: Option<u64>) -> bool {
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+    if comm.trim_end() != "Xvfb" {
+        return false;
+    }
+    let Some(stat) = proc_stat(pid) else {
+        return false;
+    };
+    if stat.first().map(String::as_str) == Some("Z") {
+        return false;
+    }
+    match start_time {
+        Some(start) => stat.get(19).and_then(|s| s.parse().ok()) == Some(start),
+        None => true,
+    }
+}
+
+/// SIGTERM (so Xvfb removes its socket and lock file), escalating to
+/// SIGKILL if it is still around after `XVFB_STOP_TIMEOUT`
+async fn terminate(pid: u32, mut exited: impl FnMut() -> bool) {
+    // SAFETY: kill has no memory-safety preconditions
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+    let started = std::time::Instant::now();
+    while !exited() {
+        if started.elapsed() > XVFB_STOP_TIMEOUT {
+            tracing::warn!("Xvfb {} ignored SIGTERM, killing", pid);
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Xvfb servers started by this manager run, keyed by sandbox ID.
+///
+/// Owning the `Child` lets us signal and reap exactly the process we
+/// started. Servers inherited from a previous run are only known by PID
+/// and start time, and are verified before being signalled.
+#[derive(Default)]
+pub struct XvfbServers {
+    children: std::sync::Mutex<HashMap<String, Child>>,
+}
+
+impl XvfbServers {
+    pub fn insert(&self, id: &str, child: Child) {
+        self.lock().insert(id.to_string(), child);
+    }
+
+    /// Stop a sandbox's Xvfb and wait for it to exit
+    pub async fn stop(&self, id: &str, pid: u32, start_time: Option<u64>) {
+        let owned = self.lock().remove(id);
+        if let Some(mut child) = owned {
+            terminate(child.id(), || matches!(child.try_wait(), Ok(Some(_)))).await;
+            let _ = child.wait();
+        } else if is_our_xvfb(pid, start_time) {
+            terminate(pid, || !is_our_xvfb(pid, start_time)).await;
+        } else {
+            tracing::info!("Xvfb {} already gone or PID reused, not signalling it", pid);
+        }
+    }
+
+    /// Collect servers that exited on their own, so they don't linger as
+    /// zombies until their sandbox is stopped
+    pub fn reap(&self) {
+        self.lock().retain(|id, child| match child.try_wait() {
+            Ok(None) => true,
+            _ => {
+                tracing::info!("Xvfb for sandbox {} exited", &id[..8.min(id.len())]);
+                false
+            }
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Child>> {
+        self.children.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Terminate Xvfb servers left behind by a previous manager run.
+///
+/// A server is ours if its `-auth` file lives in `runtime_dir`; it is an
+/// orphan unless it is one of `live`, the `(pid, start time)` pairs of
+/// sandboxes still running. Returns how many were stopped.
+pub async fn kill_orphans(runtime_dir: &Path, live: &[(u32, Option<u64>)]) -> usize {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return 0;
+    };
+    let mut killed = 0;
+    for entry in entries.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+            continue;
+        };
+        if !is_our_xvfb(pid, None) {
+            continue;
+        }
+        let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) else {
+            continue;
+        };
+        let args: Vec<&[u8]> = cmdline.split(|&b| b == 0).collect();
+        let ours = args
+            .windows(2)
+            .any(|w| w[0] == b"-auth" && Path::new(&*String::from_utf8_lossy(w[1])).starts_with(runtime_dir));
+        if !ours {
+            continue;
+        }
+        let start_time = process_start_time(pid);
+        let claimed = live
+            .iter()
+            .any(|&(p, start)| p == pid && (start.is_none() || start == start_time));
+        if !claimed {
+            tracing::info!("Stopping orphaned Xvfb {}", pid);
+            terminate(pid, || !is_our_xvfb(pid, start_time)).await;
+            killed += 1;
+        }
+    }
+    killed
 }
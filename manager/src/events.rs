@@ -0,0 +1,345 @@
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Path, State, WebSocketUpgrade};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::claude::Tail;
+use crate::metrics::{self, ClaudeMetrics, TokenUsage};
+use crate::state::{Sandbox, SharedState};
+
+/// How often to look for new session lines (milliseconds)
+const POLL_INTERVAL_MS: u64 = 250;
+
+/// How much of the latest session to read on connect to find the tool calls
+/// still running
+const BACKLOG_BYTES: u64 = 1024 * 1024;
+
+/// Bytes of one file read per poll, or the first line if longer; the rest
+/// waits for the next one
+const MAX_READ: u64 = 8 * 1024 * 1024;
+
+/// Longest prompt text or tool summary sent
+const MAX_TEXT: usize = 500;
+
+/// Something that happened in one of the sandbox's Claude sessions
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Sent first: tool calls without a result yet, and the totals so far
+    Snapshot {
+        running: Vec<RunningTool>,
+        metrics: Option<ClaudeMetrics>,
+    },
+    UserMessage {
+        session: String,
+        timestamp: Option<DateTime<Utc>>,
+        text: String,
+    },
+    ToolStarted {
+        session: String,
+        #[serde(flatten)]
+        tool: RunningTool,
+    },
+    ToolFinished {
+        session: String,
+        timestamp: Option<DateTime<Utc>>,
+        id: String,
+        tool: String,
+        is_error: bool,
+    },
+    /// Tokens of one assistant message
+    Usage {
+        session: String,
+        timestamp: Option<DateTime<Utc>>,
+        model: String,
+        #[serde(flatten)]
+        usage: TokenUsage,
+    },
+    /// New totals, after every batch of other events
+    Metrics { metrics: ClaudeMetrics },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunningTool {
+    pub id: String,
+    pub tool: String,
+    /// The gist of the call's input, e.g. a Bash command or a file path
+    pub summary: String,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+pub async fn ws_events(
+    ws: WebSocketUpgrade,
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Response {
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    let Some(sandbox) = sandbox else {
+        return Response::builder()
+            .status(404)
+            .body("Sandbox not found".into())
+            .unwrap();
+    };
+    ws.on_upgrade(move |socket| handle_socket(socket, state, sandbox))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: SharedState, sandbox: Sandbox) {
+    let mut tailer = Tailer::new(&sandbox);
+    let mut poll = tokio::time::interval(tokio::time::Duration::from_millis(POLL_INTERVAL_MS));
+    loop {
+        poll.tick().await;
+
+        // Session files are read with blocking IO, off the async runtime
+        let s = state.clone();
+        let sb = sandbox.clone();
+        let polled = tokio::task::spawn_blocking(move || {
+            let events = tailer.poll(&s, &sb);
+            (tailer, events)
+        })
+        .await;
+        let events;
+        (tailer, events) = match polled {
+            Ok(polled) => polled,
+            Err(_) => return,
+        };
+
+        for event in events {
+            let Ok(text) = serde_json::to_string(&event) else {
+                continue;
+            };
+            if socket.send(Message::Text(text)).await.is_err() {
+                return; // Client disconnected
+            }
+        }
+
+        // Check for incoming close/ping messages (non-blocking)
+        match tokio::time::timeout(
+            tokio::time::Duration::from_millis(1),
+            socket.recv(),
+        )
+        .await
+        {
+            Ok(Some(Ok(Message::Close(_)))) | Ok(None) => return,
+            _ => {} // Timeout or other message, continue
+        }
+    }
+}
+
+/// Follows all of a sandbox's session files, including ones Claude starts
+/// later, e.g. after `/clear`
+struct Tailer {
+    /// Entries timestamped earlier are history, never sent as events
+    connected: DateTime<Utc>,
+    created: DateTime<Utc>,
+    files: HashMap<PathBuf, Tail>,
+    running: HashMap<String, RunningTool>,
+    /// Responses whose usage was sent, by `metrics::message_key`; each
+    /// content block's line repeats it
+    messages: HashSet<String>,
+    started: bool,
+}
+
+impl Tailer {
+    fn new(sandbox: &Sandbox) -> Self {
+        Self {
+            connected: Utc::now(),
+            created: sandbox.created_at,
+            files: HashMap::new(),
+            running: HashMap::new(),
+            messages: HashSet::new(),
+            started: false,
+        }
+    }
+
+    fn poll(&mut self, state: &SharedState, sandbox: &Sandbox) -> Vec<Event> {
+        let mut paths = state.sessions.session_files(sandbox);
+        paths.sort();
+        if !self.started {
+            self.started = true;
+            self.skip_history(&paths);
+            let mut running: Vec<RunningTool> = self.running.values().cloned().collect();
+            running.sort_by_key(|t| t.timestamp);
+            return vec![Event::Snapshot {
+                running,
+                metrics: state.sessions.totals(sandbox),
+            }];
+        }
+
+        let mut events = Vec::new();
+        for path in paths {
+            let session = session_id(&path);
+            for v in self.read(&path) {
+                events.extend(self.events(&session, &v));
+            }
+        }
+        if !events.is_empty() {
+            if let Some(metrics) = state.sessions.totals(sandbox) {
+                events.push(Event::Metrics { metrics });
+            }
+        }
+        events
+    }
+
+    /// Start every file at its end, after collecting the tool calls still
+    /// running from the end of the most recently written one
+    fn skip_history(&mut self, paths: &[PathBuf]) {
+        let latest = paths
+            .iter()
+            .max_by_key(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok());
+        for path in paths {
+            let Ok(meta) = std::fs::metadata(path) else {
+                continue;
+            };
+            let len = meta.len();
+            let from = if Some(path) == latest { len.saturating_sub(BACKLOG_BYTES) } else { len };
+            self.files.insert(path.clone(), Tail::at(&meta, from));
+            if from == len {
+                continue;
+            }
+            let session = session_id(path);
+            let lines = self.read(path);
+            // Starting mid-file, the first line is likely cut
+            for v in lines.iter().skip(usize::from(from > 0)) {
+                self.events(&session, v);
+            }
+        }
+    }
+
+    /// Complete lines appended to `path` since the last read
+    fn read(&mut self, path: &PathBuf) -> Vec<serde_json::Value> {
+        let tail = self.files.entry(path.clone()).or_default();
+        let Ok((file, _)) = tail.open(path) else {
+            self.files.remove(path);
+            return Vec::new();
+        };
+        let mut values = Vec::new();
+        let _ = tail.read_lines(file, MAX_READ, |line| {
+            if let Ok(v) = serde_json::from_slice(line) {
+                values.push(v);
+            }
+        });
+        values
+    }
+
+    /// Events of one session entry, keeping track of running tool calls
+    fn events(&mut self, session: &str, v: &serde_json::Value) -> Vec<Event> {
+        let timestamp = v
+            .get("timestamp")
+            .and_then(|t| t.as_str())
+            .and_then(|t| t.parse::<DateTime<Utc>>().ok());
+        if timestamp.is_some_and(|t| t < self.created) {
+            return Vec::new();
+        }
+        let live = timestamp.is_none_or(|t| t >= self.connected);
+        let content = v.pointer("/message/content");
+        let mut events = Vec::new();
+        match v.get("type").and_then(|t| t.as_str()) {
+            Some("user") => {
+                let blocks = content.and_then(|c| c.as_array()).map(Vec::as_slice).unwrap_or_default();
+                for block in blocks {
+                    if block.get("type").and_then(|t| t.as_str()) != Some("tool_result") {
+                        continue;
+                    }
+                    let Some(id) = block.get("tool_use_id").and_then(|i| i.as_str()) else {
+                        continue;
+                    };
+                    let Some(call) = self.running.remove(id) else {
+                        continue;
+                    };
+                    events.push(Event::ToolFinished {
+                        session: session.to_string(),
+                        timestamp,
+                        id: call.id,
+                        tool: call.tool,
+                        is_error: block.get("is_error").and_then(|e| e.as_bool()) == Some(true),
+                    });
+                }
+                // Context Claude injects itself isn't something the user said
+                let meta = v.get("isMeta").and_then(|m| m.as_bool()) == Some(true);
+                let text = match content {
+                    Some(serde_json::Value::String(s)) => s.clone(),
+                    _ => blocks
+                        .iter()
+                        .filter(|b| b.get("type").and_then(|t| t.as_str()) == Some("text"))
+                        .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                };
+                if !meta && !text.trim().is_empty() {
+                    events.push(Event::UserMessage {
+                        session: session.to_string(),
+                        timestamp,
+                        text: truncate(text.trim()),
+                    });
+                }
+            }
+            Some("assistant") => {
+                let first = metrics::message_key(v).is_none_or(|key| self.messages.insert(key));
+                if let Some(usage) = v.pointer("/message/usage").filter(|_| first) {
+                    let model = v.pointer("/message/model").and_then(|m| m.as_str()).unwrap_or("unknown");
+                    events.push(Event::Usage {
+                        session: session.to_string(),
+                        timestamp,
+                        model: model.to_string(),
+                        usage: TokenUsage::from_json(usage),
+                    });
+                }
+                for block in content.and_then(|c| c.as_array()).into_iter().flatten() {
+                    if block.get("type").and_then(|t| t.as_str()) != Some("tool_use") {
+                        continue;
+                    }
+                    let Some(id) = block.get("id").and_then(|i| i.as_str()) else {
+                        continue;
+                    };
+                    let tool = block.get("name").and_then(|n| n.as_str()).unwrap_or("unknown");
+                    let call = RunningTool {
+                        id: id.to_string(),
+                        tool: tool.to_string(),
+                        summary: block.get("input").map(tool_summary).unwrap_or_default(),
+                        timestamp,
+                    };
+                    self.running.insert(call.id.clone(), call.clone());
+                    events.push(Event::ToolStarted {
+                        session: session.to_string(),
+                        tool: call,
+                    });
+                }
+            }
+            _ => {}
+        }
+        if live {
+            events
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+fn session_id(path: &std::path::Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Input fields that say what a call does, in order of preference
+const SUMMARY_FIELDS: &[&str] = &["command", "file_path", "path", "pattern", "url", "query", "description"];
+
+/// The gist of a tool call's input: its command, file or pattern, else the
+/// input as JSON
+fn tool_summary(input: &serde_json::Value) -> String {
+    let field = SUMMARY_FIELDS
+        .iter()
+        .find_map(|key| input.get(*key).and_then(|v| v.as_str()));
+    match field {
+        Some(text) => truncate(text),
+        None => truncate(&input.to_string()),
+    }
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_TEXT) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
@@ -1,9 +1,19 @@
 use askama::Template;
-use axum::extract::{Path, State};
-use axum::response::IntoResponse;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 
-use crate::metrics::{self, ClaudeMetrics, SystemMetrics};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::api;
+use crate::cost::{self, CostLabels};
+use crate::metrics::{ClaudeMetrics, SystemMetrics};
+use crate::metrics_history::{self, Sparkline};
+use crate::resources::ResourceUsage;
+use crate::screenshot;
 use crate::state::{Sandbox, SharedState};
+use crate::transcript::{self, TranscriptPage, TranscriptQuery};
 
 // ---------------------------------------------------------------------------
 // Fragment templates (htmx partials — no {% extends %})
@@ -13,6 +23,8 @@ use crate::state::{Sandbox, SharedState};
 #[template(path = "fragments/sandbox_list.html")]
 pub struct SandboxListFragment {
     pub sandboxes: Vec<Sandbox>,
+    pub usage: HashMap<String, ResourceUsage>,
+    pub costs: CostLabels,
 }
 
 #[derive(Template)]
@@ -25,13 +37,44 @@ pub struct SystemMetricsFragment {
 #[template(path = "fragments/claude_metrics.html")]
 pub struct ClaudeMetricsFragment {
     pub metrics: ClaudeMetrics,
+    pub budget: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "fragments/tools.html")]
+pub struct ToolsFragment {
+    pub metrics: ClaudeMetrics,
+}
+
+#[derive(Template)]
+#[template(path = "fragments/transcript.html")]
+pub struct TranscriptFragment {
+    pub sandbox_id: String,
+    pub page: TranscriptPage,
+    /// Search text, to keep it in the form
+    pub q: String,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "fragments/resources.html")]
+pub struct ResourcesFragment {
+    pub resources: Option<ResourceUsage>,
+}
+
+#[derive(Template)]
+#[template(path = "fragments/sparklines.html")]
+pub struct SparklinesFragment {
+    pub sparklines: Vec<Sparkline>,
+    pub minutes: u32,
 }
 
 #[derive(Template)]
 #[template(path = "fragments/screenshot_frame.html")]
 pub struct ScreenshotFrameFragment {
     pub sandbox_id: String,
-    pub has_screenshot: bool,
+    /// Frame hash without quotes, to make the `<img>` URL change with it
+    pub version: Option<String>,
 }
 
 // ---------------------------------------------------------------------------
@@ -39,14 +82,22 @@ pub struct ScreenshotFrameFragment {
 // ---------------------------------------------------------------------------
 
 pub async fn sandbox_list(State(state): State<SharedState>) -> impl IntoResponse {
-    let manager = state.manager.read().await;
-    let mut sandboxes: Vec<Sandbox> = manager.sandboxes.values().cloned().collect();
-    sandboxes.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-    SandboxListFragment { sandboxes }
+    let mut sandboxes: Vec<Sandbox> = {
+        let manager = state.manager.read().await;
+        manager.sandboxes.values().cloned().collect()
+    };
+    sandboxes.sort_by_key(|sb| std::cmp::Reverse(sb.created_at));
+    let usage = api::sample_resources(&state, sandboxes.clone()).await;
+    let costs = CostLabels::new(&state, sandboxes.clone()).await;
+    SandboxListFragment {
+        sandboxes,
+        usage,
+        costs,
+    }
 }
 
-pub async fn system_metrics() -> impl IntoResponse {
-    let metrics = metrics::collect_system_metrics();
+pub async fn system_metrics(State(state): State<SharedState>) -> impl IntoResponse {
+    let metrics = api::sample_system(&state).await;
     SystemMetricsFragment { metrics }
 }
 
@@ -54,22 +105,128 @@ pub async fn claude_metrics(
     State(state): State<SharedState>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
-    let manager = state.manager.read().await;
-    let metrics = manager
-        .sandboxes
-        .get(&id)
-        .and_then(|sb| metrics::parse_claude_metrics(&sb.project_dir))
-        .unwrap_or_default();
-    ClaudeMetricsFragment { metrics }
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    let budget = sandbox.as_ref().and_then(|sb| sb.budget_usd).map(cost::usd);
+    let metrics = match sandbox {
+        Some(sb) => api::claude_totals(&state, sb).await.unwrap_or_default(),
+        None => ClaudeMetrics::default(),
+    };
+    ClaudeMetricsFragment { metrics, budget }
 }
 
-pub async fn screenshot_frame(
+pub async fn tools(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    let metrics = match sandbox {
+        Some(sb) => api::claude_totals(&state, sb).await.unwrap_or_default(),
+        None => ClaudeMetrics::default(),
+    };
+    ToolsFragment { metrics }
+}
+
+pub async fn transcript(
     State(state): State<SharedState>,
     Path(id): Path<String>,
+    Query(query): Query<TranscriptQuery>,
 ) -> impl IntoResponse {
-    let has_screenshot = state.screenshots.read().await.contains_key(&id);
-    ScreenshotFrameFragment {
+    let q = query.q.clone().unwrap_or_default();
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    let page = match sandbox {
+        Some(sb) => {
+            let s = state.clone();
+            tokio::task::spawn_blocking(move || transcript::load(&s, &sb, &query))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+        }
+        None => Err("Sandbox not found".to_string()),
+    };
+    let (page, error) = match page {
+        Ok(page) => (page, None),
+        Err(e) => (TranscriptPage::default(), Some(e)),
+    };
+    TranscriptFragment {
         sandbox_id: id,
-        has_screenshot,
+        page,
+        q,
+        error,
     }
 }
+
+pub async fn resources(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    let resources = match sandbox {
+        Some(sb) => api::sample_resources(&state, vec![sb]).await.remove(&id),
+        None => None,
+    };
+    ResourcesFragment { resources }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SparklineQuery {
+    /// How far back the charts go
+    #[serde(default = "default_sparkline_minutes")]
+    pub minutes: u32,
+}
+
+fn default_sparkline_minutes() -> u32 {
+    60
+}
+
+pub async fn system_sparklines(
+    State(state): State<SharedState>,
+    Query(query): Query<SparklineQuery>,
+) -> impl IntoResponse {
+    let minutes = query.minutes.clamp(1, 24 * 60);
+    // No sandbox has an empty ID: keep only the host's numbers
+    let samples = metrics_history::recent(&state, minutes, Some("")).await;
+    SparklinesFragment {
+        sparklines: metrics_history::system_sparklines(&samples),
+        minutes,
+    }
+}
+
+pub async fn sandbox_sparklines(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Query(query): Query<SparklineQuery>,
+) -> impl IntoResponse {
+    let minutes = query.minutes.clamp(1, 24 * 60);
+    let samples = metrics_history::recent(&state, minutes, Some(&id)).await;
+    SparklinesFragment {
+        sparklines: metrics_history::sandbox_sparklines(&samples, &id),
+        minutes,
+    }
+}
+
+/// The fragment carries the frame's ETag, so polls while the screen is
+/// unchanged are answered with `304 Not Modified`
+pub async fn screenshot_frame(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    state.screenshots.touch(&id).await;
+    let etag = state
+        .screenshots
+        .etag(&id)
+        .await
+        .unwrap_or_else(|| "\"none\"".into());
+    if screenshot::etag_matches(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [("etag", etag)]).into_response();
+    }
+    let version = Some(etag.trim_matches('"').to_string()).filter(|v| v != "none");
+    let fragment = ScreenshotFrameFragment {
+        sandbox_id: id,
+        version,
+    };
+    (
+        [("etag", etag), ("cache-control", "no-cache".into())],
+        fragment,
+    )
+        .into_response()
+}
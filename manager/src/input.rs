@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::session;
+
+/// Scrollback lines captured on each side of the input to find new output
+const CAPTURE_HISTORY: u32 = 500;
+
+/// How often to re-capture the pane while waiting for output to settle
+const POLL_INTERVAL_MS: u64 = 200;
+
+/// Upper bound on `settle_ms` and `timeout_ms`, whatever the client asks for
+const MAX_WAIT_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendInputRequest {
+    /// Text typed literally into the pane
+    #[serde(default)]
+    pub text: Option<String>,
+    /// tmux key names sent after `text`, e.g. `["Escape"]` or `["C-c"]`
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// Press Enter after `text` and `keys`
+    #[serde(default)]
+    pub enter: bool,
+    /// Wait until the pane stops changing and return the new lines
+    #[serde(default)]
+    pub wait: bool,
+    /// How long the pane must stay unchanged to count as settled, at most
+    /// a minute
+    #[serde(default = "default_settle_ms")]
+    pub settle_ms: u64,
+    /// Give up waiting after this long and return what is there, at most a
+    /// minute
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl SendInputRequest {
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none() && self.keys.is_empty() && !self.enter
+    }
+}
+
+fn default_settle_ms() -> u64 {
+    1000
+}
+
+fn default_timeout_ms() -> u64 {
+    30_000
+}
+
+#[derive(Debug, Serialize)]
+pub struct SendInputResponse {
+    /// Lines that appeared in the pane after the input (only with `wait`)
+    pub output: Option<Vec<String>>,
+    /// False if `timeout_ms` elapsed before the pane settled
+    pub settled: bool,
+}
+
+pub async fn send_input(
+    tmux_session: &str,
+    req: &SendInputRequest,
+) -> Result<SendInputResponse, String> {
+    let before = if req.wait {
+        Some(capture(tmux_session).await?)
+    } else {
+        None
+    };
+
+    let name = tmux_session.to_string();
+    let text = req.text.clone();
+    let mut keys = req.keys.clone();
+    if req.enter {
+        keys.push("Enter".into());
+    }
+    tokio::task::spawn_blocking(move || {
+        if let Some(ref text) = text {
+            session::send_text(&name, text).map_err(|e| format!("Failed to send text: {}", e))?;
+        }
+        if !keys.is_empty() {
+            session::send_keys(&name, &keys).map_err(|e| format!("Failed to send keys: {}", e))?;
+        }
+        Ok::<_, String>(())
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    let Some(before) = before else {
+        return Ok(SendInputResponse {
+            output: None,
+            settled: true,
+        });
+    };
+
+    let (after, settled) = wait_for_settle(tmux_session, req).await?;
+    Ok(SendInputResponse {
+        output: Some(new_lines(&before, &after)),
+        settled,
+    })
+}
+
+/// Capture the pane off the async runtime, tmux being a blocking command
+async fn capture(tmux_session: &str) -> Result<Vec<String>, String> {
+    let name = tmux_session.to_string();
+    let text = tokio::task::spawn_blocking(move || session::capture_pane(&name, CAPTURE_HISTORY))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to capture pane: {}", e))?;
+    let mut lines: Vec<String> = text.lines().map(|l| l.trim_end().to_string()).collect();
+    // The screen below the cursor is usually blank padding
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    Ok(lines)
+}
+
+/// Poll the pane until it has looked the same for `settle_ms`, or until
+/// `timeout_ms` has passed. Returns the last capture and whether it settled.
+async fn wait_for_settle(
+    tmux_session: &str,
+    req: &SendInputRequest,
+) -> Result<(Vec<String>, bool), String> {
+    let started = Instant::now();
+    let settle = Duration::from_millis(req.settle_ms.min(MAX_WAIT_MS));
+    let timeout = Duration::from_millis(req.timeout_ms.min(MAX_WAIT_MS));
+
+    let mut last = capture(tmux_session).await?;
+    let mut last_change = Instant::now();
+    loop {
+        tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MS)).await;
+        let current = capture(tmux_session).await?;
+        if current != last {
+            last = current;
+            last_change = Instant::now();
+        } else if last_change.elapsed() >= settle {
+            return Ok((last, true));
+        }
+        if started.elapsed() >= timeout {
+            return Ok((last, false));
+        }
+    }
+}
+
+/// Lines of `after` that are not part of its longest common subsequence
+/// with `before`. Covers both output that scrolled in at the bottom and
+/// TUI regions redrawn in place.
+fn new_lines(before: &[String], after: &[String]) -> Vec<String> {
+    let (n, m) = (before.len(), after.len());
+    // lcs[i][j] = LCS length of before[i..] and after[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if before[i] == after[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut added = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while j < m {
+        if i < n && before[i] == after[j] {
+            i += 1;
+            j += 1;
+        } else if i < n && lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            added.push(after[j].clone());
+            j += 1;
+        }
+    }
+    added
+}
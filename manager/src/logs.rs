@@ -63,10 +63,8 @@ async fn handle_socket(mut socket: WebSocket, log_path: std::path::PathBuf) {
 
     let backlog_start = all_lines.len().saturating_sub(INITIAL_BACKLOG_LINES);
     let backlog: String = all_lines[backlog_start..].concat();
-    if !backlog.is_empty() {
-        if socket.send(Message::Text(backlog.into())).await.is_err() {
-            return;
-        }
+    if !backlog.is_empty() && socket.send(Message::Text(backlog)).await.is_err() {
+        return;
     }
     drop(all_lines);
 
@@ -86,10 +84,8 @@ async fn handle_socket(mut socket: WebSocket, log_path: std::path::PathBuf) {
             }
         }
 
-        if !new_data.is_empty() {
-            if socket.send(Message::Text(new_data.into())).await.is_err() {
-                return; // Client disconnected
-            }
+        if !new_data.is_empty() && socket.send(Message::Text(new_data)).await.is_err() {
+            return; // Client disconnected
         }
 
         // Check for incoming close/ping messages (non-blocking)
@@ -1,22 +1,55 @@
 use axum::routing::{get, post};
 use axum::Router;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tower_http::services::ServeDir;
 
+mod ansi;
 mod api;
+mod attention;
+mod audit;
+mod claude;
+mod cost;
 mod display;
+mod events;
 mod fragments;
+mod input;
 mod logs;
 mod metrics;
+mod metrics_history;
+mod notify;
+mod openmetrics;
+mod resources;
 mod sandbox;
+mod scope;
+mod screen;
 mod screenshot;
+mod screenshot_history;
 mod session;
 mod state;
+mod terminal;
+mod transcript;
+mod vnc;
+mod webhooks;
 
-use state::{AppState, ManagerState, SandboxStatus};
+use attention::AttentionConfig;
+use audit::AuditLog;
+use claude::SessionCache;
+use cost::PriceTable;
+use display::XvfbServers;
+use metrics::SystemSampler;
+use metrics_history::{MetricsHistory, MetricsHistoryConfig};
+use notify::{Notifier, NotifyConfig};
+use openmetrics::Telemetry;
+use resources::ResourceMonitor;
+use scope::ScopeConfig;
+use screenshot::{ScreenshotConfig, ScreenshotStore};
+use screenshot_history::{HistoryConfig, ScreenshotHistory};
+use vnc::VncServers;
+use webhooks::{WebhookConfig, WebhookEvent, Webhooks};
+use state::{AppState, ManagerState, Sandbox, SandboxStatus};
 
 #[tokio::main]
 async fn main() {
@@ -26,73 +59,186 @@ async fn main() {
         std::env::var("MANAGER_LISTEN").unwrap_or_else(|_| "127.0.0.1:3000".into());
     let state_dir =
         std::env::var("MANAGER_STATE_DIR").unwrap_or_else(|_| ".".into());
+    let runtime_dir = std::env::var("MANAGER_RUNTIME_DIR")
+        .unwrap_or_else(|_| "/run/claude-manager".into());
     let static_dir =
         std::env::var("MANAGER_STATIC_DIR").unwrap_or_else(|_| "static".into());
 
     let state_path = PathBuf::from(&state_dir).join("state.json");
     let log_dir = PathBuf::from(&state_dir).join("logs");
+    let audit_path = PathBuf::from(&state_dir).join("audit.jsonl");
     std::fs::create_dir_all(&log_dir).expect("Failed to create log directory");
     let mut manager_state = ManagerState::load(&state_path);
-    manager_state.reconcile_pids();
+    let died = manager_state.reconcile_pids();
+
+    // Xvfb servers of sandboxes that died while we were down, or that no
+    // sandbox knows about any more, would otherwise run forever
+    let live: Vec<(u32, Option<u64>)> = manager_state
+        .sandboxes
+        .values()
+        .filter(|sb| sb.status == SandboxStatus::Running)
+        .filter_map(|sb| Some((sb.pid_xvfb?, sb.xvfb_start_time)))
+        .collect();
+    let orphans = display::kill_orphans(Path::new(&runtime_dir), &live).await;
+    if orphans > 0 {
+        tracing::info!("Stopped {} orphaned Xvfb server(s)", orphans);
+    }
+    for sandbox in manager_state.sandboxes.values_mut() {
+        if sandbox.status != SandboxStatus::Running {
+            sandbox.pid_xvfb = None;
+            sandbox.xvfb_start_time = None;
+        }
+    }
     let _ = manager_state.save(&state_path);
 
+    let scopes = ScopeConfig::from_env();
+    if scopes.enabled {
+        if let Err(e) = scope::available() {
+            tracing::warn!(
+                "The user's systemd instance is unreachable, sandboxes will fail to start \
+                 (set MANAGER_SCOPES=0 to run them without scopes): {}",
+                e
+            );
+        }
+    }
+
+    let prices = PriceTable::from_env();
+    let default_budget = cost::default_budget_from_env(&prices);
+
     let shared = Arc::new(AppState {
         manager: RwLock::new(manager_state),
         state_path,
         log_dir,
-        screenshots: RwLock::new(HashMap::new()),
+        runtime_dir: PathBuf::from(runtime_dir),
+        screenshots: ScreenshotStore::new(ScreenshotConfig::from_env()),
+        history: ScreenshotHistory::new(
+            PathBuf::from(&state_dir).join("screenshots"),
+            HistoryConfig::from_env(),
+        ),
+        audit: AuditLog::new(audit_path),
+        vnc: VncServers::default(),
+        xvfb: XvfbServers::default(),
+        resources: ResourceMonitor::default(),
+        scopes,
+        metrics_history: MetricsHistory::load(
+            PathBuf::from(&state_dir).join("metrics-history.jsonl"),
+            MetricsHistoryConfig::from_env(),
+        ),
+        telemetry: Telemetry::default(),
+        system: SystemSampler::default(),
+        sessions: SessionCache::new(prices, claude::default_claude_dir()),
+        default_budget,
+        attention: AttentionConfig::from_env(),
+        notifier: Notifier::new(NotifyConfig::from_env()),
+        webhooks: Webhooks::new(
+            WebhookConfig::from_env(),
+            PathBuf::from(&state_dir).join("webhook-deliveries.jsonl"),
+        ),
     });
+    for sandbox in &died {
+        shared.webhooks.emit(WebhookEvent::Died, sandbox, serde_json::json!({}));
+    }
 
-    // Background: monitor sandbox liveness every 5s
+    // Background: monitor sandbox liveness and sample CPU usage every 5s
     {
         let s = shared.clone();
         tokio::spawn(async move {
             loop {
                 tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                s.xvfb.reap();
                 let mut mgr = s.manager.write().await;
-                mgr.reconcile_pids();
+                for sandbox in mgr.reconcile_pids() {
+                    s.webhooks.emit(WebhookEvent::Died, &sandbox, serde_json::json!({}));
+                }
                 let _ = mgr.save(&s.state_path);
+                let running: Vec<Sandbox> = mgr.sandboxes.values().filter(|sb| sb.is_running()).cloned().collect();
+                drop(mgr);
+                let s2 = s.clone();
+                let _ = tokio::task::spawn_blocking(move || s2.resources.update_cpu(&running)).await;
             }
         });
     }
 
-    // Background: capture screenshots every 2s for running sandboxes
+    // Background: every 2s, capture sandboxes whose screenshot is being
+    // watched or whose history is due a new frame
     {
         let s = shared.clone();
         tokio::spawn(async move {
             loop {
                 tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-                let ids_and_info: Vec<(String, Option<u32>, Option<String>)> = {
+                let watched = s.screenshots.watched().await;
+                let running: Vec<Sandbox> = {
                     let mgr = s.manager.read().await;
                     mgr.sandboxes
                         .values()
                         .filter(|sb| sb.status == SandboxStatus::Running)
-                        .map(|sb| {
-                            (
-                                sb.id.clone(),
-                                sb.display_num,
-                                sb.qemu_qmp_socket.clone(),
-                            )
-                        })
+                        .filter(|sb| sb.display_num.is_some() || sb.qemu_qmp_socket.is_some())
+                        .cloned()
                         .collect()
                 };
+                let mut targets = Vec::new();
+                for sandbox in running {
+                    if watched.contains(&sandbox.id) || s.history.due(&sandbox.id).await {
+                        targets.push(sandbox);
+                    }
+                }
 
-                for (id, display_num, qmp_socket) in ids_and_info {
-                    let png = if let Some(num) = display_num {
-                        screenshot::capture_xvfb(num)
-                    } else if let Some(ref sock) = qmp_socket {
-                        screenshot::capture_qmp(sock)
-                    } else {
-                        None
-                    };
-                    if let Some(data) = png {
-                        s.screenshots.write().await.insert(id, data);
+                for sandbox in targets {
+                    let s2 = s.clone();
+                    let id = sandbox.id.clone();
+                    let started = std::time::Instant::now();
+                    let raw = tokio::task::spawn_blocking(move || s2.screenshots.capture(&sandbox))
+                        .await
+                        .ok()
+                        .flatten();
+                    s.telemetry.record_capture(&id, started.elapsed(), raw.is_some());
+                    if let Some(raw) = raw {
+                        s.screenshots.update(&id, raw).await;
+                        if let Some(frame) = s.screenshots.get(&id).await {
+                            s.history.record(&id, &frame).await;
+                        }
                     }
                 }
             }
         });
     }
 
+    // Background: sample host and sandbox metrics into the history
+    let interval = shared.metrics_history.interval();
+    if !interval.is_zero() {
+        let s = shared.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let sample = metrics_history::sample(&s).await;
+                s.metrics_history.record(sample).await;
+            }
+        });
+    }
+
+    // Background: stop sandboxes that went over their budget
+    {
+        let s = shared.clone();
+        tokio::spawn(async move {
+            let mut warned = std::collections::HashMap::new();
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                cost::enforce_budgets(&s, &mut warned).await;
+            }
+        });
+    }
+
+    // Background: notice sandboxes waiting for someone and notify about them
+    {
+        let s = shared.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                attention::check(&s).await;
+            }
+        });
+    }
+
     let app = Router::new()
         // Pages
         .route("/", get(api::index))
@@ -105,33 +251,85 @@ async fn main() {
         )
         .route("/api/sandboxes/:id", get(api::get_sandbox).delete(api::delete_sandbox_api))
         .route("/api/sandboxes/:id/stop", post(api::stop_sandbox_api))
+        .route("/api/sandboxes/:id/input", post(api::send_input_api))
         .route(
             "/api/sandboxes/:id/screenshot",
             get(api::get_screenshot),
         )
+        .route(
+            "/api/sandboxes/:id/screenshots",
+            get(screenshot_history::list_screenshots),
+        )
+        .route(
+            "/api/sandboxes/:id/screenshots/:time",
+            get(screenshot_history::get_screenshot_at),
+        )
+        .route(
+            "/api/sandboxes/:id/timelapse",
+            get(screenshot_history::get_timelapse),
+        )
         .route("/ws/sandboxes/:id/logs", get(logs::ws_logs))
+        .route("/ws/sandboxes/:id/events", get(events::ws_events))
+        .route("/ws/sandboxes/:id/terminal", get(terminal::ws_terminal))
+        .route("/ws/sandboxes/:id/vnc", get(vnc::ws_vnc))
+        .route("/api/sandboxes/:id/screen", get(screen::get_screen))
+        .route("/ws/sandboxes/:id/screen", get(screen::ws_screen))
         .route(
             "/api/sandboxes/:id/metrics",
             get(api::get_sandbox_metrics),
         )
+        .route("/api/sandboxes/:id/sessions", get(api::get_sandbox_sessions))
+        .route("/api/sandboxes/:id/transcript", get(transcript::get_transcript))
         .route("/api/metrics/system", get(api::get_system_metrics))
+        .route("/api/metrics/history", get(metrics_history::get_history))
+        .route("/api/metrics/cost", get(cost::get_cost))
         .route("/api/sandboxes/:id/logs", get(api::get_logs))
+        .route("/api/audit", get(api::get_audit))
+        .route("/api/webhooks/deliveries", get(webhooks::get_deliveries))
         // htmx fragments
         .route("/fragments/sandbox-list", get(fragments::sandbox_list))
         .route("/fragments/system-metrics", get(fragments::system_metrics))
+        .route("/fragments/system-sparklines", get(fragments::system_sparklines))
         .route(
             "/fragments/sandboxes/:id/claude-metrics",
             get(fragments::claude_metrics),
         )
+        .route(
+            "/fragments/sandboxes/:id/tools",
+            get(fragments::tools),
+        )
+        .route(
+            "/fragments/sandboxes/:id/transcript",
+            get(fragments::transcript),
+        )
+        .route(
+            "/fragments/sandboxes/:id/resources",
+            get(fragments::resources),
+        )
+        .route(
+            "/fragments/sandboxes/:id/sparklines",
+            get(fragments::sandbox_sparklines),
+        )
         .route(
             "/fragments/sandboxes/:id/screenshot",
             get(fragments::screenshot_frame),
         )
+        // Prometheus scrape endpoint
+        .route("/metrics", get(openmetrics::get_metrics))
+        .route_layer(axum::middleware::from_fn_with_state(
+            shared.clone(),
+            openmetrics::track_http,
+        ))
         // Static files
         .nest_service("/static", ServeDir::new(&static_dir))
         .with_state(shared);
 
     tracing::info!("Listening on {}", listen_addr);
     let listener = tokio::net::TcpListener::bind(&listen_addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -1,46 +1,164 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use sysinfo::{Disks, Networks, System};
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SystemMetrics {
+    /// Average over all cores since the previous sample, 0 to 100
     pub cpu_usage: f32,
+    pub cpu_per_core: Vec<f32>,
+    pub load_1: f64,
+    pub load_5: f64,
+    pub load_15: f64,
     pub memory_used: u64,
     pub memory_total: u64,
+    /// Space on mounted filesystems, each device counted once
     pub disk_used: u64,
     pub disk_total: u64,
+    /// Bytes read from and written to those devices since boot
+    pub disk_read_bytes: u64,
+    pub disk_write_bytes: u64,
+    /// Bytes received and sent on all interfaces but loopback since boot
     pub net_rx_bytes: u64,
     pub net_tx_bytes: u64,
+    /// Per-second rates since the previous sample; unknown on the first
+    pub disk_read_rate: Option<f64>,
+    pub disk_write_rate: Option<f64>,
+    pub net_rx_rate: Option<f64>,
+    pub net_tx_rate: Option<f64>,
 }
 
-pub fn collect_system_metrics() -> SystemMetrics {
-    let mut sys = System::new();
-    sys.refresh_cpu_usage();
-    sys.refresh_memory();
-
-    let disks = Disks::new_with_refreshed_list();
-    let (disk_used, disk_total) = disks.list().iter().fold((0u64, 0u64), |(used, total), d| {
-        (
-            used + d.total_space() - d.available_space(),
-            total + d.total_space(),
-        )
-    });
-
-    let networks = Networks::new_with_refreshed_list();
-    let (rx, tx) = networks
-        .list()
-        .iter()
-        .fold((0u64, 0u64), |(rx, tx), (_, data)| {
-            (rx + data.total_received(), tx + data.total_transmitted())
-        });
-
-    SystemMetrics {
-        cpu_usage: sys.global_cpu_usage(),
-        memory_used: sys.used_memory(),
-        memory_total: sys.total_memory(),
-        disk_used,
-        disk_total,
-        net_rx_bytes: rx,
-        net_tx_bytes: tx,
+/// Long-lived handle on the host's counters, so CPU usage and throughput
+/// can be computed against the previous sample
+pub struct SystemSampler {
+    inner: Mutex<SamplerState>,
+}
+
+struct SamplerState {
+    sys: System,
+    disks: Disks,
+    networks: Networks,
+    /// The latest sample and when it was taken
+    last: Option<(Instant, SystemMetrics)>,
+}
+
+/// Samples closer together than this return the previous one: CPU usage
+/// needs time between refreshes, and rates over a few milliseconds are noise
+const MIN_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Default for SystemSampler {
+    fn default() -> Self {
+        let mut sys = System::new();
+        // The baseline the first sample's CPU usage is measured against
+        sys.refresh_cpu_usage();
+        Self {
+            inner: Mutex::new(SamplerState {
+                sys,
+                disks: Disks::new_with_refreshed_list(),
+                networks: Networks::new_with_refreshed_list(),
+                last: None,
+            }),
+        }
+    }
+}
+
+impl SystemSampler {
+    /// Current metrics. Blocking: reads `/proc` and `statvfs`s every mount.
+    pub fn sample(&self) -> SystemMetrics {
+        let mut state = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((at, ref metrics)) = state.last {
+            if at.elapsed() < MIN_SAMPLE_INTERVAL {
+                return metrics.clone();
+            }
+        }
+        let SamplerState {
+            sys,
+            disks,
+            networks,
+            last,
+        } = &mut *state;
+
+        sys.refresh_cpu_usage();
+        sys.refresh_memory();
+        disks.refresh(true);
+        networks.refresh(true);
+
+        // Bind mounts and btrfs subvolumes show up once per mount point
+        let mut seen = HashSet::new();
+        let mut metrics = SystemMetrics::default();
+        for disk in disks.list() {
+            if !seen.insert(disk.name().to_os_string()) {
+                continue;
+            }
+            metrics.disk_total += disk.total_space();
+            metrics.disk_used += disk.total_space() - disk.available_space();
+            let usage = disk.usage();
+            metrics.disk_read_bytes += usage.total_read_bytes;
+            metrics.disk_write_bytes += usage.total_written_bytes;
+        }
+        for (name, data) in networks.list() {
+            if name != "lo" {
+                metrics.net_rx_bytes += data.total_received();
+                metrics.net_tx_bytes += data.total_transmitted();
+            }
+        }
+
+        let load = System::load_average();
+        metrics.cpu_usage = sys.global_cpu_usage();
+        metrics.cpu_per_core = sys.cpus().iter().map(|c| c.cpu_usage()).collect();
+        metrics.load_1 = load.one;
+        metrics.load_5 = load.five;
+        metrics.load_15 = load.fifteen;
+        metrics.memory_used = sys.used_memory();
+        metrics.memory_total = sys.total_memory();
+
+        let now = Instant::now();
+        if let Some((at, ref prev)) = *last {
+            let secs = now.duration_since(at).as_secs_f64();
+            // A counter going down was reset, e.g. a disk was unmounted
+            let rate = |new: u64, old: u64| new.checked_sub(old).map(|d| d as f64 / secs);
+            metrics.disk_read_rate = rate(metrics.disk_read_bytes, prev.disk_read_bytes);
+            metrics.disk_write_rate = rate(metrics.disk_write_bytes, prev.disk_write_bytes);
+            metrics.net_rx_rate = rate(metrics.net_rx_bytes, prev.net_rx_bytes);
+            metrics.net_tx_rate = rate(metrics.net_tx_bytes, prev.net_tx_bytes);
+        }
+        *last = Some((now, metrics.clone()));
+        metrics
+    }
+}
+
+/// Tokens of one kind of use, e.g. of one model
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_tokens: u64,
+    pub cache_read_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn from_json(usage: &serde_json::Value) -> Self {
+        let get = |key: &str| usage.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+        Self {
+            input_tokens: get("input_tokens"),
+            output_tokens: get("output_tokens"),
+            cache_creation_tokens: get("cache_creation_input_tokens"),
+            cache_read_tokens: get("cache_read_input_tokens"),
+        }
+    }
+
+    fn add(&mut self, other: &TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_tokens += other.cache_creation_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.input_tokens + self.output_tokens + self.cache_creation_tokens + self.cache_read_tokens
     }
 }
 
@@ -53,88 +171,239 @@ pub struct ClaudeMetrics {
     pub cache_read_tokens: u64,
     pub message_count: u64,
     pub tool_use_count: u64,
+    /// Token usage by the model of the assistant messages
+    pub models: BTreeMap<String, TokenUsage>,
+    /// Estimated from `models` and the price table; `null` without one
+    pub cost_usd: Option<f64>,
+    /// Models used but missing from the price table, left out of `cost_usd`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unpriced_models: Vec<String>,
+    /// Calls and failed results by tool name
+    pub tools: BTreeMap<String, ToolStats>,
+    /// Programs run through the Bash tool, e.g. `cargo test` or `ls`, with
+    /// how often each was run
+    pub bash_commands: BTreeMap<String, u64>,
+    /// Tool calls still waiting for their result, by call ID
+    #[serde(skip)]
+    pending_tools: HashMap<String, String>,
 }
 
-/// Parse Claude metrics from JSONL session files for a given project directory.
-///
-/// Claude stores JSONL in ~/.claude/projects/<encoded-path>/<session-id>.jsonl
-/// where path encoding replaces `/` with `-`: /path/to/project → -path-to-project
-pub fn parse_claude_metrics(project_dir: &str) -> Option<ClaudeMetrics> {
-    let encoded = project_dir.replace('/', "-");
-    let home = std::env::var("HOME").ok()?;
-    let claude_dir = std::path::PathBuf::from(home)
-        .join(".claude")
-        .join("projects")
-        .join(&encoded);
-
-    if !claude_dir.exists() {
-        return None;
+/// Identity of the API response an assistant line belongs to: its message
+/// ID, with the request ID if there is one
+pub fn message_key(v: &serde_json::Value) -> Option<String> {
+    let id = v.pointer("/message/id").and_then(|i| i.as_str())?;
+    match v.get("requestId").and_then(|r| r.as_str()) {
+        Some(request) => Some(format!("{}/{}", id, request)),
+        None => Some(id.to_string()),
     }
+}
 
-    // Find the most recent JSONL file
-    let mut latest: Option<(std::time::SystemTime, std::path::PathBuf)> = None;
-    for entry in std::fs::read_dir(&claude_dir).ok()? {
-        let entry = entry.ok()?;
-        let path = entry.path();
-        if path.extension().map(|e| e == "jsonl").unwrap_or(false) {
-            if let Ok(meta) = entry.metadata() {
-                if let Ok(modified) = meta.modified() {
-                    match &latest {
-                        None => latest = Some((modified, path)),
-                        Some((prev, _)) if modified > *prev => latest = Some((modified, path)),
-                        _ => {}
-                    }
-                }
-            }
+/// Calls of one tool and how many of them failed
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ToolStats {
+    pub uses: u64,
+    /// Results marked as errors, including denials
+    pub errors: u64,
+    /// Calls refused by the user or the permission settings
+    pub denied: u64,
+}
+
+/// Tools whose first argument is a subcommand worth telling apart
+const SUBCOMMAND_PROGRAMS: &[&str] = &[
+    "cargo", "docker", "gh", "git", "go", "kubectl", "make", "nix", "npm", "pip", "pnpm",
+    "podman", "systemctl", "uv", "yarn",
+];
+
+/// Markers of a tool result that refused the call rather than failed
+const DENIAL_MARKERS: &[&str] = &[
+    "doesn't want to proceed",
+    "tool use was rejected",
+    "has been denied",
+    "haven't granted",
+];
+
+impl ClaudeMetrics {
+    /// Add another session's totals; the cost is left to `PriceTable::apply`
+    pub fn merge(&mut self, other: &ClaudeMetrics) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_tokens += other.cache_creation_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.message_count += other.message_count;
+        self.tool_use_count += other.tool_use_count;
+        for (model, usage) in &other.models {
+            self.models.entry(model.clone()).or_default().add(usage);
+        }
+        for (tool, stats) in &other.tools {
+            let t = self.tools.entry(tool.clone()).or_default();
+            t.uses += stats.uses;
+            t.errors += stats.errors;
+            t.denied += stats.denied;
+        }
+        for (command, n) in &other.bash_commands {
+            *self.bash_commands.entry(command.clone()).or_default() += n;
         }
     }
 
-    let (_, jsonl_path) = latest?;
-    let contents = std::fs::read_to_string(&jsonl_path).ok()?;
+    /// Tools by number of calls, most used first
+    pub fn tools_by_use(&self) -> Vec<(&String, &ToolStats)> {
+        let mut tools: Vec<_> = self.tools.iter().collect();
+        tools.sort_by(|a, b| b.1.uses.cmp(&a.1.uses).then_with(|| a.0.cmp(b.0)));
+        tools
+    }
 
-    let mut metrics = ClaudeMetrics::default();
-    for line in contents.lines() {
-        let v: serde_json::Value = match serde_json::from_str(line) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        let msg_type = match v.get("type").and_then(|t| t.as_str()) {
-            Some(t) => t,
-            None => continue,
-        };
+    /// The `n` most frequent Bash programs
+    pub fn top_bash_commands(&self, n: usize) -> Vec<(&String, &u64)> {
+        let mut commands: Vec<_> = self.bash_commands.iter().collect();
+        commands.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        commands.truncate(n);
+        commands
+    }
 
+    /// Estimated cost for the dashboard, `-` if unknown
+    pub fn cost_human(&self) -> String {
+        self.cost_usd.map_or("-".to_string(), crate::cost::usd)
+    }
+
+    /// Count one line of a session JSONL file. Claude writes a line per
+    /// content block of a message, each repeating the message's usage, so
+    /// `count_usage` is only set for the first line of a message
+    pub fn add_entry(&mut self, v: &serde_json::Value, count_usage: bool) {
+        let Some(msg_type) = v.get("type").and_then(|t| t.as_str()) else {
+            return;
+        };
         match msg_type {
             "assistant" => {
-                if let Some(usage) = v.pointer("/message/usage") {
-                    metrics.input_tokens +=
-                        usage.get("input_tokens").and_then(|v| v.as_u64()).unwrap_or(0);
-                    metrics.output_tokens += usage
-                        .get("output_tokens")
-                        .and_then(|v| v.as_u64())
-                        .unwrap_or(0);
-                    metrics.cache_creation_tokens += usage
-                        .get("cache_creation_input_tokens")
-                        .and_then(|v| v.as_u64())
-                        .unwrap_or(0);
-                    metrics.cache_read_tokens += usage
-                        .get("cache_read_input_tokens")
-                        .and_then(|v| v.as_u64())
-                        .unwrap_or(0);
+                if let Some(usage) = v.pointer("/message/usage").filter(|_| count_usage) {
+                    let usage = TokenUsage::from_json(usage);
+                    self.input_tokens += usage.input_tokens;
+                    self.output_tokens += usage.output_tokens;
+                    self.cache_creation_tokens += usage.cache_creation_tokens;
+                    self.cache_read_tokens += usage.cache_read_tokens;
+                    let model = v
+                        .pointer("/message/model")
+                        .and_then(|m| m.as_str())
+                        .unwrap_or("unknown");
+                    self.models.entry(model.to_string()).or_default().add(&usage);
                 }
                 if let Some(content) = v.pointer("/message/content").and_then(|c| c.as_array()) {
                     for block in content {
                         if block.get("type").and_then(|t| t.as_str()) == Some("tool_use") {
-                            metrics.tool_use_count += 1;
+                            self.tool_use_count += 1;
+                            self.add_tool_use(block);
                         }
                     }
                 }
             }
             "user" => {
-                metrics.message_count += 1;
+                self.message_count += 1;
+                if let Some(content) = v.pointer("/message/content").and_then(|c| c.as_array()) {
+                    for block in content {
+                        if block.get("type").and_then(|t| t.as_str()) == Some("tool_result") {
+                            self.add_tool_result(block);
+                        }
+                    }
+                }
             }
             _ => {}
         }
     }
 
-    Some(metrics)
+    fn add_tool_use(&mut self, block: &serde_json::Value) {
+        let name = block.get("name").and_then(|n| n.as_str()).unwrap_or("unknown");
+        self.tools.entry(name.to_string()).or_default().uses += 1;
+        if let Some(id) = block.get("id").and_then(|i| i.as_str()) {
+            self.pending_tools.insert(id.to_string(), name.to_string());
+        }
+        if name == "Bash" {
+            let command = block.pointer("/input/command").and_then(|c| c.as_str());
+            for program in command.map(bash_programs).unwrap_or_default() {
+                *self.bash_commands.entry(program).or_default() += 1;
+            }
+        }
+    }
+
+    fn add_tool_result(&mut self, block: &serde_json::Value) {
+        let Some(name) = block
+            .get("tool_use_id")
+            .and_then(|i| i.as_str())
+            .and_then(|id| self.pending_tools.remove(id))
+        else {
+            return;
+        };
+        if block.get("is_error").and_then(|e| e.as_bool()) != Some(true) {
+            return;
+        }
+        let text = match block.get("content") {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(serde_json::Value::Array(parts)) => parts
+                .iter()
+                .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        };
+        let stats = self.tools.entry(name).or_default();
+        stats.errors += 1;
+        if DENIAL_MARKERS.iter().any(|m| text.contains(m)) {
+            stats.denied += 1;
+        }
+    }
+}
+
+/// Programs a shell command line runs, one per pipeline or list element:
+/// `cd src && cargo test | tail` gives `cd`, `cargo test` and `tail`
+fn bash_programs(command: &str) -> Vec<String> {
+    // Split on unquoted `&&`, `||`, `;`, `|` and newlines
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '\\') => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+                continue;
+            }
+            // `2>&1` is a redirection, not a list
+            (None, '&') if current.ends_with(['>', '<']) => {}
+            (None, ';' | '|' | '&' | '\n') => {
+                segments.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    segments.push(current);
+
+    segments
+        .iter()
+        .filter_map(|segment| {
+            // Skip leading `VAR=value` assignments
+            let mut words = segment
+                .split_whitespace()
+                .skip_while(|w| w.contains('=') && !w.starts_with('='));
+            let program = words.next()?;
+            let program = program.rsplit('/').next().unwrap_or(program);
+            if program.is_empty() || !program.chars().next()?.is_alphanumeric() {
+                return None;
+            }
+            match words.next() {
+                Some(sub)
+                    if SUBCOMMAND_PROGRAMS.contains(&program)
+                        && sub.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+                        && !sub.starts_with('-') =>
+                {
+                    Some(format!("{} {}", program, sub))
+                }
+                _ => Some(program.to_string()),
+            }
+        })
+        .collect()
 }
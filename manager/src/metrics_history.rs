@@ -0,0 +1,505 @@
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::api;
+use crate::metrics::{ClaudeMetrics, SystemMetrics};
+use crate::resources::ResourceUsage;
+use crate::state::{Sandbox, SharedState};
+
+/// Sampling rate and retention, from `MANAGER_METRICS_HISTORY_*`
+#[derive(Debug, Clone, Copy)]
+pub struct MetricsHistoryConfig {
+    /// Time between samples; zero disables the sampler
+    pub interval: Duration,
+    /// Samples older than this are dropped
+    pub max_age: Duration,
+}
+
+impl Default for MetricsHistoryConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            max_age: Duration::from_secs(24 * 3600),
+        }
+    }
+}
+
+impl MetricsHistoryConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        let var = |name: &str| -> Option<u64> {
+            let value = std::env::var(name).ok()?;
+            value
+                .parse()
+                .map_err(|_| tracing::warn!("{} must be a whole number, got {:?}", name, value))
+                .ok()
+        };
+        if let Some(secs) = var("MANAGER_METRICS_HISTORY_INTERVAL") {
+            config.interval = Duration::from_secs(secs);
+        }
+        if let Some(hours) = var("MANAGER_METRICS_HISTORY_HOURS") {
+            config.max_age = Duration::from_secs(hours * 3600);
+        }
+        config
+    }
+
+    /// Samples kept when the sampler keeps up with its interval
+    fn capacity(&self) -> usize {
+        (self.max_age.as_secs() / self.interval.as_secs().max(1)).max(1) as usize
+    }
+}
+
+/// One sample of everything: the host and each running sandbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsSample {
+    pub timestamp: DateTime<Utc>,
+    pub system: SystemMetrics,
+    /// Running sandboxes, by ID
+    pub sandboxes: HashMap<String, SandboxSample>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SandboxSample {
+    pub cpu_percent: Option<f32>,
+    pub memory_bytes: u64,
+    pub pids: u64,
+    pub io_read_bytes: u64,
+    pub io_write_bytes: u64,
+    pub net_rx_bytes: Option<u64>,
+    pub net_tx_bytes: Option<u64>,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub tool_use_count: u64,
+    #[serde(default)]
+    pub cost_usd: Option<f64>,
+}
+
+impl SandboxSample {
+    pub fn new(resources: Option<&ResourceUsage>, claude: Option<&ClaudeMetrics>) -> Self {
+        let mut sample = Self::default();
+        if let Some(r) = resources {
+            sample.cpu_percent = r.cpu_percent;
+            sample.memory_bytes = r.memory_bytes;
+            sample.pids = r.pids;
+            sample.io_read_bytes = r.io_read_bytes;
+            sample.io_write_bytes = r.io_write_bytes;
+            sample.net_rx_bytes = r.net_rx_bytes;
+            sample.net_tx_bytes = r.net_tx_bytes;
+        }
+        if let Some(c) = claude {
+            sample.input_tokens = c.input_tokens;
+            sample.output_tokens = c.output_tokens;
+            sample.cache_creation_tokens = c.cache_creation_tokens;
+            sample.cache_read_tokens = c.cache_read_tokens;
+            sample.tool_use_count = c.tool_use_count;
+            sample.cost_usd = c.cost_usd;
+        }
+        sample
+    }
+}
+
+/// What `MetricsHistory::record` writes to the file
+enum FileWrite {
+    Append(String),
+    Rewrite(Vec<MetricsSample>),
+}
+
+/// Rolling window of samples, kept in memory and appended to a JSONL file
+/// so it survives restarts.
+///
+/// The file is rewritten from memory once it holds about twice the window,
+/// which keeps it bounded without rewriting it on every sample.
+pub struct MetricsHistory {
+    path: PathBuf,
+    config: MetricsHistoryConfig,
+    samples: RwLock<VecDeque<MetricsSample>>,
+    /// Lines in the file that have dropped out of the window
+    stale_lines: std::sync::atomic::AtomicUsize,
+}
+
+impl MetricsHistory {
+    /// Load the samples still inside the window from `path`
+    pub fn load(path: PathBuf, config: MetricsHistoryConfig) -> Self {
+        let cutoff = Utc::now() - chrono::Duration::from_std(config.max_age).unwrap_or_default();
+        let mut samples = VecDeque::new();
+        let mut lines = 0;
+        if let Ok(file) = std::fs::File::open(&path) {
+            for line in std::io::BufReader::new(file).lines().map_while(Result::ok) {
+                lines += 1;
+                if let Ok(sample) = serde_json::from_str::<MetricsSample>(&line) {
+                    if sample.timestamp >= cutoff {
+                        samples.push_back(sample);
+                    }
+                }
+            }
+        }
+        let stale = lines - samples.len();
+        Self {
+            path,
+            config,
+            samples: RwLock::new(samples),
+            stale_lines: stale.into(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.config.interval
+    }
+
+    /// Add a sample, dropping the ones that aged out of the window
+    pub async fn record(&self, sample: MetricsSample) {
+        use std::sync::atomic::Ordering;
+
+        let line = match serde_json::to_string(&sample) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let cutoff =
+            sample.timestamp - chrono::Duration::from_std(self.config.max_age).unwrap_or_default();
+        let mut samples = self.samples.write().await;
+        samples.push_back(sample);
+        let mut dropped = 0;
+        while samples.front().is_some_and(|s| s.timestamp < cutoff) {
+            samples.pop_front();
+            dropped += 1;
+        }
+        let stale = self.stale_lines.fetch_add(dropped, Ordering::Relaxed) + dropped;
+        // Write outside the lock, so queries don't wait on the disk
+        let write = if stale > self.config.capacity() {
+            self.stale_lines.store(0, Ordering::Relaxed);
+            FileWrite::Rewrite(samples.iter().cloned().collect())
+        } else {
+            FileWrite::Append(line)
+        };
+        drop(samples);
+
+        let path = self.path.clone();
+        let result = tokio::task::spawn_blocking(move || match write {
+            FileWrite::Append(line) => append(&path, &line),
+            FileWrite::Rewrite(samples) => rewrite(&path, &samples),
+        })
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+        if let Err(e) = result {
+            tracing::warn!("Failed to write {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Samples since `since`, at most one per `step`: the last one in each
+    /// step-wide bucket. Only sandbox `only` is kept, if given.
+    pub async fn query(
+        &self,
+        since: Option<DateTime<Utc>>,
+        step: Option<Duration>,
+        only: Option<&str>,
+    ) -> Vec<MetricsSample> {
+        let samples = self.samples.read().await;
+        let step = step
+            .and_then(|s| chrono::Duration::from_std(s).ok())
+            .filter(|s| *s > chrono::Duration::zero());
+        let mut out: Vec<MetricsSample> = Vec::new();
+        let mut bucket_start: Option<DateTime<Utc>> = None;
+        for sample in samples.iter() {
+            if since.is_some_and(|t| sample.timestamp < t) {
+                continue;
+            }
+            let mut sample = sample.clone();
+            if let Some(id) = only {
+                sample.sandboxes.retain(|k, _| k == id);
+            }
+            match (step, bucket_start) {
+                (Some(step), Some(start)) if sample.timestamp < start + step => {
+                    // Same bucket: keep the latest
+                    if let Some(last) = out.last_mut() {
+                        *last = sample;
+                    }
+                }
+                _ => {
+                    bucket_start = Some(sample.timestamp);
+                    out.push(sample);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn append(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Replace the file with `samples`, atomically
+fn rewrite(path: &Path, samples: &[MetricsSample]) -> std::io::Result<()> {
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+    for sample in samples {
+        serde_json::to_writer(&mut out, sample)?;
+        out.write_all(b"\n")?;
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Take a sample of the host and every running sandbox
+pub async fn sample(state: &SharedState) -> MetricsSample {
+    let running: Vec<Sandbox> = {
+        let manager = state.manager.read().await;
+        manager
+            .sandboxes
+            .values()
+            .filter(|sb| sb.is_running())
+            .cloned()
+            .collect()
+    };
+    let usage = api::sample_resources(state, running.clone()).await;
+    let s = state.clone();
+    let (system, claude) = tokio::task::spawn_blocking(move || {
+        let claude: HashMap<String, ClaudeMetrics> = running
+            .iter()
+            .filter_map(|sb| {
+                Some((sb.id.clone(), s.sessions.totals(sb)?))
+            })
+            .collect();
+        (s.system.sample(), claude)
+    })
+    .await
+    .unwrap_or_default();
+
+    let ids: HashSet<&String> = usage.keys().chain(claude.keys()).collect();
+    let sandboxes = ids
+        .into_iter()
+        .map(|id| {
+            (
+                id.clone(),
+                SandboxSample::new(usage.get(id), claude.get(id)),
+            )
+        })
+        .collect();
+    MetricsSample {
+        timestamp: Utc::now(),
+        system,
+        sandboxes,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Sparklines
+// ---------------------------------------------------------------------------
+
+/// A small line chart of one series, rendered as an SVG polyline over a
+/// 100x20 view box
+#[derive(Debug, Clone)]
+pub struct Sparkline {
+    pub label: String,
+    /// Latest value, formatted
+    pub current: String,
+    /// `points` attribute of the polyline
+    pub points: String,
+}
+
+impl Sparkline {
+    /// Scale `values` into the view box, from 0 to `max` (or their
+    /// maximum). Missing values break nothing; they're just skipped.
+    pub fn new(label: &str, current: String, values: &[Option<f64>], max: Option<f64>) -> Self {
+        let top = max
+            .unwrap_or_else(|| values.iter().flatten().fold(0.0, |a: f64, &b| a.max(b)))
+            .max(f64::EPSILON);
+        let n = values.len().max(2) - 1;
+        let points = values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| {
+                let y = 20.0 - ((*v)? / top).clamp(0.0, 1.0) * 19.0 - 0.5;
+                Some(format!("{:.1},{:.1}", i as f64 * 100.0 / n as f64, y))
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            label: label.to_string(),
+            current,
+            points,
+        }
+    }
+}
+
+/// Per-second rates of a counter between consecutive samples
+fn rates(
+    samples: &[MetricsSample],
+    counter: impl Fn(&MetricsSample) -> Option<u64>,
+) -> Vec<Option<f64>> {
+    let mut out = vec![None];
+    for pair in samples.windows(2) {
+        let secs = (pair[1].timestamp - pair[0].timestamp).num_milliseconds() as f64 / 1000.0;
+        let rate = match (counter(&pair[0]), counter(&pair[1])) {
+            // A counter going down was reset, e.g. by a reboot
+            (Some(a), Some(b)) if secs > 0.0 && b >= a => Some((b - a) as f64 / secs),
+            _ => None,
+        };
+        out.push(rate);
+    }
+    out.truncate(samples.len());
+    out
+}
+
+/// Points to draw per sparkline, whatever the range
+const SPARKLINE_POINTS: u32 = 120;
+
+/// The last `minutes` of history, thinned to about `SPARKLINE_POINTS`
+pub async fn recent(state: &SharedState, minutes: u32, only: Option<&str>) -> Vec<MetricsSample> {
+    let since = Utc::now() - chrono::Duration::minutes(minutes as i64);
+    let step = Duration::from_secs(minutes as u64 * 60 / SPARKLINE_POINTS as u64);
+    let step = (step > state.metrics_history.interval()).then_some(step);
+    state.metrics_history.query(Some(since), step, only).await
+}
+
+pub fn system_sparklines(samples: &[MetricsSample]) -> Vec<Sparkline> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let last = samples.last().map(|s| &s.system);
+    let cpu: Vec<Option<f64>> = samples
+        .iter()
+        .map(|s| Some(s.system.cpu_usage as f64))
+        .collect();
+    let mem: Vec<Option<f64>> = samples
+        .iter()
+        .map(|s| Some(s.system.memory_used as f64))
+        .collect();
+    let load: Vec<Option<f64>> = samples.iter().map(|s| Some(s.system.load_1)).collect();
+    let rx = rates(samples, |s| Some(s.system.net_rx_bytes));
+    let tx = rates(samples, |s| Some(s.system.net_tx_bytes));
+    // Samples from before disk IO was recorded have it as zero
+    let disk = rates(samples, |s| {
+        Some(s.system.disk_read_bytes + s.system.disk_write_bytes).filter(|n| *n > 0)
+    });
+    let rate = |v: &[Option<f64>]| match v.last().copied().flatten() {
+        Some(r) => format!("{}/s", crate::resources::human_bytes(r as u64)),
+        None => "-".to_string(),
+    };
+    vec![
+        Sparkline::new(
+            "CPU",
+            last.map_or("-".into(), |m| format!("{:.1}%", m.cpu_usage)),
+            &cpu,
+            Some(100.0),
+        ),
+        Sparkline::new(
+            "Load",
+            last.map_or("-".into(), |m| format!("{:.2}", m.load_1)),
+            &load,
+            None,
+        ),
+        Sparkline::new(
+            "Memory",
+            last.map_or("-".into(), |m| crate::resources::human_bytes(m.memory_used)),
+            &mem,
+            last.map(|m| m.memory_total as f64),
+        ),
+        Sparkline::new("Disk IO", rate(&disk), &disk, None),
+        Sparkline::new("Net RX", rate(&rx), &rx, None),
+        Sparkline::new("Net TX", rate(&tx), &tx, None),
+    ]
+}
+
+pub fn sandbox_sparklines(samples: &[MetricsSample], id: &str) -> Vec<Sparkline> {
+    let series: Vec<Option<&SandboxSample>> = samples.iter().map(|s| s.sandboxes.get(id)).collect();
+    if series.iter().all(Option::is_none) {
+        return Vec::new();
+    }
+    let last = series.last().copied().flatten();
+    let cpu: Vec<Option<f64>> = series
+        .iter()
+        .map(|s| s.and_then(|s| s.cpu_percent).map(f64::from))
+        .collect();
+    let mem: Vec<Option<f64>> = series
+        .iter()
+        .map(|s| s.map(|s| s.memory_bytes as f64))
+        .collect();
+    let tokens: Vec<Option<f64>> = series
+        .iter()
+        .map(|s| s.map(|s| (s.input_tokens + s.output_tokens) as f64))
+        .collect();
+    let tools: Vec<Option<f64>> = series
+        .iter()
+        .map(|s| s.map(|s| s.tool_use_count as f64))
+        .collect();
+    vec![
+        Sparkline::new(
+            "CPU",
+            last.and_then(|s| s.cpu_percent)
+                .map_or("-".into(), |p| format!("{:.0}%", p)),
+            &cpu,
+            None,
+        ),
+        Sparkline::new(
+            "Memory",
+            last.map_or("-".into(), |s| {
+                crate::resources::human_bytes(s.memory_bytes)
+            }),
+            &mem,
+            None,
+        ),
+        Sparkline::new(
+            "Tokens",
+            last.map_or("-".into(), |s| {
+                (s.input_tokens + s.output_tokens).to_string()
+            }),
+            &tokens,
+            None,
+        ),
+        Sparkline::new(
+            "Tool uses",
+            last.map_or("-".into(), |s| s.tool_use_count.to_string()),
+            &tools,
+            None,
+        ),
+    ]
+}
+
+// ---------------------------------------------------------------------------
+// Handlers
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub since: Option<DateTime<Utc>>,
+    /// Seconds between returned samples
+    pub step: Option<u64>,
+    /// Only include this sandbox
+    pub sandbox: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HistoryResponse {
+    /// Seconds between recorded samples
+    interval: u64,
+    samples: Vec<MetricsSample>,
+}
+
+pub async fn get_history(
+    State(state): State<SharedState>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    let samples = state
+        .metrics_history
+        .query(
+            query.since,
+            query.step.map(Duration::from_secs),
+            query.sandbox.as_deref(),
+        )
+        .await;
+    Json(HistoryResponse {
+        interval: state.metrics_history.interval().as_secs(),
+        samples,
+    })
+}
@@ -0,0 +1,190 @@
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::attention::Attention;
+use crate::state::Sandbox;
+
+/// How long to wait on notify-send or the SMTP server
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Where to send word of a sandbox that needs someone, all optional.
+/// Webhooks are subscriptions to `sandbox.attention` (see `webhooks`).
+#[derive(Debug, Clone, Default)]
+pub struct NotifyConfig {
+    /// Show a desktop notification with `notify-send`
+    pub desktop: bool,
+    /// Recipients of an email sent through `smtp`
+    pub email: Vec<String>,
+    pub smtp: String,
+    pub from: String,
+}
+
+impl NotifyConfig {
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let mut config = Self {
+            desktop: false,
+            email: Vec::new(),
+            smtp: var("MANAGER_SMTP").unwrap_or_else(|| "127.0.0.1:25".to_string()),
+            from: var("MANAGER_NOTIFY_FROM").unwrap_or_else(|| "claude-manager@localhost".to_string()),
+        };
+        if let Some(desktop) = var("MANAGER_NOTIFY_DESKTOP") {
+            match desktop.as_str() {
+                "1" | "true" => config.desktop = true,
+                "0" | "false" => {}
+                other => tracing::warn!("MANAGER_NOTIFY_DESKTOP must be 0 or 1, got {:?}", other),
+            }
+        }
+        if let Some(email) = var("MANAGER_NOTIFY_EMAIL") {
+            config.email = email
+                .split(',')
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect();
+        }
+        config
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.desktop && self.email.is_empty()
+    }
+}
+
+/// Sends notifications over every configured channel
+pub struct Notifier {
+    config: NotifyConfig,
+}
+
+impl Notifier {
+    pub fn new(config: NotifyConfig) -> Self {
+        Self { config }
+    }
+
+    /// Whether no channel is configured
+    pub fn is_empty(&self) -> bool {
+        self.config.is_empty()
+    }
+
+    /// Tell everyone configured that `sandbox` needs attention. Failures are
+    /// logged; one channel failing doesn't hold up the others.
+    pub async fn attention(&self, sandbox: &Sandbox, attention: &Attention) {
+        let title = format!("Sandbox {} needs you", sandbox.name);
+        let body = format!(
+            "{} since {}: {}",
+            attention.reason.description(),
+            attention.since.format("%H:%M:%S UTC"),
+            attention.detail
+        );
+
+        let desktop = async {
+            if !self.config.desktop {
+                return;
+            }
+            let status = tokio::process::Command::new("notify-send")
+                .args(["--app-name=claude-manager", "--urgency=critical", &title, &body])
+                .kill_on_drop(true)
+                .status();
+            match tokio::time::timeout(TIMEOUT, status).await {
+                Ok(Ok(s)) if s.success() => {}
+                Ok(Ok(s)) => tracing::warn!("notify-send exited with {}", s),
+                Ok(Err(e)) => tracing::warn!("Failed to run notify-send: {}", e),
+                Err(_) => tracing::warn!("notify-send timed out"),
+            }
+        };
+
+        let email = async {
+            if self.config.email.is_empty() {
+                return;
+            }
+            let sent = send_mail(&self.config.smtp, &self.config.from, &self.config.email, &title, &body);
+            match tokio::time::timeout(TIMEOUT, sent).await {
+                Ok(Ok(())) => tracing::info!("Mailed attention notice for sandbox {}", sandbox.short_id()),
+                Ok(Err(e)) => tracing::warn!("Failed to mail {}: {}", self.config.smtp, e),
+                Err(_) => tracing::warn!("Mailing through {} timed out", self.config.smtp),
+            }
+        };
+
+        tokio::join!(desktop, email);
+    }
+}
+
+/// Deliver a plain text mail through an SMTP server that takes mail without
+/// authentication, such as a local MTA
+async fn send_mail(server: &str, from: &str, to: &[String], subject: &str, body: &str) -> Result<(), String> {
+    let stream = TcpStream::connect(server).await.map_err(|e| e.to_string())?;
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+
+    expect_reply(&mut read, 220).await?;
+    let mut command = async |line: String, code: u16| -> Result<(), String> {
+        write
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        expect_reply(&mut read, code).await
+    };
+    command("EHLO localhost".to_string(), 250).await?;
+    command(format!("MAIL FROM:<{}>", from), 250).await?;
+    for rcpt in to {
+        command(format!("RCPT TO:<{}>", rcpt), 250).await?;
+    }
+    command("DATA".to_string(), 354).await?;
+
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to.join(", "),
+        encode_header(subject),
+        chrono::Utc::now().to_rfc2822()
+    );
+    for line in body.lines() {
+        // Dot-stuffing, so a line of "." doesn't end the message early
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push('.');
+    command(message, 250).await?;
+    command("QUIT".to_string(), 221).await?;
+    Ok(())
+}
+
+/// RFC 2047 encoded-word for header text that isn't plain ASCII, e.g. a
+/// sandbox name with accents
+fn encode_header(text: &str) -> String {
+    if text.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        return text.to_string();
+    }
+    let mut encoded = String::from("=?utf-8?Q?");
+    for b in text.bytes() {
+        match b {
+            b' ' => encoded.push('_'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("={:02X}", b)),
+        }
+    }
+    encoded.push_str("?=");
+    encoded
+}
+
+/// Read a (possibly multi-line) SMTP reply and check its code. 251 (user
+/// not local, will forward) counts as 250.
+async fn expect_reply<R: AsyncBufReadExt + Unpin>(read: &mut R, code: u16) -> Result<(), String> {
+    loop {
+        let mut line = String::new();
+        if read.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 {
+            return Err("Connection closed by the SMTP server".to_string());
+        }
+        let got: u16 = line.get(..3).and_then(|c| c.parse().ok()).ok_or_else(|| format!("Bad SMTP reply {:?}", line))?;
+        // `250-` continues, `250 ` ends the reply
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if got == code || (code == 250 && got == 251) {
+            return Ok(());
+        }
+        return Err(format!("SMTP server replied {}", line.trim_end()));
+    }
+}
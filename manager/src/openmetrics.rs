@@ -0,0 +1,393 @@
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics::ClaudeMetrics;
+use crate::resources::ResourceUsage;
+use crate::state::{Backend, Sandbox, SandboxStatus, SharedState};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Upper bounds, in seconds, of the latency histograms' buckets
+const HTTP_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const CAPTURE_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        if let Some(i) = self.bounds.iter().position(|b| secs <= *b) {
+            self.counts[i] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, n) in self.bounds.iter().zip(&self.counts) {
+            cumulative += n;
+            // `{:?}` keeps the `.0` of whole numbers, as OpenMetrics wants
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{:?}\"}} {}",
+                name, labels, sep, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, sep, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// Counters the manager keeps for the `/metrics` endpoint: request and
+/// capture latencies, which aren't recorded anywhere else
+pub struct Telemetry {
+    started: chrono::DateTime<chrono::Utc>,
+    /// By method, route and status code
+    http: Mutex<HashMap<(String, String, u16), Histogram>>,
+    /// By sandbox ID
+    captures: Mutex<HashMap<String, Histogram>>,
+    capture_failures: Mutex<HashMap<String, u64>>,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            started: chrono::Utc::now(),
+            http: Mutex::default(),
+            captures: Mutex::default(),
+            capture_failures: Mutex::default(),
+        }
+    }
+}
+
+impl Telemetry {
+    /// Record how long a screenshot capture took, or that it failed
+    pub fn record_capture(&self, id: &str, elapsed: Duration, ok: bool) {
+        if ok {
+            let mut captures = self.captures.lock().unwrap_or_else(|e| e.into_inner());
+            captures
+                .entry(id.to_string())
+                .or_insert_with(|| Histogram::new(CAPTURE_BUCKETS))
+                .observe(elapsed);
+        } else {
+            let mut failures = self
+                .capture_failures
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            *failures.entry(id.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Forget a deleted sandbox
+    pub fn remove(&self, id: &str) {
+        self.captures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+        self.capture_failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+    }
+
+    fn record_http(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let mut http = self.http.lock().unwrap_or_else(|e| e.into_inner());
+        http.entry((method.to_string(), route.to_string(), status))
+            .or_insert_with(|| Histogram::new(HTTP_BUCKETS))
+            .observe(elapsed);
+    }
+}
+
+/// Middleware timing every routed request, labelled by route pattern
+/// (`/api/sandboxes/:id`) rather than path, to keep the label set small
+pub async fn track_http(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    state
+        .telemetry
+        .record_http(&method, &route, response.status().as_u16(), start.elapsed());
+    response
+}
+
+/// `sandbox_id`, `sandbox_name` and `backend` labels of a sandbox
+fn sandbox_labels(sb: &Sandbox) -> String {
+    format!(
+        "sandbox_id=\"{}\",sandbox_name=\"{}\",backend=\"{}\"",
+        escape(&sb.id),
+        escape(&sb.name),
+        sb.backend
+    )
+}
+
+/// Escape a label value: backslash, double quote and newline
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// OpenMetrics text being built, one metric family at a time
+#[derive(Default)]
+struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    /// Start a family. A unit must also end its name, e.g. `_bytes`.
+    fn family(&mut self, name: &str, kind: &str, unit: Option<&str>, help: &str) {
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        if let Some(unit) = unit {
+            let _ = writeln!(self.out, "# UNIT {} {}", name, unit);
+        }
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+    }
+
+    fn sample(&mut self, name: &str, labels: &str, value: impl std::fmt::Display) {
+        if labels.is_empty() {
+            let _ = writeln!(self.out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(self.out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+
+    /// A family with a single unlabelled sample
+    fn single(&mut self, name: &str, kind: &str, unit: Option<&str>, help: &str, value: impl std::fmt::Display) {
+        self.family(name, kind, unit, help);
+        let sample = if kind == "counter" { format!("{}_total", name) } else { name.to_string() };
+        self.sample(&sample, "", value);
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push_str("# EOF\n");
+        self.out
+    }
+}
+
+pub async fn get_metrics(State(state): State<SharedState>) -> Response {
+    let mut sandboxes: Vec<Sandbox> = {
+        let manager = state.manager.read().await;
+        manager.sandboxes.values().cloned().collect()
+    };
+    sandboxes.sort_by_key(|sb| sb.created_at);
+
+    let s = state.clone();
+    let list = sandboxes.clone();
+    let (system, claude, usage) = tokio::task::spawn_blocking(move || {
+        let claude: Vec<Option<ClaudeMetrics>> = list
+            .iter()
+            .map(|sb| s.sessions.totals(sb))
+            .collect();
+        let usage = s.resources.sample(&list);
+        (s.system.sample(), claude, usage)
+    })
+    .await
+    .unwrap_or_default();
+
+    let mut m = Exposition::default();
+    m.single(
+        "claude_manager_start_time_seconds",
+        "gauge",
+        Some("seconds"),
+        "When the manager process started",
+        state.telemetry.started.timestamp(),
+    );
+
+    // Host
+    m.single("claude_manager_system_cpu_usage_percent", "gauge", None, "Host CPU usage", system.cpu_usage);
+    m.family("claude_manager_system_cpu_core_usage_percent", "gauge", None, "CPU usage per core");
+    for (i, usage) in system.cpu_per_core.iter().enumerate() {
+        m.sample("claude_manager_system_cpu_core_usage_percent", &format!("core=\"{}\"", i), usage);
+    }
+    m.family("claude_manager_system_load_average", "gauge", None, "Load average");
+    for (window, load) in [("1m", system.load_1), ("5m", system.load_5), ("15m", system.load_15)] {
+        m.sample("claude_manager_system_load_average", &format!("window=\"{}\"", window), load);
+    }
+    m.single("claude_manager_system_memory_used_bytes", "gauge", Some("bytes"), "Host memory in use", system.memory_used);
+    m.single("claude_manager_system_memory_total_bytes", "gauge", Some("bytes"), "Host memory installed", system.memory_total);
+    m.single("claude_manager_system_disk_used_bytes", "gauge", Some("bytes"), "Disk space in use", system.disk_used);
+    m.single("claude_manager_system_disk_total_bytes", "gauge", Some("bytes"), "Disk space in total", system.disk_total);
+    m.single(
+        "claude_manager_system_network_receive_bytes",
+        "counter",
+        Some("bytes"),
+        "Bytes received on all interfaces but loopback since boot",
+        system.net_rx_bytes,
+    );
+    m.single(
+        "claude_manager_system_network_transmit_bytes",
+        "counter",
+        Some("bytes"),
+        "Bytes sent on all interfaces but loopback since boot",
+        system.net_tx_bytes,
+    );
+    m.single(
+        "claude_manager_system_disk_read_bytes",
+        "counter",
+        Some("bytes"),
+        "Bytes read from mounted devices since boot",
+        system.disk_read_bytes,
+    );
+    m.single(
+        "claude_manager_system_disk_written_bytes",
+        "counter",
+        Some("bytes"),
+        "Bytes written to mounted devices since boot",
+        system.disk_write_bytes,
+    );
+
+    // Sandbox counts, including zeros so every series always exists
+    m.family("claude_manager_sandboxes", "gauge", None, "Sandboxes by status and backend");
+    for status in [SandboxStatus::Running, SandboxStatus::Stopped, SandboxStatus::Dead] {
+        for backend in [Backend::Bubblewrap, Backend::Container, Backend::Vm] {
+            let n = sandboxes
+                .iter()
+                .filter(|sb| sb.status == status && sb.backend == backend)
+                .count();
+            let labels = format!("status=\"{}\",backend=\"{}\"", status, backend);
+            m.sample("claude_manager_sandboxes", &labels, n);
+        }
+    }
+
+    // Claude usage per sandbox
+    let with_claude: Vec<(String, &ClaudeMetrics)> = sandboxes
+        .iter()
+        .zip(&claude)
+        .filter_map(|(sb, c)| Some((sandbox_labels(sb), c.as_ref()?)))
+        .collect();
+    m.family("claude_manager_sandbox_tokens", "counter", None, "Tokens used in the sandbox's Claude sessions");
+    for (labels, c) in &with_claude {
+        for (kind, n) in [
+            ("input", c.input_tokens),
+            ("output", c.output_tokens),
+            ("cache_creation", c.cache_creation_tokens),
+            ("cache_read", c.cache_read_tokens),
+        ] {
+            let labels = format!("{},type=\"{}\"", labels, kind);
+            m.sample("claude_manager_sandbox_tokens_total", &labels, n);
+        }
+    }
+    m.family("claude_manager_sandbox_messages", "counter", None, "User messages in the sandbox's Claude sessions");
+    for (labels, c) in &with_claude {
+        m.sample("claude_manager_sandbox_messages_total", labels, c.message_count);
+    }
+    m.family("claude_manager_sandbox_tool_uses", "counter", None, "Tool calls in the sandbox's Claude sessions");
+    for (labels, c) in &with_claude {
+        m.sample("claude_manager_sandbox_tool_uses_total", labels, c.tool_use_count);
+    }
+    m.family("claude_manager_sandbox_cost_usd", "gauge", None, "Estimated cost of the sandbox's Claude sessions");
+    for (labels, c) in &with_claude {
+        if let Some(cost) = c.cost_usd {
+            m.sample("claude_manager_sandbox_cost_usd", labels, cost);
+        }
+    }
+
+    // Resource usage of running sandboxes
+    let running: Vec<(String, &ResourceUsage)> = sandboxes
+        .iter()
+        .filter_map(|sb| Some((sandbox_labels(sb), usage.get(&sb.id)?)))
+        .collect();
+    // A gauge, not a counter: processes outside the sandbox's own cgroups are
+    // summed from /proc, and their time drops out of the sum once they exit
+    m.family("claude_manager_sandbox_cpu_seconds", "gauge", Some("seconds"), "CPU time used by the sandbox's live processes and cgroups");
+    for (labels, u) in &running {
+        m.sample("claude_manager_sandbox_cpu_seconds", labels, u.cpu_seconds);
+    }
+    m.family("claude_manager_sandbox_memory_bytes", "gauge", Some("bytes"), "Memory used by the sandbox");
+    for (labels, u) in &running {
+        m.sample("claude_manager_sandbox_memory_bytes", labels, u.memory_bytes);
+    }
+    m.family("claude_manager_sandbox_pids", "gauge", None, "Processes in the sandbox");
+    for (labels, u) in &running {
+        m.sample("claude_manager_sandbox_pids", labels, u.pids);
+    }
+
+    // Screenshot captures of sandboxes that still exist
+    let labels_of: HashMap<&str, String> = sandboxes
+        .iter()
+        .map(|sb| (sb.id.as_str(), sandbox_labels(sb)))
+        .collect();
+    m.family(
+        "claude_manager_screenshot_capture_seconds",
+        "histogram",
+        Some("seconds"),
+        "Time to capture a sandbox's screen",
+    );
+    {
+        let captures = state.telemetry.captures.lock().unwrap_or_else(|e| e.into_inner());
+        for (id, hist) in captures.iter() {
+            if let Some(labels) = labels_of.get(id.as_str()) {
+                hist.write(&mut m.out, "claude_manager_screenshot_capture_seconds", labels);
+            }
+        }
+    }
+    m.family("claude_manager_screenshot_capture_failures", "counter", None, "Screen captures that failed");
+    {
+        let failures = state.telemetry.capture_failures.lock().unwrap_or_else(|e| e.into_inner());
+        for (id, n) in failures.iter() {
+            if let Some(labels) = labels_of.get(id.as_str()) {
+                m.sample("claude_manager_screenshot_capture_failures_total", labels, n);
+            }
+        }
+    }
+
+    // HTTP
+    m.family(
+        "claude_manager_http_request_duration_seconds",
+        "histogram",
+        Some("seconds"),
+        "Time to answer HTTP requests, by route",
+    );
+    {
+        let http = state.telemetry.http.lock().unwrap_or_else(|e| e.into_inner());
+        let mut keys: Vec<_> = http.keys().collect();
+        keys.sort();
+        for key in keys {
+            let (method, route, status) = key;
+            let labels = format!("method=\"{}\",route=\"{}\",status=\"{}\"", method, escape(route), status);
+            http[key].write(&mut m.out, "claude_manager_http_request_duration_seconds", &labels);
+        }
+    }
+
+    ([("content-type", CONTENT_TYPE)], m.finish()).into_response()
+}
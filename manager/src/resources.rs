@@ -0,0 +1,390 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::scope;
+use crate::session;
+use crate::state::Sandbox;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Resource usage of one sandbox: its systemd scope and everything running
+/// under its tmux pane.
+///
+/// The scope, and any other cgroup of its own a process lives in (an
+/// nspawn container's machine scope), is counted as a whole, which also
+/// covers processes that already exited. Processes still in tmux's cgroup,
+/// shared by all sandboxes, are summed one by one from `/proc`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResourceUsage {
+    /// CPU time used so far, user plus system
+    pub cpu_seconds: f64,
+    /// Average over the last few seconds, from the background sampler; 100
+    /// is one core
+    pub cpu_percent: Option<f32>,
+    pub memory_bytes: u64,
+    pub pids: u64,
+    pub io_read_bytes: u64,
+    pub io_write_bytes: u64,
+    /// Only known when the sandbox has its own network namespace; with the
+    /// host's network, its traffic can't be told apart from anyone else's
+    pub net_rx_bytes: Option<u64>,
+    pub net_tx_bytes: Option<u64>,
+    /// Cgroups counted as a whole, relative to the cgroup root
+    pub cgroups: Vec<String>,
+}
+
+impl ResourceUsage {
+    pub fn memory_human(&self) -> String {
+        human_bytes(self.memory_bytes)
+    }
+
+    pub fn io_human(&self) -> String {
+        format!(
+            "{} / {}",
+            human_bytes(self.io_read_bytes),
+            human_bytes(self.io_write_bytes)
+        )
+    }
+
+    pub fn net_human(&self) -> Option<String> {
+        Some(format!(
+            "{} / {}",
+            human_bytes(self.net_rx_bytes?),
+            human_bytes(self.net_tx_bytes?)
+        ))
+    }
+
+    pub fn cpu_human(&self) -> String {
+        match self.cpu_percent {
+            Some(pct) => format!("{:.0}%", pct),
+            None => format!("{:.1}s", self.cpu_seconds),
+        }
+    }
+}
+
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Samples sandbox resource usage. CPU percentages come from `update_cpu`,
+/// run on a fixed interval in the background, so how often anyone else asks
+/// for usage doesn't change the window they are averaged over.
+#[derive(Default)]
+pub struct ResourceMonitor {
+    cpu: std::sync::Mutex<HashMap<String, CpuRate>>,
+}
+
+struct CpuRate {
+    /// CPU time at the last update and when it was read
+    at: Instant,
+    seconds: f64,
+    /// Average between the last two updates
+    percent: Option<f32>,
+}
+
+impl ResourceMonitor {
+    /// Usage of each running sandbox with a tmux session, by sandbox ID.
+    /// Blocking: walks `/proc` once for all of them.
+    pub fn sample(&self, sandboxes: &[Sandbox]) -> HashMap<String, ResourceUsage> {
+        let mut usage = measure(sandboxes);
+        let cpu = self.cpu.lock().unwrap_or_else(|e| e.into_inner());
+        for (id, u) in usage.iter_mut() {
+            u.cpu_percent = cpu.get(id).and_then(|rate| rate.percent);
+        }
+        usage
+    }
+
+    /// Measure CPU time against the previous update. Blocking, like
+    /// `sample`; the only place CPU percentages are computed.
+    pub fn update_cpu(&self, sandboxes: &[Sandbox]) {
+        let usage = measure(sandboxes);
+        let now = Instant::now();
+        let mut cpu = self.cpu.lock().unwrap_or_else(|e| e.into_inner());
+        // Stopped sandboxes start over if they ever run again
+        cpu.retain(|id, _| usage.contains_key(id));
+        for (id, u) in usage {
+            let percent = cpu.get(&id).and_then(|prev| {
+                let elapsed = now.duration_since(prev.at).as_secs_f64();
+                let used = (u.cpu_seconds - prev.seconds).max(0.0);
+                (elapsed > 0.0).then_some((used / elapsed * 100.0) as f32)
+            });
+            cpu.insert(
+                id,
+                CpuRate {
+                    at: now,
+                    seconds: u.cpu_seconds,
+                    percent,
+                },
+            );
+        }
+    }
+}
+
+fn measure(sandboxes: &[Sandbox]) -> HashMap<String, ResourceUsage> {
+    let panes = session::pane_pids().unwrap_or_default();
+    let procs = ProcTable::read();
+    let mut usage = HashMap::new();
+    for sandbox in sandboxes.iter().filter(|sb| sb.is_running()) {
+        let roots = sandbox
+            .tmux_session
+            .as_ref()
+            .and_then(|s| panes.get(s))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let unit = sandbox.scope.as_deref().and_then(scope::control_group);
+        if roots.is_empty() && unit.is_none() {
+            continue;
+        }
+        usage.insert(sandbox.id.clone(), procs.usage(roots, unit));
+    }
+    usage
+}
+
+struct Proc {
+    ppid: u32,
+    /// utime + stime + cutime + cstime, in clock ticks; the child times
+    /// cover descendants that exited and were waited for
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+/// Snapshot of every process's parent and counters
+struct ProcTable {
+    procs: HashMap<u32, Proc>,
+    children: HashMap<u32, Vec<u32>>,
+}
+
+impl ProcTable {
+    fn read() -> Self {
+        let mut procs = HashMap::new();
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        let Ok(entries) = std::fs::read_dir("/proc") else {
+            return Self { procs, children };
+        };
+        for entry in entries.flatten() {
+            let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+                continue;
+            };
+            let Some(proc) = read_proc(pid) else {
+                continue;
+            };
+            children.entry(proc.ppid).or_default().push(pid);
+            procs.insert(pid, proc);
+        }
+        Self { procs, children }
+    }
+
+    /// The given pane processes and all their descendants
+    fn tree(&self, roots: &[u32]) -> Vec<u32> {
+        let mut tree = Vec::new();
+        let mut stack: Vec<u32> = roots.to_vec();
+        while let Some(pid) = stack.pop() {
+            if !self.procs.contains_key(&pid) {
+                continue;
+            }
+            tree.push(pid);
+            if let Some(kids) = self.children.get(&pid) {
+                stack.extend(kids);
+            }
+        }
+        tree
+    }
+
+    /// Usage of the tree under `roots`, counting the sandbox's scope
+    /// `unit` as a whole if it has one
+    fn usage(&self, roots: &[u32], unit: Option<String>) -> ResourceUsage {
+        let tree = self.tree(roots);
+        // The tmux server's cgroup, which every sandbox's pane starts in
+        let shared = roots
+            .first()
+            .and_then(|pid| self.procs.get(pid))
+            .and_then(|p| cgroup_of(p.ppid));
+
+        let mut own_cgroups: Vec<String> =
+            unit.into_iter().filter(|cg| cgroup_readable(cg)).collect();
+        let mut loose = Vec::new();
+        for &pid in &tree {
+            match cgroup_of(pid) {
+                // Never a cgroup containing tmux's own, e.g. the root one
+                Some(cg)
+                    if !shared.as_ref().is_some_and(|s| Path::new(s).starts_with(&cg))
+                        && cgroup_readable(&cg) =>
+                {
+                    if !own_cgroups.contains(&cg) {
+                        own_cgroups.push(cg);
+                    }
+                }
+                _ => loose.push(pid),
+            }
+        }
+        // A cgroup nested in another one we count is already included
+        let nested: Vec<String> = own_cgroups
+            .iter()
+            .filter(|cg| {
+                own_cgroups
+                    .iter()
+                    .any(|other| other != *cg && Path::new(cg).starts_with(other))
+            })
+            .cloned()
+            .collect();
+        own_cgroups.retain(|cg| !nested.contains(cg));
+
+        let mut usage = ResourceUsage::default();
+        for cg in &own_cgroups {
+            add_cgroup(&mut usage, cg);
+        }
+        let ticks = clock_ticks();
+        let page = page_size();
+        for pid in loose {
+            let proc = &self.procs[&pid];
+            usage.cpu_seconds += proc.cpu_ticks as f64 / ticks;
+            usage.memory_bytes += proc.rss_pages * page;
+            usage.pids += 1;
+            if let Some((read, write)) = read_proc_io(pid) {
+                usage.io_read_bytes += read;
+                usage.io_write_bytes += write;
+            }
+        }
+        if let Some((rx, tx)) = net_usage(&tree) {
+            usage.net_rx_bytes = Some(rx);
+            usage.net_tx_bytes = Some(tx);
+        }
+        usage.cgroups = own_cgroups;
+        usage
+    }
+}
+
+fn read_proc(pid: u32) -> Option<Proc> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // Fields from 3 on; the command name before them may contain spaces
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let num = |i: usize| -> u64 { fields.get(i).and_then(|f| f.parse().ok()).unwrap_or(0) };
+    Some(Proc {
+        ppid: num(1) as u32,
+        cpu_ticks: num(11) + num(12) + num(13) + num(14),
+        rss_pages: num(21),
+    })
+}
+
+/// Bytes actually read from and written to storage
+fn read_proc_io(pid: u32) -> Option<(u64, u64)> {
+    let io = std::fs::read_to_string(format!("/proc/{}/io", pid)).ok()?;
+    let field = |name: &str| {
+        io.lines()
+            .find_map(|l| l.strip_prefix(name))
+            .and_then(|v| v.trim().parse().ok())
+    };
+    Some((field("read_bytes:")?, field("write_bytes:")?))
+}
+
+/// The process's cgroup v2 path, e.g. `/machine.slice/machine-foo.scope`
+fn cgroup_of(pid: u32) -> Option<String> {
+    let cgroup = std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    cgroup
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .map(str::to_string)
+}
+
+fn cgroup_dir(cgroup: &str) -> PathBuf {
+    Path::new(CGROUP_ROOT).join(cgroup.trim_start_matches('/'))
+}
+
+fn cgroup_readable(cgroup: &str) -> bool {
+    cgroup_dir(cgroup).join("cpu.stat").exists()
+}
+
+fn add_cgroup(usage: &mut ResourceUsage, cgroup: &str) {
+    let dir = cgroup_dir(cgroup);
+    let read = |file: &str| std::fs::read_to_string(dir.join(file)).unwrap_or_default();
+    let single = |file: &str| read(file).trim().parse::<u64>().unwrap_or(0);
+
+    if let Some(usec) = read("cpu.stat")
+        .lines()
+        .find_map(|l| l.strip_prefix("usage_usec "))
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        usage.cpu_seconds += usec as f64 / 1e6;
+    }
+    usage.memory_bytes += single("memory.current");
+    usage.pids += single("pids.current");
+    // One line per device: `8:0 rbytes=1 wbytes=2 rios=3 ...`
+    for line in read("io.stat").lines() {
+        for kv in line.split_whitespace().skip(1) {
+            match kv.split_once('=') {
+                Some(("rbytes", v)) => usage.io_read_bytes += v.parse::<u64>().unwrap_or(0),
+                Some(("wbytes", v)) => usage.io_write_bytes += v.parse::<u64>().unwrap_or(0),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Traffic of the network namespaces in the tree other than our own,
+/// summed over their non-loopback interfaces
+fn net_usage(tree: &[u32]) -> Option<(u64, u64)> {
+    let ours = std::fs::read_link("/proc/self/ns/net").ok()?;
+    let mut seen = HashSet::new();
+    let mut total: Option<(u64, u64)> = None;
+    for &pid in tree {
+        let Ok(ns) = std::fs::read_link(format!("/proc/{}/ns/net", pid)) else {
+            continue;
+        };
+        if ns == ours || !seen.insert(ns) {
+            continue;
+        }
+        let Ok(dev) = std::fs::read_to_string(format!("/proc/{}/net/dev", pid)) else {
+            continue;
+        };
+        let (rx, tx) = total.get_or_insert((0, 0));
+        // Two header lines, then `iface: rx_bytes ... (8 rx fields) tx_bytes ...`
+        for line in dev.lines().skip(2) {
+            let Some((iface, counters)) = line.split_once(':') else {
+                continue;
+            };
+            if iface.trim() == "lo" {
+                continue;
+            }
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .map(|c| c.parse().unwrap_or(0))
+                .collect();
+            *rx += counters.first().copied().unwrap_or(0);
+            *tx += counters.get(8).copied().unwrap_or(0);
+        }
+    }
+    total
+}
+
+fn clock_ticks() -> f64 {
+    // SAFETY: sysconf has no memory-safety preconditions
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 {
+        ticks as f64
+    } else {
+        100.0
+    }
+}
+
+fn page_size() -> u64 {
+    // SAFETY: sysconf has no memory-safety preconditions
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 {
+        size as u64
+    } else {
+        4096
+    }
+}
@@ -1,49 +1,166 @@
 use crate::display;
+use crate::scope::{self, Limits};
 use crate::session;
 use crate::state::{AppState, Backend, CreateSandboxRequest, Sandbox, SandboxStatus};
+use crate::webhooks::WebhookEvent;
 use chrono::Utc;
 use uuid::Uuid;
 
+/// Why a sandbox couldn't be created
+#[derive(Debug)]
+pub enum CreateError {
+    /// The request asks for something that can't be had, e.g. a malformed
+    /// limit; nothing was started
+    Invalid(String),
+    /// Starting the sandbox failed
+    Failed(String),
+}
+
+impl std::fmt::Display for CreateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CreateError::Invalid(e) | CreateError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<String> for CreateError {
+    fn from(e: String) -> Self {
+        CreateError::Failed(e)
+    }
+}
+
 pub async fn create_sandbox(
     state: &AppState,
     req: CreateSandboxRequest,
-) -> Result<Sandbox, String> {
+) -> Result<Sandbox, CreateError> {
     let id = Uuid::new_v4().to_string();
     let short_id = id[..8].to_string();
     let tmux_session = format!("sandbox-{}", short_id);
-
-    // Allocate display number (VM has its own Xorg, skip Xvfb)
-    let display_num = match req.backend {
+    let limits = Limits {
+        memory_max: req.memory_max.clone(),
+        cpu_quota: req.cpu_quota.clone(),
+        tasks_max: req.tasks_max,
+        io_weight: req.io_weight,
+    };
+    let limits = if state.scopes.enabled {
+        limits.resolve(&state.scopes.defaults).map_err(CreateError::Invalid)?
+    } else if !limits.is_empty() {
+        return Err(CreateError::Invalid("Resource limits need systemd scopes, which are disabled".to_string()));
+    } else {
+        limits
+    };
+    let budget_usd = req.budget_usd.or(state.default_budget);
+    if budget_usd.is_some() && state.sessions.prices().is_empty() {
+        return Err(CreateError::Invalid("A budget needs a price table (MANAGER_PRICES)".to_string()));
+    }
+    let claude_dir = match req.claude_dir {
+        Some(ref dir) if !std::path::Path::new(dir).is_absolute() => {
+            return Err(CreateError::Invalid(format!(
+                "Claude data directory must be an absolute path, got {:?}",
+                dir
+            )));
+        }
+        Some(ref dir) => dir.clone(),
+        None => state.sessions.default_claude_dir().to_string_lossy().into_owned(),
+    };
+    let geometry = match req.backend {
         Backend::Vm => None,
-        _ => {
-            let mut manager = state.manager.write().await;
-            let num = display::allocate_display(manager.next_display);
-            manager.next_display = num + 1;
-            Some(num)
+        _ => Some(
+            display::Geometry::parse(req.resolution.as_deref(), req.depth).map_err(CreateError::Invalid)?,
+        ),
+    };
+
+    // Start Xvfb (VM has its own Xorg), accepting only clients holding
+    // its cookie, on the first free display from 50 up
+    let (display_num, pid_xvfb, xvfb_start_time, xauthority) = match geometry {
+        Some(geometry) => {
+            let xauthority = state.runtime_dir.join(format!("xauth-{}", short_id));
+            let log = state.log_dir.join(format!("{}.xvfb.log", id));
+            let xvfb = display::start_xvfb(geometry, &xauthority, &log)
+                .await
+                .map_err(|e| {
+                    // The error already carries the end of Xvfb's output
+                    let _ = std::fs::remove_file(&xauthority);
+                    let _ = std::fs::remove_file(&log);
+                    format!("Failed to start Xvfb: {}", e)
+                })?;
+            state.xvfb.insert(&id, xvfb.child);
+            (
+                Some(xvfb.display_num),
+                Some(xvfb.pid),
+                xvfb.start_time,
+                Some(xauthority.to_string_lossy().into_owned()),
+            )
         }
+        None => (None, None, None, None),
     };
 
-    // Start Xvfb outside of lock
-    let pid_xvfb = match display_num {
-        Some(num) => {
-            let pid =
-                display::start_xvfb(num).map_err(|e| format!("Failed to start Xvfb: {}", e))?;
-            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-            Some(pid)
+    // QEMU listens on these itself; the VM launcher picks them up from env
+    let (qemu_qmp_socket, vnc_socket) = if req.backend == Backend::Vm {
+        let sock = |kind: &str| {
+            state
+                .runtime_dir
+                .join(format!("{}-{}.sock", kind, short_id))
+                .to_string_lossy()
+                .into_owned()
+        };
+        if let Err(e) = std::fs::create_dir_all(&state.runtime_dir) {
+            tracing::warn!("Failed to create {}: {}", state.runtime_dir.display(), e);
         }
-        None => None,
+        (Some(sock("qmp")), Some(sock("vnc")))
+    } else {
+        (None, None)
     };
 
     // Build the backend command
     let backend_cmd = match req.backend {
         Backend::Bubblewrap => format!("claude-sandbox {}", req.project_dir),
-        Backend::Container => format!("sudo claude-sandbox-container {}", req.project_dir),
-        Backend::Vm => format!("claude-sandbox-vm {}", req.project_dir),
+        // nspawn moves the container into a machine scope of its own, out
+        // of ours, so it applies the limits to that one as well
+        Backend::Container => {
+            let props: String = limits
+                .properties()
+                .iter()
+                .map(|p| format!("--property {} ", p))
+                .collect();
+            format!("sudo claude-sandbox-container {}{}", props, req.project_dir)
+        }
+        Backend::Vm => format!(
+            "CLAUDE_SANDBOX_QMP_SOCKET={} CLAUDE_SANDBOX_VNC_SOCKET={} claude-sandbox-vm {}",
+            qemu_qmp_socket.as_deref().unwrap_or_default(),
+            vnc_socket.as_deref().unwrap_or_default(),
+            req.project_dir
+        ),
+    };
+
+    // Run the backend in a scope of its own, so it can be limited,
+    // accounted for and stopped as a unit
+    let unit = state.scopes.enabled.then(|| scope::unit_name(&short_id));
+    let command = match unit {
+        Some(ref unit) => scope::wrap_command(unit, &limits, &backend_cmd),
+        None => backend_cmd,
     };
 
     // Create tmux session
-    session::create_session(&tmux_session, display_num, &backend_cmd, &req.project_dir)
-        .map_err(|e| format!("Failed to create tmux session: {}", e))?;
+    let created = session::create_session(
+        &tmux_session,
+        display_num,
+        xauthority.as_deref(),
+        &command,
+        &req.project_dir,
+    )
+    .map_err(|e| format!("Failed to create tmux session: {}", e));
+    if let Err(e) = created {
+        // Don't leave the display behind for a sandbox that never started
+        if let Some(pid) = pid_xvfb {
+            state.xvfb.stop(&id, pid, xvfb_start_time).await;
+        }
+        if let Some(ref path) = xauthority {
+            let _ = std::fs::remove_file(path);
+        }
+        return Err(e.into());
+    }
 
     // Start capturing tmux output to log file
     let log_path = state.log_dir.join(format!("{}.log", id));
@@ -51,12 +168,6 @@ pub async fn create_sandbox(
         tracing::warn!("Failed to start log capture for {}: {}", short_id, e);
     }
 
-    let qemu_qmp_socket = if req.backend == Backend::Vm {
-        Some(format!("/run/claude-manager/qmp-{}.sock", short_id))
-    } else {
-        None
-    };
-
     let sandbox = Sandbox {
         id: id.clone(),
         name: req.name,
@@ -64,21 +175,47 @@ pub async fn create_sandbox(
         project_dir: req.project_dir,
         status: SandboxStatus::Running,
         display_num,
+        geometry,
+        xauthority,
         tmux_session: Some(tmux_session),
         pid_xvfb,
+        xvfb_start_time,
         qemu_qmp_socket,
+        vnc_socket,
         network: req.network,
+        scope: unit,
+        limits,
+        budget_usd,
+        claude_dir: Some(claude_dir),
+        attention: None,
         created_at: Utc::now(),
     };
 
     let mut manager = state.manager.write().await;
     manager.sandboxes.insert(id, sandbox.clone());
     let _ = manager.save(&state.state_path);
+    drop(manager);
 
+    state.webhooks.emit(WebhookEvent::Created, &sandbox, serde_json::json!({}));
+    state.webhooks.emit(WebhookEvent::Started, &sandbox, serde_json::json!({}));
     Ok(sandbox)
 }
 
 pub async fn stop_sandbox(state: &AppState, id: &str) -> Result<(), String> {
+    // Stop the scope first, so the backend gets to shut down cleanly before
+    // its terminal goes away
+    let unit = {
+        let manager = state.manager.read().await;
+        let sandbox = manager
+            .sandboxes
+            .get(id)
+            .ok_or_else(|| "Sandbox not found".to_string())?;
+        sandbox.scope.clone().filter(|_| sandbox.is_running())
+    };
+    if let Some(ref unit) = unit {
+        scope::stop(unit).await;
+    }
+
     let mut manager = state.manager.write().await;
     let sandbox = manager
         .sandboxes
@@ -88,15 +225,27 @@ pub async fn stop_sandbox(state: &AppState, id: &str) -> Result<(), String> {
     if let Some(ref session) = sandbox.tmux_session {
         session::kill_session(session);
     }
-    if let Some(pid) = sandbox.pid_xvfb {
-        display::stop_xvfb(pid);
+    let xvfb = sandbox.pid_xvfb.take().map(|pid| (pid, sandbox.xvfb_start_time.take()));
+    if let Some(ref xauthority) = sandbox.xauthority {
+        let _ = std::fs::remove_file(xauthority);
     }
 
+    // A dead sandbox is cleaned up too, but it didn't stop now
+    let was_running = sandbox.is_running();
     sandbox.status = SandboxStatus::Stopped;
+    let stopped = sandbox.clone();
     let _ = manager.save(&state.state_path);
     drop(manager);
+    if was_running {
+        state.webhooks.emit(WebhookEvent::Stopped, &stopped, serde_json::json!({}));
+    }
+
+    if let Some((pid, start_time)) = xvfb {
+        state.xvfb.stop(id, pid, start_time).await;
+    }
 
-    state.screenshots.write().await.remove(id);
+    state.screenshots.remove(id).await;
+    state.vnc.stop(id).await;
     Ok(())
 }
 
@@ -113,12 +262,18 @@ pub async fn delete_sandbox(state: &AppState, id: &str) -> Result<(), String> {
     }
 
     let mut manager = state.manager.write().await;
-    manager.sandboxes.remove(id);
+    if let Some(sb) = manager.sandboxes.remove(id) {
+        state.sessions.forget(&sb);
+        state.webhooks.emit(WebhookEvent::Deleted, &sb, serde_json::json!({}));
+    }
     let _ = manager.save(&state.state_path);
 
     // Clean up log file
     let log_path = state.log_dir.join(format!("{}.log", id));
     let _ = std::fs::remove_file(&log_path);
+    let _ = std::fs::remove_file(state.log_dir.join(format!("{}.xvfb.log", id)));
+    state.history.remove(id).await;
+    state.telemetry.remove(id);
 
     Ok(())
 }
@@ -0,0 +1,376 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::process::Command;
+
+/// Resource limits of a sandbox's systemd scope. Unset fields are left to
+/// systemd, i.e. unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// `MemoryMax=`: bytes with an optional K/M/G/T suffix, a percentage of
+    /// physical memory, or `infinity`
+    #[serde(default)]
+    pub memory_max: Option<String>,
+    /// `CPUQuota=`: CPU time relative to one core, e.g. `200%` for two
+    #[serde(default)]
+    pub cpu_quota: Option<String>,
+    /// `TasksMax=`: processes and threads
+    #[serde(default)]
+    pub tasks_max: Option<u32>,
+    /// `IOWeight=`: 1 to 10000, 100 being the default
+    #[serde(default)]
+    pub io_weight: Option<u16>,
+}
+
+impl Limits {
+    /// Validate and normalise, filling unset fields from `defaults`
+    pub fn resolve(self, defaults: &Limits) -> Result<Limits, String> {
+        let memory_max = match self.memory_max.or_else(|| defaults.memory_max.clone()) {
+            Some(v) => Some(parse_memory(&v)?),
+            None => None,
+        };
+        let cpu_quota = match self.cpu_quota.or_else(|| defaults.cpu_quota.clone()) {
+            Some(v) => Some(parse_cpu_quota(&v)?),
+            None => None,
+        };
+        let tasks_max = self.tasks_max.or(defaults.tasks_max);
+        if tasks_max == Some(0) {
+            return Err("Tasks limit must be at least 1".to_string());
+        }
+        let io_weight = self.io_weight.or(defaults.io_weight);
+        if io_weight.is_some_and(|w| !(1..=10000).contains(&w)) {
+            return Err("IO weight must be between 1 and 10000".to_string());
+        }
+        Ok(Limits {
+            memory_max,
+            cpu_quota,
+            tasks_max,
+            io_weight,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// Unit properties, e.g. `MemoryMax=4G`
+    pub fn properties(&self) -> Vec<String> {
+        let mut props = Vec::new();
+        if let Some(ref v) = self.memory_max {
+            props.push(format!("MemoryMax={}", v));
+        }
+        if let Some(ref v) = self.cpu_quota {
+            props.push(format!("CPUQuota={}", v));
+        }
+        if let Some(v) = self.tasks_max {
+            props.push(format!("TasksMax={}", v));
+        }
+        if let Some(v) = self.io_weight {
+            props.push(format!("IOWeight={}", v));
+        }
+        props
+    }
+
+    /// Comma-separated summary for the dashboard, e.g. `memory 4G, CPU 200%`
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(ref v) = self.memory_max {
+            parts.push(format!("memory {}", v));
+        }
+        if let Some(ref v) = self.cpu_quota {
+            parts.push(format!("CPU {}", v));
+        }
+        if let Some(v) = self.tasks_max {
+            parts.push(format!("{} tasks", v));
+        }
+        if let Some(v) = self.io_weight {
+            parts.push(format!("IO weight {}", v));
+        }
+        if parts.is_empty() {
+            "none".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+fn parse_memory(value: &str) -> Result<String, String> {
+    let v = value.trim();
+    let err = || {
+        format!(
+            "Memory limit must be bytes with an optional K/M/G/T suffix, a percentage or \"infinity\", got {:?}",
+            value
+        )
+    };
+    if v == "infinity" {
+        return Ok(v.to_string());
+    }
+    if let Some(pct) = v.strip_suffix('%') {
+        return match pct.parse::<f64>() {
+            Ok(p) if p > 0.0 && p <= 100.0 => Ok(v.to_string()),
+            _ => Err(err()),
+        };
+    }
+    let digits = v.trim_end_matches(['K', 'M', 'G', 'T', 'k', 'm', 'g', 't']);
+    if v.len() - digits.len() > 1 || digits.parse::<u64>().map_or(true, |n| n == 0) {
+        return Err(err());
+    }
+    Ok(v.to_uppercase())
+}
+
+/// `200%` or a bare `200`
+fn parse_cpu_quota(value: &str) -> Result<String, String> {
+    let v = value.trim();
+    match v.strip_suffix('%').unwrap_or(v).parse::<u32>() {
+        Ok(pct) if pct > 0 => Ok(format!("{}%", pct)),
+        _ => Err(format!(
+            "CPU quota must be a percentage such as 200%, got {:?}",
+            value
+        )),
+    }
+}
+
+/// Accept a number, a numeric string, or an empty string (an empty form
+/// field) for an optional numeric limit
+pub fn optional_number<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr + TryFrom<u64>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Number(u64),
+        Text(String),
+    }
+    let invalid = || serde::de::Error::custom("expected a whole number");
+    match Option::<Raw>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Raw::Number(n)) => T::try_from(n).map(Some).map_err(|_| invalid()),
+        Some(Raw::Text(s)) if s.trim().is_empty() => Ok(None),
+        Some(Raw::Text(s)) => s.trim().parse().map(Some).map_err(|_| invalid()),
+    }
+}
+
+/// Treat an empty form field as unset
+pub fn optional_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(deserializer)?.filter(|s| !s.trim().is_empty()))
+}
+
+/// Whether sandboxes get scopes, and the limits they get by default
+#[derive(Debug, Clone)]
+pub struct ScopeConfig {
+    pub enabled: bool,
+    pub defaults: Limits,
+}
+
+impl ScopeConfig {
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let number = |name: &str| -> Option<u64> {
+            let value = var(name)?;
+            value
+                .parse()
+                .map_err(|_| tracing::warn!("{} must be a whole number, got {:?}", name, value))
+                .ok()
+        };
+        let config = Self {
+            enabled: var("MANAGER_SCOPES").is_none_or(|v| v != "0"),
+            defaults: Limits {
+                memory_max: var("MANAGER_SANDBOX_MEMORY_MAX"),
+                cpu_quota: var("MANAGER_SANDBOX_CPU_QUOTA"),
+                tasks_max: number("MANAGER_SANDBOX_TASKS_MAX").and_then(|n| {
+                    u32::try_from(n)
+                        .map_err(|_| tracing::warn!("MANAGER_SANDBOX_TASKS_MAX is too large: {}", n))
+                        .ok()
+                }),
+                io_weight: number("MANAGER_SANDBOX_IO_WEIGHT").and_then(|n| {
+                    u16::try_from(n)
+                        .map_err(|_| tracing::warn!("MANAGER_SANDBOX_IO_WEIGHT must be between 1 and 10000, got {}", n))
+                        .ok()
+                }),
+            },
+        };
+        // Catch a typo now rather than on every create
+        if let Err(e) = config.defaults.clone().resolve(&Limits::default()) {
+            tracing::warn!("Invalid default sandbox limits: {}", e);
+        }
+        if !config.enabled && !config.defaults.is_empty() {
+            tracing::warn!("Default sandbox limits are ignored with MANAGER_SCOPES=0");
+        }
+        config
+    }
+}
+
+/// Unit name of a sandbox's scope
+pub fn unit_name(short_id: &str) -> String {
+    format!("claude-sandbox-{}.scope", short_id)
+}
+
+/// Shell command running `command` in a new transient scope of the
+/// manager user's systemd instance, with `limits` applied
+pub fn wrap_command(unit: &str, limits: &Limits, command: &str) -> String {
+    let mut args = vec![
+        format!("XDG_RUNTIME_DIR={}", shell_quote(&runtime_dir())),
+        "systemd-run".to_string(),
+        "--user".to_string(),
+        "--scope".to_string(),
+        "--quiet".to_string(),
+        "--collect".to_string(),
+        format!("--unit={}", unit),
+        // Don't wait the default 90s for a backend ignoring SIGTERM
+        "-p".to_string(),
+        "TimeoutStopSec=10".to_string(),
+    ];
+    for prop in limits.properties() {
+        args.push("-p".to_string());
+        args.push(prop);
+    }
+    args.extend(["--".to_string(), "sh".to_string(), "-c".to_string()]);
+    args.push(shell_quote(command));
+    args.join(" ")
+}
+
+/// Stop the scope, terminating everything in it
+pub async fn stop(unit: &str) {
+    let result = tokio::process::Command::new("systemctl")
+        .args(["--user", "stop", unit])
+        .env("XDG_RUNTIME_DIR", runtime_dir())
+        .output()
+        .await;
+    match result {
+        Ok(out) if !out.status.success() => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            // Already gone: the backend exited and the scope was collected
+            if !stderr.contains("not loaded") {
+                tracing::warn!("Failed to stop {}: {}", unit, stderr.trim());
+            }
+        }
+        Err(e) => tracing::warn!("Failed to run systemctl: {}", e),
+        Ok(_) => {}
+    }
+}
+
+/// Ask systemd to stop the scope without waiting for it
+pub fn stop_no_block(unit: &str) {
+    let _ = Command::new("systemctl")
+        .args(["--user", "stop", "--no-block", unit])
+        .env("XDG_RUNTIME_DIR", runtime_dir())
+        .output();
+}
+
+/// The scope's cgroup relative to the cgroup root, if it is running
+pub fn control_group(unit: &str) -> Option<String> {
+    let out = Command::new("systemctl")
+        .args(["--user", "show", "-p", "ControlGroup", "--value", unit])
+        .env("XDG_RUNTIME_DIR", runtime_dir())
+        .output()
+        .ok()?;
+    let cgroup = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    (out.status.success() && !cgroup.is_empty()).then_some(cgroup)
+}
+
+/// Whether the user's systemd instance is reachable, checked at startup
+pub fn available() -> Result<(), String> {
+    let out = Command::new("systemctl")
+        .args(["--user", "show", "-p", "Version"])
+        .env("XDG_RUNTIME_DIR", runtime_dir())
+        .output()
+        .map_err(|e| format!("Failed to run systemctl: {}", e))?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&out.stderr).trim().to_string())
+    }
+}
+
+/// Where the user's systemd instance listens; a system service doesn't get
+/// `XDG_RUNTIME_DIR` from a login session
+fn runtime_dir() -> String {
+    std::env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| {
+        // SAFETY: getuid has no preconditions and cannot fail
+        format!("/run/user/{}", unsafe { libc::getuid() })
+    })
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(memory_max: &str, cpu_quota: &str) -> Limits {
+        Limits {
+            memory_max: Some(memory_max.to_string()),
+            cpu_quota: Some(cpu_quota.to_string()),
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn resolve_normalises_limits() {
+        let resolved = limits(" 4g ", "200").resolve(&Limits::default()).unwrap();
+        assert_eq!(resolved.memory_max.as_deref(), Some("4G"));
+        assert_eq!(resolved.cpu_quota.as_deref(), Some("200%"));
+        for memory in ["infinity", "50%", "1073741824", "512M"] {
+            assert!(limits(memory, "100%").resolve(&Limits::default()).is_ok(), "{}", memory);
+        }
+    }
+
+    #[test]
+    fn resolve_rejects_invalid_limits() {
+        for memory in ["", "0", "4GB", "-1G", "0%", "150%", "lots"] {
+            assert!(limits(memory, "100%").resolve(&Limits::default()).is_err(), "{}", memory);
+        }
+        for quota in ["0", "0%", "-50%", "1.5", "half"] {
+            assert!(limits("1G", quota).resolve(&Limits::default()).is_err(), "{}", quota);
+        }
+        let tasks = Limits { tasks_max: Some(0), ..Limits::default() };
+        assert!(tasks.resolve(&Limits::default()).is_err());
+        for weight in [0, 10001] {
+            let io = Limits { io_weight: Some(weight), ..Limits::default() };
+            assert!(io.resolve(&Limits::default()).is_err(), "{}", weight);
+        }
+    }
+
+    #[test]
+    fn resolve_fills_unset_limits_from_defaults() {
+        let defaults = Limits {
+            memory_max: Some("8G".to_string()),
+            cpu_quota: Some("400%".to_string()),
+            tasks_max: Some(512),
+            io_weight: Some(50),
+        };
+        let own = Limits {
+            memory_max: Some("2G".to_string()),
+            tasks_max: Some(64),
+            ..Limits::default()
+        };
+        let resolved = own.resolve(&defaults).unwrap();
+        assert_eq!(resolved.memory_max.as_deref(), Some("2G"));
+        assert_eq!(resolved.cpu_quota.as_deref(), Some("400%"));
+        assert_eq!(resolved.tasks_max, Some(64));
+        assert_eq!(resolved.io_weight, Some(50));
+        assert!(Limits::default().resolve(&Limits::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn wraps_command_in_a_scope_with_limits() {
+        let limits = Limits {
+            memory_max: Some("2G".to_string()),
+            tasks_max: Some(64),
+            ..Limits::default()
+        };
+        let command = wrap_command("claude-sandbox-1a2b3c4d.scope", &limits, "echo 'hi'");
+        let expected = concat!(
+            " systemd-run --user --scope --quiet --collect --unit=claude-sandbox-1a2b3c4d.scope",
+            " -p TimeoutStopSec=10 -p MemoryMax=2G -p TasksMax=64",
+            r#" -- sh -c 'echo '\''hi'\'''"#,
+        );
+        assert!(command.starts_with("XDG_RUNTIME_DIR='/"), "{}", command);
+        assert!(command.ends_with(expected), "{}", command);
+    }
+}
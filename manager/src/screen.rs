@@ -0,0 +1,154 @@
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Path, Query, State, WebSocketUpgrade};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::ansi;
+use crate::session;
+use crate::state::SharedState;
+
+/// How often the live view re-captures the pane (milliseconds)
+const POLL_INTERVAL_MS: u64 = 250;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreenFormat {
+    #[default]
+    Text,
+    Ansi,
+    Html,
+    Json,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScreenQuery {
+    #[serde(default)]
+    pub format: ScreenFormat,
+}
+
+#[derive(Debug, Serialize)]
+struct ScreenSnapshot {
+    text: Vec<String>,
+    html: Vec<String>,
+}
+
+/// A diff pushed over the live-view WebSocket: the pane's height plus the
+/// HTML of every line that changed since the previous push
+#[derive(Debug, Serialize)]
+struct ScreenUpdate<'a> {
+    rows: usize,
+    lines: Vec<(usize, &'a str)>,
+}
+
+async fn running_session(state: &SharedState, id: &str) -> Result<String, Response> {
+    let manager = state.manager.read().await;
+    match manager.sandboxes.get(id) {
+        Some(sb) if sb.is_running() => sb
+            .tmux_session
+            .clone()
+            .ok_or_else(|| (StatusCode::CONFLICT, "Sandbox has no tmux session").into_response()),
+        Some(_) => Err((StatusCode::CONFLICT, "Sandbox is not running").into_response()),
+        None => Err((StatusCode::NOT_FOUND, "Sandbox not found").into_response()),
+    }
+}
+
+pub async fn get_screen(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Query(query): Query<ScreenQuery>,
+) -> Response {
+    let tmux_session = match running_session(&state, &id).await {
+        Ok(s) => s,
+        Err(resp) => return resp,
+    };
+
+    let escapes = !matches!(query.format, ScreenFormat::Text);
+    let captured = match capture(&tmux_session, escapes).await {
+        Ok(c) => c,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to capture pane: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    match query.format {
+        ScreenFormat::Text | ScreenFormat::Ansi => {
+            (StatusCode::OK, [("content-type", "text/plain; charset=utf-8")], captured)
+                .into_response()
+        }
+        ScreenFormat::Html => {
+            let html = format!(
+                "<pre class=\"screen\">{}</pre>",
+                ansi::to_html_lines(&captured).join("\n")
+            );
+            (StatusCode::OK, [("content-type", "text/html; charset=utf-8")], html).into_response()
+        }
+        ScreenFormat::Json => Json(ScreenSnapshot {
+            text: ansi::strip(&captured),
+            html: ansi::to_html_lines(&captured),
+        })
+        .into_response(),
+    }
+}
+
+/// Capture the visible screen off the async runtime, tmux being a blocking
+/// command
+async fn capture(tmux_session: &str, escapes: bool) -> std::io::Result<String> {
+    let name = tmux_session.to_string();
+    tokio::task::spawn_blocking(move || session::capture_screen(&name, escapes))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+pub async fn ws_screen(
+    ws: WebSocketUpgrade,
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Response {
+    match running_session(&state, &id).await {
+        Ok(tmux_session) => ws.on_upgrade(move |socket| handle_socket(socket, tmux_session)),
+        Err(resp) => resp,
+    }
+}
+
+async fn handle_socket(mut socket: WebSocket, tmux_session: String) {
+    let mut previous: Vec<String> = Vec::new();
+    let mut poll = tokio::time::interval(tokio::time::Duration::from_millis(POLL_INTERVAL_MS));
+    loop {
+        tokio::select! {
+            _ = poll.tick() => {
+                let captured = match capture(&tmux_session, true).await {
+                    Ok(c) => c,
+                    Err(_) => {
+                        let _ = socket.send(Message::Text("[Session ended]".into())).await;
+                        return;
+                    }
+                };
+                let current = ansi::to_html_lines(&captured);
+                let lines: Vec<(usize, &str)> = current
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, line)| previous.get(*i) != Some(*line))
+                    .map(|(i, line)| (i, line.as_str()))
+                    .collect();
+                if lines.is_empty() && current.len() == previous.len() {
+                    continue;
+                }
+                let update = ScreenUpdate { rows: current.len(), lines };
+                let json = serde_json::to_string(&update).unwrap_or_default();
+                if socket.send(Message::Text(json)).await.is_err() {
+                    return; // Client disconnected
+                }
+                previous = current;
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => return,
+                _ => {} // The live view is read-only; ignore input
+            },
+        }
+    }
+}
@@ -1,22 +1,323 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::process::Command;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{ConnectionExt, ImageFormat, ImageOrder};
+use x11rb::rust_connection::{DefaultStream, RustConnection};
 
-/// Capture screenshot from an Xvfb display using ImageMagick's `import`
-pub fn capture_xvfb(display_num: u32) -> Option<Vec<u8>> {
-    let display = format!(":{}", display_num);
-    let output = Command::new("import")
-        .args(["-window", "root", "-display", &display, "png:-"])
-        .output()
-        .ok()?;
-    if output.status.success() && !output.stdout.is_empty() {
-        Some(output.stdout)
-    } else {
+use crate::display;
+use crate::state::Sandbox;
+
+/// Keep capturing a sandbox for this long after its screenshot was last asked for
+const WATCH_WINDOW: Duration = Duration::from_secs(10);
+
+/// Image format served from the screenshot endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EncodeFormat {
+    Png,
+    /// Lossless WebP: smaller than PNG but slower to encode
+    Webp,
+}
+
+impl EncodeFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            EncodeFormat::Png => "image/png",
+            EncodeFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            EncodeFormat::Png => "png",
+            EncodeFormat::Webp => "webp",
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "png" => Some(EncodeFormat::Png),
+            "webp" => Some(EncodeFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// How screenshots are scaled and encoded, from `MANAGER_SCREENSHOT_*`
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenshotConfig {
+    pub format: EncodeFormat,
+    /// Output size relative to the screen, in `(0, 1]`
+    pub scale: f32,
+    /// PNG compression effort. Encoding is lossless either way, so this
+    /// only trades CPU time for size.
+    pub quality: png::Compression,
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> Self {
+        Self {
+            format: EncodeFormat::Png,
+            scale: 1.0,
+            quality: png::Compression::Fast,
+        }
+    }
+}
+
+impl ScreenshotConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(format) = std::env::var("MANAGER_SCREENSHOT_FORMAT") {
+            match format.as_str() {
+                "png" => config.format = EncodeFormat::Png,
+                "webp" => config.format = EncodeFormat::Webp,
+                other => tracing::warn!("Unknown MANAGER_SCREENSHOT_FORMAT {:?}, using png", other),
+            }
+        }
+        if let Ok(scale) = std::env::var("MANAGER_SCREENSHOT_SCALE") {
+            match scale.parse::<f32>() {
+                Ok(s) if s > 0.0 && s <= 1.0 => config.scale = s,
+                _ => tracing::warn!("MANAGER_SCREENSHOT_SCALE must be in (0, 1], got {:?}", scale),
+            }
+        }
+        if let Ok(quality) = std::env::var("MANAGER_SCREENSHOT_QUALITY") {
+            match quality.as_str() {
+                "fast" => config.quality = png::Compression::Fast,
+                "default" => config.quality = png::Compression::Default,
+                "best" => config.quality = png::Compression::Best,
+                other => tracing::warn!("Unknown MANAGER_SCREENSHOT_QUALITY {:?}, using fast", other),
+            }
+            // The WebP encoder is lossless only and has no effort setting
+            if config.format == EncodeFormat::Webp {
+                tracing::warn!("MANAGER_SCREENSHOT_QUALITY only applies to PNG and is ignored with webp");
+            }
+        }
+        config
+    }
+}
+
+/// A captured screen as packed 8-bit RGB
+pub struct RawFrame {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+/// Latest encoded frame for one sandbox
+#[derive(Clone)]
+pub struct Frame {
+    pub data: Vec<u8>,
+    pub format: EncodeFormat,
+    /// Quoted hash of the raw pixels, usable as an HTTP ETag
+    pub etag: String,
+}
+
+/// Latest frame per sandbox, plus which sandboxes someone is looking at.
+///
+/// Capturing is demand-driven: the capture loop only visits sandboxes whose
+/// screenshot was requested within `WATCH_WINDOW`, and a frame is only
+/// encoded when its raw pixels hash differently from the previous one.
+#[derive(Default)]
+pub struct ScreenshotStore {
+    config: ScreenshotConfig,
+    frames: RwLock<HashMap<String, Frame>>,
+    watched: Mutex<HashMap<String, Instant>>,
+    /// One X11 connection per Xvfb display, reused across captures
+    x11: std::sync::Mutex<HashMap<u32, RustConnection>>,
+}
+
+impl ScreenshotStore {
+    pub fn new(config: ScreenshotConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Note that a client wants this sandbox's screenshot
+    pub async fn touch(&self, id: &str) {
+        self.watched.lock().await.insert(id.to_string(), Instant::now());
+    }
+
+    /// IDs requested within the watch window; forgets the rest
+    pub async fn watched(&self) -> Vec<String> {
+        let mut watched = self.watched.lock().await;
+        watched.retain(|_, at| at.elapsed() < WATCH_WINDOW);
+        watched.keys().cloned().collect()
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Frame> {
+        self.frames.read().await.get(id).cloned()
+    }
+
+    pub async fn etag(&self, id: &str) -> Option<String> {
+        self.frames.read().await.get(id).map(|f| f.etag.clone())
+    }
+
+    /// Capture a sandbox's screen from Xvfb or QEMU. Blocking.
+    pub fn capture(&self, sandbox: &Sandbox) -> Option<RawFrame> {
+        if let Some(num) = sandbox.display_num {
+            self.capture_xvfb(num, sandbox.xauthority.as_deref())
+        } else if let Some(ref sock) = sandbox.qemu_qmp_socket {
+            capture_qmp(sock)
+        } else {
+            None
+        }
+    }
+
+    /// Grab the root window with `GetImage` over a cached connection,
+    /// reconnecting once if the display went away (e.g. Xvfb restarted)
+    fn capture_xvfb(&self, display_num: u32, xauthority: Option<&str>) -> Option<RawFrame> {
+        let mut connections = self.x11.lock().unwrap_or_else(|e| e.into_inner());
+        for _ in 0..2 {
+            let conn = match connections.entry(display_num) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let conn = connect_x11(display_num, xauthority)
+                        .map_err(|e| tracing::debug!("X11 connect to :{} failed: {}", display_num, e))
+                        .ok()?;
+                    e.insert(conn)
+                }
+            };
+            match get_root_image(conn) {
+                Ok(frame) => return Some(frame),
+                Err(e) => {
+                    tracing::debug!("X11 capture of :{} failed: {}", display_num, e);
+                    connections.remove(&display_num);
+                }
+            }
+        }
         None
     }
+
+    /// Store a raw capture, encoding it only if the screen changed
+    pub async fn update(&self, id: &str, raw: RawFrame) {
+        let etag = frame_etag(&raw);
+        if self.etag(id).await.as_deref() == Some(etag.as_str()) {
+            return;
+        }
+        let config = self.config;
+        let encoded = tokio::task::spawn_blocking(move || encode(raw, &config))
+            .await
+            .ok()
+            .flatten();
+        if let Some(data) = encoded {
+            let frame = Frame {
+                data,
+                format: config.format,
+                etag,
+            };
+            self.frames.write().await.insert(id.to_string(), frame);
+        }
+    }
+
+    pub async fn remove(&self, id: &str) {
+        self.frames.write().await.remove(id);
+        self.watched.lock().await.remove(id);
+    }
+}
+
+fn frame_etag(raw: &RawFrame) -> String {
+    let mut hasher = DefaultHasher::new();
+    (raw.width, raw.height).hash(&mut hasher);
+    raw.rgb.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Connect to a local display, authenticating with the cookie from its
+/// Xauthority file if it has one
+fn connect_x11(display_num: u32, xauthority: Option<&str>) -> Result<RustConnection, String> {
+    let socket = format!("/tmp/.X11-unix/X{}", display_num);
+    let stream = std::os::unix::net::UnixStream::connect(&socket)
+        .map_err(|e| format!("{}: {}", socket, e))?;
+    let (stream, _) = DefaultStream::from_unix_stream(stream).map_err(|e| e.to_string())?;
+    let (name, cookie) = match xauthority.and_then(|p| display::read_cookie(p.as_ref())) {
+        Some(cookie) => (display::COOKIE_PROTOCOL.to_vec(), cookie),
+        None => (Vec::new(), Vec::new()),
+    };
+    RustConnection::connect_to_stream_with_auth_info(stream, 0, name, cookie)
+        .map_err(|e| e.to_string())
 }
 
-/// Capture screenshot from a QEMU VM via QMP socket
-pub fn capture_qmp(socket_path: &str) -> Option<Vec<u8>> {
-    let tmp_ppm = format!("/tmp/qmp-screenshot-{}.ppm", std::process::id());
+fn get_root_image(conn: &RustConnection) -> Result<RawFrame, String> {
+    let setup = conn.setup();
+    let screen = setup.roots.first().ok_or("X server has no screens")?;
+    let (width, height) = (screen.width_in_pixels, screen.height_in_pixels);
+    let reply = conn
+        .get_image(ImageFormat::Z_PIXMAP, screen.root, 0, 0, width, height, !0)
+        .map_err(|e| e.to_string())?
+        .reply()
+        .map_err(|e| e.to_string())?;
+
+    let format = setup
+        .pixmap_formats
+        .iter()
+        .find(|f| f.depth == reply.depth)
+        .ok_or("No pixmap format for the root depth")?;
+    let visual = screen
+        .allowed_depths
+        .iter()
+        .flat_map(|d| &d.visuals)
+        .find(|v| v.visual_id == reply.visual)
+        .ok_or("Root visual not found")?;
+
+    let bytes_pp = format.bits_per_pixel as usize / 8;
+    if !matches!(bytes_pp, 2..=4) {
+        return Err(format!("Unsupported {} bits per pixel", format.bits_per_pixel));
+    }
+    let pad = format.scanline_pad as usize;
+    let stride = (width as usize * format.bits_per_pixel as usize).div_ceil(pad) * pad / 8;
+    if reply.data.len() < stride * height as usize {
+        return Err("Short GetImage reply".into());
+    }
+    let big_endian = setup.image_byte_order == ImageOrder::MSB_FIRST;
+    let channels = [visual.red_mask, visual.green_mask, visual.blue_mask].map(Channel::new);
+
+    let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+    for row in reply.data.chunks(stride).take(height as usize) {
+        for px in row[..width as usize * bytes_pp].chunks_exact(bytes_pp) {
+            let value = px.iter().enumerate().fold(0u32, |acc, (i, &b)| {
+                let shift = if big_endian { bytes_pp - 1 - i } else { i };
+                acc | (b as u32) << (8 * shift)
+            });
+            rgb.extend(channels.iter().map(|c| c.extract(value)));
+        }
+    }
+    Ok(RawFrame {
+        width: width as u32,
+        height: height as u32,
+        rgb,
+    })
+}
+
+/// One colour channel of a TrueColor visual, e.g. mask 0xf800 for 5-bit red
+struct Channel {
+    shift: u32,
+    max: u32,
+}
+
+impl Channel {
+    fn new(mask: u32) -> Self {
+        let shift = mask.trailing_zeros().min(31);
+        Self {
+            shift,
+            max: (mask >> shift).max(1),
+        }
+    }
+
+    fn extract(&self, pixel: u32) -> u8 {
+        (((pixel >> self.shift) & self.max) * 255 / self.max) as u8
+    }
+}
+
+/// Capture a QEMU VM's screen via QMP `screendump` (always PPM)
+fn capture_qmp(socket_path: &str) -> Option<RawFrame> {
+    // Unique per call: on-demand and background captures of the same VM can
+    // run at the same time
+    let tmp_ppm = format!("/tmp/qmp-screenshot-{}.ppm", uuid::Uuid::new_v4());
 
     // Send QMP commands over the unix socket via socat
     // Both commands go over a single connection
@@ -28,18 +329,106 @@ pub fn capture_qmp(socket_path: &str) -> Option<Vec<u8>> {
 
     let _ = Command::new("bash").args(["-c", &script]).output().ok()?;
 
-    // Convert PPM → PNG
-    let output = Command::new("convert")
-        .args([&tmp_ppm, "png:-"])
-        .output()
-        .ok()?;
-
-    // Clean up temp file
+    let ppm = std::fs::read(&tmp_ppm).ok();
     let _ = std::fs::remove_file(&tmp_ppm);
+    parse_ppm(&ppm?)
+}
+
+/// Parse a binary (P6) PPM with 8-bit samples
+fn parse_ppm(data: &[u8]) -> Option<RawFrame> {
+    let mut fields = Vec::with_capacity(4);
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while pos < data.len() && (data[pos].is_ascii_whitespace() || data[pos] == b'#') {
+            if data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            pos += 1;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        fields.push(std::str::from_utf8(&data[start..pos]).ok()?);
+    }
+    // Exactly one whitespace byte separates the header from the pixels
+    pos += 1;
+
+    let (width, height): (u32, u32) = (fields[1].parse().ok()?, fields[2].parse().ok()?);
+    if fields[0] != "P6" || fields[3] != "255" {
+        return None;
+    }
+    let len = width as usize * height as usize * 3;
+    let rgb = data.get(pos..pos + len)?.to_vec();
+    Some(RawFrame { width, height, rgb })
+}
 
-    if output.status.success() && !output.stdout.is_empty() {
-        Some(output.stdout)
+/// Box-filter downscale by `scale`, averaging every source pixel that
+/// falls into each destination pixel
+pub fn downscale(raw: RawFrame, scale: f32) -> RawFrame {
+    let w = ((raw.width as f32 * scale).round() as u32).max(1);
+    let h = ((raw.height as f32 * scale).round() as u32).max(1);
+    if w >= raw.width && h >= raw.height {
+        return raw;
+    }
+    let (sw, sh) = (raw.width as usize, raw.height as usize);
+    let mut rgb = Vec::with_capacity(w as usize * h as usize * 3);
+    for y in 0..h as usize {
+        let (y0, y1) = (y * sh / h as usize, ((y + 1) * sh / h as usize).max(y * sh / h as usize + 1));
+        for x in 0..w as usize {
+            let (x0, x1) = (x * sw / w as usize, ((x + 1) * sw / w as usize).max(x * sw / w as usize + 1));
+            let mut sum = [0u32; 3];
+            for sy in y0..y1 {
+                for px in raw.rgb[(sy * sw + x0) * 3..(sy * sw + x1) * 3].chunks_exact(3) {
+                    for c in 0..3 {
+                        sum[c] += px[c] as u32;
+                    }
+                }
+            }
+            let n = ((y1 - y0) * (x1 - x0)) as u32;
+            rgb.extend(sum.map(|s| (s / n) as u8));
+        }
+    }
+    RawFrame { width: w, height: h, rgb }
+}
+
+fn encode(raw: RawFrame, config: &ScreenshotConfig) -> Option<Vec<u8>> {
+    let raw = if config.scale < 1.0 {
+        downscale(raw, config.scale)
     } else {
-        None
+        raw
+    };
+    let mut out = Vec::new();
+    match config.format {
+        EncodeFormat::Png => {
+            let mut encoder = png::Encoder::new(&mut out, raw.width, raw.height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_compression(config.quality);
+            let mut writer = encoder.write_header().ok()?;
+            writer.write_image_data(&raw.rgb).ok()?;
+            writer.finish().ok()?;
+        }
+        EncodeFormat::Webp => {
+            image_webp::WebPEncoder::new(&mut out)
+                .encode(&raw.rgb, raw.width, raw.height, image_webp::ColorType::Rgb8)
+                .ok()?;
+        }
     }
+    Some(out)
+}
+
+/// Whether the request's `If-None-Match` already names `etag`
+pub fn etag_matches(headers: &axum::http::HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(axum::http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        })
 }
@@ -0,0 +1,418 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::screenshot::{self, EncodeFormat, Frame, RawFrame};
+use crate::state::SharedState;
+
+/// Upper bound on frames in one timelapse, whatever the client asks for
+const MAX_TIMELAPSE_FRAMES: usize = 1000;
+
+/// Retention for recorded frames, from `MANAGER_SCREENSHOT_HISTORY_*`
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryConfig {
+    /// Minimum time between recorded frames; zero disables history
+    pub interval: Duration,
+    /// Frames kept per sandbox; the oldest are deleted first
+    pub max_frames: usize,
+    /// Frames older than this are deleted
+    pub max_age: Duration,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            max_frames: 1000,
+            max_age: Duration::from_secs(24 * 3600),
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        let var = |name: &str| -> Option<u64> {
+            let value = std::env::var(name).ok()?;
+            value
+                .parse()
+                .map_err(|_| tracing::warn!("{} must be a whole number, got {:?}", name, value))
+                .ok()
+        };
+        if let Some(secs) = var("MANAGER_SCREENSHOT_HISTORY_INTERVAL") {
+            config.interval = Duration::from_secs(secs);
+        }
+        if let Some(frames) = var("MANAGER_SCREENSHOT_HISTORY_FRAMES") {
+            config.max_frames = frames as usize;
+        }
+        if let Some(hours) = var("MANAGER_SCREENSHOT_HISTORY_HOURS") {
+            config.max_age = Duration::from_secs(hours * 3600);
+        }
+        config
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameInfo {
+    pub timestamp: DateTime<Utc>,
+    pub bytes: u64,
+    pub url: String,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    format: EncodeFormat,
+}
+
+/// Bounded on-disk ring of timestamped frames per sandbox, stored as
+/// `<dir>/<sandbox id>/<unix millis>.<png|webp>`.
+///
+/// Frames are recorded at most once per `interval` and only when the
+/// screen changed since the last recorded frame.
+pub struct ScreenshotHistory {
+    dir: PathBuf,
+    config: HistoryConfig,
+    /// ETag of the last frame recorded per sandbox, and when a frame was
+    /// last offered, recorded or not, so an unchanged screen isn't captured
+    /// again before the next interval
+    last: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl ScreenshotHistory {
+    pub fn new(dir: PathBuf, config: HistoryConfig) -> Self {
+        Self {
+            dir,
+            config,
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a new frame for this sandbox would be recorded now
+    pub async fn due(&self, id: &str) -> bool {
+        if self.config.interval.is_zero() {
+            return false;
+        }
+        match self.last.lock().await.get(id) {
+            Some((_, at)) => at.elapsed() >= self.config.interval,
+            None => true,
+        }
+    }
+
+    /// Record `frame` if the interval has passed and the screen changed
+    pub async fn record(&self, id: &str, frame: &Frame) {
+        if !self.due(id).await {
+            return;
+        }
+        let mut last = self.last.lock().await;
+        let unchanged = last.get(id).is_some_and(|(etag, _)| *etag == frame.etag);
+        last.insert(id.to_string(), (frame.etag.clone(), Instant::now()));
+        drop(last);
+        if unchanged {
+            return;
+        }
+
+        let dir = self.dir.join(id);
+        let path = dir.join(format!(
+            "{}.{}",
+            Utc::now().timestamp_millis(),
+            frame.format.extension()
+        ));
+        let data = frame.data.clone();
+        let config = self.config;
+        let result = tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(&path, data)?;
+            prune(&dir, &config);
+            Ok::<_, std::io::Error>(())
+        })
+        .await;
+        if let Ok(Err(e)) = result {
+            tracing::warn!("Failed to record screenshot for {}: {}", id, e);
+        }
+    }
+
+    /// Recorded frames for a sandbox, oldest first
+    pub fn list(&self, id: &str) -> Vec<FrameInfo> {
+        list_frames(&self.dir.join(id), id)
+    }
+
+    /// Delete all recorded frames for a sandbox
+    pub async fn remove(&self, id: &str) {
+        self.last.lock().await.remove(id);
+        let _ = std::fs::remove_dir_all(self.dir.join(id));
+    }
+}
+
+fn list_frames(dir: &std::path::Path, id: &str) -> Vec<FrameInfo> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut frames: Vec<FrameInfo> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let format = EncodeFormat::from_extension(path.extension()?.to_str()?)?;
+            let millis: i64 = path.file_stem()?.to_str()?.parse().ok()?;
+            let timestamp = Utc.timestamp_millis_opt(millis).single()?;
+            Some(FrameInfo {
+                timestamp,
+                bytes: entry.metadata().ok()?.len(),
+                url: format!("/api/sandboxes/{}/screenshots/{}", id, millis),
+                path,
+                format,
+            })
+        })
+        .collect();
+    frames.sort_by_key(|f| f.timestamp);
+    frames
+}
+
+/// Apply the retention policy to one sandbox's frames
+fn prune(dir: &std::path::Path, config: &HistoryConfig) {
+    let frames = list_frames(dir, "");
+    let cutoff = Utc::now() - chrono::Duration::from_std(config.max_age).unwrap_or_default();
+    let excess = frames.len().saturating_sub(config.max_frames);
+    for (i, frame) in frames.iter().enumerate() {
+        if i < excess || frame.timestamp < cutoff {
+            let _ = std::fs::remove_file(&frame.path);
+        }
+    }
+}
+
+/// Parse a frame time given as RFC 3339 or Unix milliseconds
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(millis) = s.parse::<i64>() {
+        return Utc.timestamp_millis_opt(millis).single();
+    }
+    DateTime::parse_from_rfc3339(s)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+// ---------------------------------------------------------------------------
+// Handlers
+// ---------------------------------------------------------------------------
+
+pub async fn list_screenshots(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Response {
+    if !state.manager.read().await.sandboxes.contains_key(&id) {
+        return (StatusCode::NOT_FOUND, "Sandbox not found").into_response();
+    }
+    Json(state.history.list(&id)).into_response()
+}
+
+/// The last frame recorded at or before the given time
+pub async fn get_screenshot_at(
+    State(state): State<SharedState>,
+    Path((id, time)): Path<(String, String)>,
+) -> Response {
+    let Some(time) = parse_time(&time) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Time must be RFC 3339 or Unix milliseconds",
+        )
+            .into_response();
+    };
+    if !state.manager.read().await.sandboxes.contains_key(&id) {
+        return (StatusCode::NOT_FOUND, "Sandbox not found").into_response();
+    }
+    let frames = state.history.list(&id);
+    let Some(frame) = frames.iter().rev().find(|f| f.timestamp <= time) else {
+        return (StatusCode::NOT_FOUND, "No screenshot at or before that time").into_response();
+    };
+    match std::fs::read(&frame.path) {
+        Ok(data) => (
+            StatusCode::OK,
+            [
+                ("content-type", frame.format.content_type().to_string()),
+                ("x-screenshot-time", frame.timestamp.to_rfc3339()),
+                // Recorded frames never change
+                ("cache-control", "max-age=86400, immutable".into()),
+            ],
+            data,
+        )
+            .into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimelapseQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Playback speed in frames per second
+    #[serde(default = "default_fps")]
+    pub fps: u16,
+    /// Frames to include, sampled evenly across the range
+    #[serde(default = "default_timelapse_frames")]
+    pub max_frames: usize,
+    /// Output size relative to the recorded frames, in `(0, 1]`
+    #[serde(default = "default_timelapse_scale")]
+    pub scale: f32,
+}
+
+fn default_fps() -> u16 {
+    4
+}
+
+fn default_timelapse_frames() -> usize {
+    200
+}
+
+fn default_timelapse_scale() -> f32 {
+    0.5
+}
+
+/// Recorded frames in a time range as an animated PNG
+pub async fn get_timelapse(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Query(query): Query<TimelapseQuery>,
+) -> Response {
+    if !(query.scale > 0.0 && query.scale <= 1.0) || query.fps == 0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            "scale must be in (0, 1] and fps above 0",
+        )
+            .into_response();
+    }
+    if !state.manager.read().await.sandboxes.contains_key(&id) {
+        return (StatusCode::NOT_FOUND, "Sandbox not found").into_response();
+    }
+    let frames: Vec<FrameInfo> = state
+        .history
+        .list(&id)
+        .into_iter()
+        .filter(|f| query.since.is_none_or(|t| f.timestamp >= t))
+        .filter(|f| query.until.is_none_or(|t| f.timestamp <= t))
+        .collect();
+    let frames = sample(frames, query.max_frames.clamp(1, MAX_TIMELAPSE_FRAMES));
+    if frames.is_empty() {
+        return (StatusCode::NOT_FOUND, "No screenshots in that range").into_response();
+    }
+
+    let result = tokio::task::spawn_blocking(move || {
+        encode_apng(&frames, query.fps, query.scale)
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(apng) => (
+            StatusCode::OK,
+            [
+                ("content-type", "image/apng".to_string()),
+                (
+                    "content-disposition",
+                    format!("inline; filename=\"timelapse-{}.png\"", &id[..8.min(id.len())]),
+                ),
+            ],
+            apng,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Pick `n` items spread evenly over `items`, keeping the first and last
+fn sample<T>(items: Vec<T>, n: usize) -> Vec<T> {
+    if items.len() <= n {
+        return items;
+    }
+    let len = items.len();
+    let keep: std::collections::HashSet<usize> = (0..n)
+        .map(|i| if n == 1 { len - 1 } else { i * (len - 1) / (n - 1) })
+        .collect();
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| keep.contains(i))
+        .map(|(_, item)| item)
+        .collect()
+}
+
+fn decode(frame: &FrameInfo) -> Result<RawFrame, String> {
+    let data = std::fs::read(&frame.path).map_err(|e| e.to_string())?;
+    let (width, height, pixels, channels) = match frame.format {
+        EncodeFormat::Png => {
+            let mut decoder = png::Decoder::new(Cursor::new(data));
+            decoder.set_transformations(png::Transformations::normalize_to_color8());
+            let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+            buf.truncate(info.buffer_size());
+            let channels = info.color_type.samples();
+            (info.width, info.height, buf, channels)
+        }
+        EncodeFormat::Webp => {
+            let mut decoder =
+                image_webp::WebPDecoder::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+            let (width, height) = decoder.dimensions();
+            let channels = if decoder.has_alpha() { 4 } else { 3 };
+            let mut buf = vec![0; decoder.output_buffer_size().ok_or("WebP too large")?];
+            decoder.read_image(&mut buf).map_err(|e| e.to_string())?;
+            (width, height, buf, channels)
+        }
+    };
+    let rgb = match channels {
+        3 => pixels,
+        4 => pixels.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect(),
+        // Grayscale, with or without alpha
+        1 | 2 => pixels.chunks_exact(channels).flat_map(|p| [p[0]; 3]).collect(),
+        n => return Err(format!("Unsupported {}-channel image", n)),
+    };
+    Ok(RawFrame { width, height, rgb })
+}
+
+/// Width and height from the image header, without decoding pixels
+fn dimensions(frame: &FrameInfo) -> Option<(u32, u32)> {
+    let file = std::io::BufReader::new(std::fs::File::open(&frame.path).ok()?);
+    match frame.format {
+        EncodeFormat::Png => {
+            let reader = png::Decoder::new(file).read_info().ok()?;
+            Some(reader.info().size())
+        }
+        EncodeFormat::Webp => image_webp::WebPDecoder::new(file).ok().map(|d| d.dimensions()),
+    }
+}
+
+/// Encode frames as an APNG, decoding one frame at a time. Frames whose size
+/// differs from the first (the display was resized) are skipped.
+fn encode_apng(frames: &[FrameInfo], fps: u16, scale: f32) -> Result<Vec<u8>, String> {
+    let size = dimensions(&frames[0]).ok_or("Failed to read first frame")?;
+    // APNG needs the frame count before any image data is written
+    let frames: Vec<&FrameInfo> = frames
+        .iter()
+        .filter(|f| dimensions(f) == Some(size))
+        .collect();
+
+    let first = screenshot::downscale(decode(frames[0])?, scale);
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, first.width, first.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Fast);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(|e| e.to_string())?;
+    encoder.set_frame_delay(1, fps).map_err(|e| e.to_string())?;
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(&first.rgb)
+        .map_err(|e| e.to_string())?;
+    for frame in &frames[1..] {
+        let raw = screenshot::downscale(decode(frame)?, scale);
+        writer
+            .write_image_data(&raw.rgb)
+            .map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(out)
+}
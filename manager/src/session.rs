@@ -1,9 +1,12 @@
+use std::collections::HashMap;
 use std::process::Command;
 
-/// Create a new tmux session running `command` with DISPLAY set
+/// Create a new tmux session running `command` with DISPLAY (and the
+/// display's XAUTHORITY) set
 pub fn create_session(
     session_name: &str,
     display_num: Option<u32>,
+    xauthority: Option<&str>,
     command: &str,
     working_dir: &str,
 ) -> std::io::Result<()> {
@@ -13,14 +16,16 @@ pub fn create_session(
     if let Some(num) = display_num {
         cmd.env("DISPLAY", format!(":{}", num));
     }
+    if let Some(path) = xauthority {
+        cmd.env("XAUTHORITY", path);
+    }
 
     // The remaining arg is the shell command to run inside tmux
     cmd.arg(command);
 
     let output = cmd.output()?;
     if !output.status.success() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(std::io::Error::other(
             String::from_utf8_lossy(&output.stderr).to_string(),
         ));
     }
@@ -49,14 +54,66 @@ pub fn start_pipe_pane(session_name: &str, log_path: &std::path::Path) -> std::i
         ])
         .output()?;
     if !output.status.success() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(std::io::Error::other(
             String::from_utf8_lossy(&output.stderr).to_string(),
         ));
     }
     Ok(())
 }
 
+/// Type `text` into the session's active pane, literally (no key-name lookup)
+pub fn send_text(session_name: &str, text: &str) -> std::io::Result<()> {
+    run_tmux(&["send-keys", "-t", session_name, "-l", "--", text]).map(|_| ())
+}
+
+/// Send tmux key names (`Enter`, `Escape`, `C-c`, `Up`, ...) to the active pane
+pub fn send_keys(session_name: &str, keys: &[String]) -> std::io::Result<()> {
+    let mut args = vec!["send-keys", "-t", session_name, "--"];
+    args.extend(keys.iter().map(String::as_str));
+    run_tmux(&args).map(|_| ())
+}
+
+/// Capture the active pane as plain text, including up to `history`
+/// scrollback lines above the visible screen. Wrapped lines are joined.
+pub fn capture_pane(session_name: &str, history: u32) -> std::io::Result<String> {
+    let start = format!("-{}", history);
+    run_tmux(&["capture-pane", "-p", "-J", "-t", session_name, "-S", &start])
+}
+
+/// Capture the visible screen of the active pane, with SGR escape
+/// sequences for colours and attributes if `escapes` is set
+pub fn capture_screen(session_name: &str, escapes: bool) -> std::io::Result<String> {
+    let mut args = vec!["capture-pane", "-p", "-t", session_name];
+    if escapes {
+        args.push("-e");
+    }
+    run_tmux(&args)
+}
+
+/// PIDs of the processes running in each pane, by session name
+pub fn pane_pids() -> std::io::Result<HashMap<String, Vec<u32>>> {
+    let out = run_tmux(&["list-panes", "-a", "-F", "#{session_name} #{pane_pid}"])?;
+    let mut pids: HashMap<String, Vec<u32>> = HashMap::new();
+    for line in out.lines() {
+        if let Some((session, pid)) = line.rsplit_once(' ') {
+            if let Ok(pid) = pid.parse() {
+                pids.entry(session.to_string()).or_default().push(pid);
+            }
+        }
+    }
+    Ok(pids)
+}
+
+fn run_tmux(args: &[&str]) -> std::io::Result<String> {
+    let output = Command::new("tmux").args(args).output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
 /// Kill a tmux session
 pub fn kill_session(session_name: &str) {
     let _ = Command::new("tmux")
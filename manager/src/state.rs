@@ -5,6 +5,22 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::attention::{Attention, AttentionConfig};
+use crate::audit::AuditLog;
+use crate::claude::SessionCache;
+use crate::cost;
+use crate::metrics::SystemSampler;
+use crate::metrics_history::MetricsHistory;
+use crate::notify::Notifier;
+use crate::openmetrics::Telemetry;
+use crate::resources::ResourceMonitor;
+use crate::display::{Geometry, XvfbServers};
+use crate::scope::{self, Limits, ScopeConfig};
+use crate::screenshot::ScreenshotStore;
+use crate::screenshot_history::ScreenshotHistory;
+use crate::vnc::VncServers;
+use crate::webhooks::Webhooks;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Backend {
@@ -49,10 +65,40 @@ pub struct Sandbox {
     pub project_dir: String,
     pub status: SandboxStatus,
     pub display_num: Option<u32>,
+    /// Xvfb screen size and depth
+    #[serde(default)]
+    pub geometry: Option<Geometry>,
+    /// Xauthority file holding the display's cookie, passed to the sandbox
+    #[serde(default)]
+    pub xauthority: Option<String>,
     pub tmux_session: Option<String>,
     pub pid_xvfb: Option<u32>,
+    /// Start time of `pid_xvfb` (see `display::process_start_time`), checked
+    /// before signalling it in case the PID was reused
+    #[serde(default)]
+    pub xvfb_start_time: Option<u64>,
     pub qemu_qmp_socket: Option<String>,
+    /// RFB socket served by QEMU (VM backend); Xvfb displays get an
+    /// x11vnc server on demand instead
+    #[serde(default)]
+    pub vnc_socket: Option<String>,
     pub network: bool,
+    /// Transient systemd scope the backend runs in, if scopes are enabled
+    #[serde(default)]
+    pub scope: Option<String>,
+    /// Limits set on the scope
+    #[serde(default)]
+    pub limits: Limits,
+    /// Estimated cost in USD at which the sandbox is stopped
+    #[serde(default)]
+    pub budget_usd: Option<f64>,
+    /// Claude data directory (`~/.claude` inside the sandbox) as seen by
+    /// the manager; the manager's default for sandboxes that predate it
+    #[serde(default)]
+    pub claude_dir: Option<String>,
+    /// What the sandbox waits for someone to do, if anything
+    #[serde(default)]
+    pub attention: Option<Attention>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -64,21 +110,15 @@ impl Sandbox {
     pub fn is_running(&self) -> bool {
         self.status == SandboxStatus::Running
     }
+
+    pub fn budget_human(&self) -> String {
+        self.budget_usd.map_or("none".to_string(), cost::usd)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ManagerState {
     pub sandboxes: HashMap<String, Sandbox>,
-    pub next_display: u32,
-}
-
-impl Default for ManagerState {
-    fn default() -> Self {
-        Self {
-            sandboxes: HashMap::new(),
-            next_display: 50,
-        }
-    }
 }
 
 impl ManagerState {
@@ -104,8 +144,9 @@ impl ManagerState {
         Ok(())
     }
 
-    /// Check if tmux sessions are still alive, mark dead sandboxes
-    pub fn reconcile_pids(&mut self) {
+    /// Mark sandboxes whose tmux session is gone dead, returning them
+    pub fn reconcile_pids(&mut self) -> Vec<Sandbox> {
+        let mut died = Vec::new();
         for sandbox in self.sandboxes.values_mut() {
             if sandbox.status != SandboxStatus::Running {
                 continue;
@@ -122,19 +163,49 @@ impl ManagerState {
                         sandbox.short_id()
                     );
                     sandbox.status = SandboxStatus::Dead;
+                    // Don't let anything the backend left behind keep running
+                    if let Some(ref unit) = sandbox.scope {
+                        scope::stop_no_block(unit);
+                    }
+                    died.push(sandbox.clone());
                 }
             }
         }
+        died
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateSandboxRequest {
     pub name: String,
     pub backend: Backend,
     pub project_dir: String,
     #[serde(default = "default_true")]
     pub network: bool,
+    /// Xvfb screen size as `WIDTHxHEIGHT`, default `1920x1080`
+    #[serde(default)]
+    pub resolution: Option<String>,
+    /// Xvfb colour depth in bits, default 24
+    #[serde(default)]
+    pub depth: Option<u8>,
+    /// Scope limits (see `scope::Limits`); unset ones use the manager's
+    /// defaults
+    #[serde(default, deserialize_with = "scope::optional_text")]
+    pub memory_max: Option<String>,
+    #[serde(default, deserialize_with = "scope::optional_text")]
+    pub cpu_quota: Option<String>,
+    #[serde(default, deserialize_with = "scope::optional_number")]
+    pub tasks_max: Option<u32>,
+    #[serde(default, deserialize_with = "scope::optional_number")]
+    pub io_weight: Option<u16>,
+    /// Stop the sandbox once its estimated cost reaches this many USD;
+    /// unset uses the manager's default
+    #[serde(default, deserialize_with = "cost::optional_amount")]
+    pub budget_usd: Option<f64>,
+    /// Where the sandbox's Claude keeps its data, for a backend that
+    /// doesn't bind the manager user's `~/.claude`
+    #[serde(default, deserialize_with = "scope::optional_text")]
+    pub claude_dir: Option<String>,
 }
 
 fn default_true() -> bool {
@@ -145,7 +216,24 @@ pub struct AppState {
     pub manager: RwLock<ManagerState>,
     pub state_path: PathBuf,
     pub log_dir: PathBuf,
-    pub screenshots: RwLock<HashMap<String, Vec<u8>>>,
+    /// Sockets for QMP, VNC and other per-sandbox endpoints
+    pub runtime_dir: PathBuf,
+    pub screenshots: ScreenshotStore,
+    pub history: ScreenshotHistory,
+    pub audit: AuditLog,
+    pub vnc: VncServers,
+    pub xvfb: XvfbServers,
+    pub resources: ResourceMonitor,
+    pub scopes: ScopeConfig,
+    pub metrics_history: MetricsHistory,
+    pub telemetry: Telemetry,
+    pub system: SystemSampler,
+    pub sessions: SessionCache,
+    /// Budget of sandboxes created without one
+    pub default_budget: Option<f64>,
+    pub attention: AttentionConfig,
+    pub notifier: Notifier,
+    pub webhooks: Webhooks,
 }
 
 pub type SharedState = Arc<AppState>;
@@ -0,0 +1,212 @@
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Path, State, WebSocketUpgrade};
+use axum::response::Response;
+use serde::Deserialize;
+use std::io::Read;
+use std::os::fd::{AsRawFd, OwnedFd};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::audit::{AuditAction, Caller};
+use crate::state::SharedState;
+
+/// Terminal size used until the browser reports its own
+const DEFAULT_COLS: u16 = 120;
+const DEFAULT_ROWS: u16 = 40;
+
+/// Control messages sent by the browser as text frames.
+/// Binary frames are raw keystrokes and bypass this.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 },
+}
+
+pub async fn ws_terminal(
+    ws: WebSocketUpgrade,
+    State(state): State<SharedState>,
+    caller: Caller,
+    Path(id): Path<String>,
+) -> Response {
+    let session = {
+        let manager = state.manager.read().await;
+        manager
+            .sandboxes
+            .get(&id)
+            .filter(|sb| sb.is_running())
+            .and_then(|sb| sb.tmux_session.clone())
+    };
+    let Some(session) = session else {
+        return Response::builder()
+            .status(404)
+            .body("Sandbox not found or not running".into())
+            .unwrap();
+    };
+
+    ws.on_upgrade(move |socket| async move {
+        let attached = Pty::attach(&session, DEFAULT_COLS, DEFAULT_ROWS);
+        let result = attached.as_ref().map(|_| ()).map_err(|e| e.to_string());
+        state.audit.record(
+            &caller,
+            AuditAction::Attach,
+            Some(&id),
+            serde_json::json!({ "via": "terminal" }),
+            &result,
+        );
+        match attached {
+            Ok(pty) => handle_socket(socket, pty).await,
+            Err(e) => {
+                let mut socket = socket;
+                let _ = socket
+                    .send(Message::Text(format!("[Failed to attach: {}]", e)))
+                    .await;
+            }
+        }
+    })
+}
+
+/// A `tmux attach` client running on the slave side of a fresh PTY
+struct Pty {
+    master: OwnedFd,
+    child: tokio::process::Child,
+}
+
+impl Pty {
+    fn attach(session: &str, cols: u16, rows: u16) -> std::io::Result<Self> {
+        let (master, slave) = open_pty(cols, rows)?;
+
+        let mut cmd = tokio::process::Command::new("tmux");
+        // -u: the browser widget always decodes UTF-8, whatever our locale
+        cmd.args(["-u", "attach-session", "-t", session])
+            .env("TERM", "xterm-256color")
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave)
+            .kill_on_drop(true);
+        // SAFETY: only async-signal-safe libc calls between fork and exec
+        unsafe {
+            cmd.pre_exec(|| {
+                // New session with the PTY as controlling terminal, so tmux
+                // sees a real tty and gets SIGWINCH on resize
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        // `cmd` owns the slave fds and closes them once it is dropped, so
+        // reads on the master see EOF/EIO when tmux exits
+        let child = cmd.spawn()?;
+        Ok(Pty { master, child })
+    }
+
+    fn resize(&self, cols: u16, rows: u16) {
+        let ws = winsize(cols, rows);
+        // SAFETY: TIOCSWINSZ reads a winsize from the pointer we pass
+        unsafe {
+            libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &ws);
+        }
+    }
+}
+
+fn winsize(cols: u16, rows: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn open_pty(cols: u16, rows: u16) -> std::io::Result<(OwnedFd, OwnedFd)> {
+    use std::os::fd::FromRawFd;
+
+    let mut master: libc::c_int = -1;
+    let mut slave: libc::c_int = -1;
+    let ws = winsize(cols, rows);
+    // SAFETY: openpty writes two fds on success; we take ownership of both
+    let rc = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &ws,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    unsafe { Ok((OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))) }
+}
+
+async fn handle_socket(mut socket: WebSocket, mut pty: Pty) {
+    let reader = match pty.master.try_clone() {
+        Ok(fd) => std::fs::File::from(fd),
+        Err(_) => return,
+    };
+    let mut writer = match pty.master.try_clone() {
+        Ok(fd) => tokio::fs::File::from_std(std::fs::File::from(fd)),
+        Err(_) => return,
+    };
+
+    // PTY reads block, so they run on a blocking thread and feed a channel
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(64);
+    tokio::task::spawn_blocking(move || {
+        let mut reader = reader;
+        let mut buf = [0u8; 8192];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break, // EIO once tmux exits
+                Ok(n) => {
+                    if tx.blocking_send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    loop {
+        tokio::select! {
+            output = rx.recv() => match output {
+                Some(data) => {
+                    if socket.send(Message::Binary(data)).await.is_err() {
+                        break; // Client disconnected
+                    }
+                }
+                None => {
+                    let _ = socket.send(Message::Text("[Session detached]".into())).await;
+                    break;
+                }
+            },
+            msg = socket.recv() => {
+                let input = match msg {
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                        Ok(ClientMessage::Input { data }) => data.into_bytes(),
+                        Ok(ClientMessage::Resize { cols, rows }) => {
+                            if cols > 0 && rows > 0 {
+                                pty.resize(cols, rows);
+                            }
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::debug!("Ignoring terminal message: {}", e);
+                            continue;
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    _ => continue,
+                };
+                if writer.write_all(&input).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Killing the tmux client only detaches it; the session keeps running
+    let _ = pty.child.kill().await;
+}
@@ -0,0 +1,498 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::BufRead;
+
+use crate::state::{Sandbox, SharedState};
+
+/// Longest text kept per entry; tool results in particular can be huge
+const MAX_TEXT: usize = 10_000;
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    User,
+    Assistant,
+    Thinking,
+    ToolUse,
+    ToolResult,
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryKind::User => write!(f, "user"),
+            EntryKind::Assistant => write!(f, "assistant"),
+            EntryKind::Thinking => write!(f, "thinking"),
+            EntryKind::ToolUse => write!(f, "tool_use"),
+            EntryKind::ToolResult => write!(f, "tool_result"),
+        }
+    }
+}
+
+impl EntryKind {
+    /// Heading shown on the detail page
+    pub fn label(&self) -> &'static str {
+        match self {
+            EntryKind::User => "User",
+            EntryKind::Assistant => "Claude",
+            EntryKind::Thinking => "Thinking",
+            EntryKind::ToolUse => "Tool call",
+            EntryKind::ToolResult => "Tool result",
+        }
+    }
+}
+
+/// One rendered piece of the conversation
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptEntry {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub kind: EntryKind,
+    /// Prompt, reply or thinking text, a tool call's arguments as JSON, or a
+    /// tool's output
+    pub text: String,
+    /// Tool called, for tool calls and their results
+    pub tool: Option<String>,
+    /// Whether a tool result is flagged as an error
+    pub is_error: bool,
+    /// Whether `text` was cut to `MAX_TEXT` characters
+    pub truncated: bool,
+}
+
+impl TranscriptEntry {
+    pub fn time_human(&self) -> String {
+        self.timestamp
+            .map_or(String::new(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TranscriptQuery {
+    /// Session ID, default the most recent one
+    #[serde(default)]
+    pub session: Option<String>,
+    /// Case-insensitive text to look for in entries and tool names
+    #[serde(default)]
+    pub q: Option<String>,
+    /// Index of the first entry; default the last page
+    #[serde(default)]
+    pub offset: Option<usize>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TranscriptPage {
+    /// Session shown, `null` if the sandbox has none
+    pub session: Option<String>,
+    /// All of the sandbox's sessions, oldest first
+    pub sessions: Vec<String>,
+    /// Entries matching the query
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl TranscriptPage {
+    /// Offset of the previous page, if there is one
+    pub fn prev_offset(&self) -> Option<usize> {
+        (self.offset > 0).then(|| self.offset.saturating_sub(self.limit))
+    }
+
+    /// Offset of the next page, if there is one
+    pub fn next_offset(&self) -> Option<usize> {
+        let next = self.offset + self.entries.len();
+        (next < self.total).then_some(next)
+    }
+}
+
+/// A page of one of the sandbox's sessions. `Err` for a session ID that
+/// isn't one of the sandbox's.
+pub fn load(state: &SharedState, sandbox: &Sandbox, query: &TranscriptQuery) -> Result<TranscriptPage, String> {
+    let sessions: Vec<String> = state.sessions.sessions(sandbox).into_iter().map(|s| s.id).collect();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let session = match query.session.as_deref().filter(|s| !s.is_empty()) {
+        Some(id) if sessions.iter().any(|s| s == id) => id.to_string(),
+        Some(id) => return Err(format!("No session {:?} in this sandbox", id)),
+        None => match sessions.last() {
+            Some(id) => id.clone(),
+            None => {
+                return Ok(TranscriptPage {
+                    limit,
+                    ..Default::default()
+                })
+            }
+        },
+    };
+    let path = state
+        .sessions
+        .session_file(sandbox, &session)
+        .ok_or_else(|| format!("Session {} is gone", session))?;
+    let file = std::fs::File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+    let needle = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()).map(str::to_lowercase);
+    let reader = Reader {
+        since: sandbox.created_at,
+        tools: HashMap::new(),
+    };
+    let file = std::io::BufReader::new(file);
+    let (total, entries) = page(file, reader, needle.as_deref(), query.offset, limit);
+
+    Ok(TranscriptPage {
+        session: Some(session),
+        sessions,
+        total,
+        offset: query.offset.unwrap_or(total - entries.len()),
+        limit,
+        entries,
+    })
+}
+
+/// Stream a session file, keeping only the entries containing `needle`
+/// (lowercase) in their text or tool name: how many there are, and the
+/// `limit` of them from `offset`, or the last ones without one
+fn page(
+    mut file: impl BufRead,
+    mut reader: Reader,
+    needle: Option<&str>,
+    offset: Option<usize>,
+    limit: usize,
+) -> (usize, Vec<TranscriptEntry>) {
+    let mut window = VecDeque::new();
+    let mut total = 0;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match file.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let Ok(v) = serde_json::from_slice::<serde_json::Value>(&buf) else {
+            continue;
+        };
+        for entry in reader.entries(&v) {
+            if let Some(needle) = needle {
+                let hit = entry.text.to_lowercase().contains(needle)
+                    || entry.tool.as_ref().is_some_and(|t| t.to_lowercase().contains(needle));
+                if !hit {
+                    continue;
+                }
+            }
+            match offset {
+                Some(offset) if total >= offset && total < offset + limit => window.push_back(entry),
+                Some(_) => {}
+                None => {
+                    if window.len() == limit {
+                        window.pop_front();
+                    }
+                    window.push_back(entry);
+                }
+            }
+            total += 1;
+        }
+    }
+    (total, window.into())
+}
+
+/// Turns JSONL lines into entries, remembering tool calls so their results
+/// can name the tool
+struct Reader {
+    since: DateTime<Utc>,
+    tools: HashMap<String, String>,
+}
+
+impl Reader {
+    fn entries(&mut self, v: &serde_json::Value) -> Vec<TranscriptEntry> {
+        let timestamp = v
+            .get("timestamp")
+            .and_then(|t| t.as_str())
+            .and_then(|t| t.parse::<DateTime<Utc>>().ok());
+        if timestamp.is_some_and(|t| t < self.since) {
+            return Vec::new();
+        }
+        // Context Claude injects itself, such as command output captions
+        if v.get("isMeta").and_then(|m| m.as_bool()) == Some(true) {
+            return Vec::new();
+        }
+        let kind = match v.get("type").and_then(|t| t.as_str()) {
+            Some("user") => EntryKind::User,
+            Some("assistant") => EntryKind::Assistant,
+            _ => return Vec::new(),
+        };
+        let entry = |kind, text: String, tool: Option<String>, is_error| {
+            let (text, truncated) = truncate(text);
+            TranscriptEntry {
+                timestamp,
+                kind,
+                text,
+                tool,
+                is_error,
+                truncated,
+            }
+        };
+
+        let blocks = match v.pointer("/message/content") {
+            Some(serde_json::Value::String(text)) => return vec![entry(kind, text.clone(), None, false)],
+            Some(serde_json::Value::Array(blocks)) => blocks,
+            _ => return Vec::new(),
+        };
+        let mut entries = Vec::new();
+        for block in blocks {
+            let text_of = |key: &str| block.get(key).and_then(|t| t.as_str()).unwrap_or_default().to_string();
+            match block.get("type").and_then(|t| t.as_str()) {
+                Some("text") => {
+                    let text = text_of("text");
+                    if !text.trim().is_empty() {
+                        entries.push(entry(kind, text, None, false));
+                    }
+                }
+                Some("thinking") => {
+                    let text = text_of("thinking");
+                    if !text.trim().is_empty() {
+                        entries.push(entry(EntryKind::Thinking, text, None, false));
+                    }
+                }
+                Some("tool_use") => {
+                    let name = text_of("name");
+                    if let Some(id) = block.get("id").and_then(|i| i.as_str()) {
+                        self.tools.insert(id.to_string(), name.clone());
+                    }
+                    let input = block
+                        .get("input")
+                        .map(|i| serde_json::to_string_pretty(i).unwrap_or_default())
+                        .unwrap_or_default();
+                    entries.push(entry(EntryKind::ToolUse, input, Some(name), false));
+                }
+                Some("tool_result") => {
+                    let tool = block
+                        .get("tool_use_id")
+                        .and_then(|i| i.as_str())
+                        .and_then(|id| self.tools.remove(id));
+                    let is_error = block.get("is_error").and_then(|e| e.as_bool()) == Some(true);
+                    entries.push(entry(EntryKind::ToolResult, result_text(block.get("content")), tool, is_error));
+                }
+                Some("image") => entries.push(entry(kind, "[image]".to_string(), None, false)),
+                _ => {}
+            }
+        }
+        entries
+    }
+}
+
+/// Text of a tool result's content: a string, or text and image blocks
+fn result_text(content: Option<&serde_json::Value>) -> String {
+    match content {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Array(parts)) => parts
+            .iter()
+            .map(|p| match p.get("type").and_then(|t| t.as_str()) {
+                Some("image") => "[image]",
+                _ => p.get("text").and_then(|t| t.as_str()).unwrap_or_default(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn truncate(text: String) -> (String, bool) {
+    match text.char_indices().nth(MAX_TEXT) {
+        Some((end, _)) => (text[..end].to_string(), true),
+        None => (text, false),
+    }
+}
+
+pub async fn get_transcript(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Query(query): Query<TranscriptQuery>,
+) -> Response {
+    let sandbox = state.manager.read().await.sandboxes.get(&id).cloned();
+    let Some(sandbox) = sandbox else {
+        return (StatusCode::NOT_FOUND, "Sandbox not found").into_response();
+    };
+    let s = state.clone();
+    let page = tokio::task::spawn_blocking(move || load(&s, &sandbox, &query)).await;
+    match page {
+        Ok(Ok(page)) => Json(page).into_response(),
+        Ok(Err(e)) => (StatusCode::NOT_FOUND, e).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn reader() -> Reader {
+        Reader {
+            since: "2026-01-01T00:00:00Z".parse().unwrap(),
+            tools: HashMap::new(),
+        }
+    }
+
+    fn line(kind: &str, content: serde_json::Value) -> serde_json::Value {
+        json!({ "type": kind, "timestamp": "2026-01-01T00:00:01Z", "message": { "content": content } })
+    }
+
+    #[test]
+    fn reads_string_content() {
+        let entries = reader().entries(&line("user", json!("Fix the build")));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].kind, EntryKind::User);
+        assert_eq!(entries[0].text, "Fix the build");
+        assert_eq!(entries[0].timestamp, Some("2026-01-01T00:00:01Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn reads_content_blocks() {
+        let entries = reader().entries(&line(
+            "assistant",
+            json!([
+                { "type": "thinking", "thinking": "Which test?" },
+                { "type": "text", "text": "Running it" },
+                { "type": "text", "text": "  " },
+                { "type": "tool_use", "id": "toolu_1", "name": "Bash", "input": { "command": "ls" } },
+                { "type": "image" },
+                { "type": "server_tool_use" },
+            ]),
+        ));
+        let kinds: Vec<EntryKind> = entries.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [EntryKind::Thinking, EntryKind::Assistant, EntryKind::ToolUse, EntryKind::Assistant]
+        );
+        assert_eq!(entries[0].text, "Which test?");
+        assert_eq!(entries[1].text, "Running it");
+        assert_eq!(entries[2].tool.as_deref(), Some("Bash"));
+        assert_eq!(entries[2].text, "{\n  \"command\": \"ls\"\n}");
+        assert_eq!(entries[3].text, "[image]");
+    }
+
+    #[test]
+    fn names_the_tool_of_a_result() {
+        let mut reader = reader();
+        reader.entries(&line(
+            "assistant",
+            json!([{ "type": "tool_use", "id": "toolu_1", "name": "Read", "input": {} }]),
+        ));
+        let result = |reader: &mut Reader| {
+            reader.entries(&line(
+                "user",
+                json!([{ "type": "tool_result", "tool_use_id": "toolu_1", "is_error": true, "content": "No such file" }]),
+            ))
+        };
+        let entries = result(&mut reader);
+        assert_eq!(entries[0].kind, EntryKind::ToolResult);
+        assert_eq!(entries[0].tool.as_deref(), Some("Read"));
+        assert!(entries[0].is_error);
+        assert_eq!(entries[0].text, "No such file");
+        // A call has one result; another one with its ID is unnamed
+        assert_eq!(result(&mut reader)[0].tool, None);
+    }
+
+    #[test]
+    fn skips_meta_earlier_and_other_lines() {
+        let mut reader = reader();
+        let mut meta = line("user", json!("Caveat: the messages below were generated by the user"));
+        meta["isMeta"] = json!(true);
+        assert!(reader.entries(&meta).is_empty());
+        let mut earlier = line("user", json!("From before the sandbox"));
+        earlier["timestamp"] = json!("2025-12-31T23:59:59Z");
+        assert!(reader.entries(&earlier).is_empty());
+        assert!(reader.entries(&line("summary", json!("A summary"))).is_empty());
+        assert!(reader.entries(&json!({ "type": "user" })).is_empty());
+        // No timestamp isn't before the cut-off
+        assert_eq!(reader.entries(&json!({ "type": "user", "message": { "content": "hi" } })).len(), 1);
+    }
+
+    #[test]
+    fn joins_result_text_parts() {
+        assert_eq!(result_text(None), "");
+        assert_eq!(result_text(Some(&json!("done"))), "done");
+        let parts = json!([{ "type": "text", "text": "one" }, { "type": "image" }, { "type": "text", "text": "two" }]);
+        assert_eq!(result_text(Some(&parts)), "one\n[image]\ntwo");
+        assert_eq!(result_text(Some(&json!(42))), "");
+    }
+
+    #[test]
+    fn truncates_by_characters() {
+        assert_eq!(truncate("short".to_string()), ("short".to_string(), false));
+        let exact = "é".repeat(MAX_TEXT);
+        assert_eq!(truncate(exact.clone()), (exact.clone(), false));
+        let (text, truncated) = truncate(format!("{}é", exact));
+        assert!(truncated);
+        assert_eq!(text, exact);
+    }
+
+    /// A session of `n` prompts, `prompt 0` to `prompt n-1`, and a garbled line
+    fn session(n: usize) -> std::io::Cursor<Vec<u8>> {
+        let mut text = String::new();
+        for i in 0..n {
+            text.push_str(&line("user", json!(format!("prompt {}", i))).to_string());
+            text.push('\n');
+            if i == 2 {
+                text.push_str("{\"type\":\n");
+            }
+        }
+        std::io::Cursor::new(text.into_bytes())
+    }
+
+    fn texts(entries: &[TranscriptEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.text.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_from_offset_or_from_the_end() {
+        let (total, entries) = page(session(7), reader(), None, None, 3);
+        assert_eq!(total, 7);
+        assert_eq!(texts(&entries), ["prompt 4", "prompt 5", "prompt 6"]);
+
+        let (total, entries) = page(session(7), reader(), None, Some(2), 3);
+        assert_eq!(total, 7);
+        assert_eq!(texts(&entries), ["prompt 2", "prompt 3", "prompt 4"]);
+
+        let (_, entries) = page(session(7), reader(), None, Some(6), 3);
+        assert_eq!(texts(&entries), ["prompt 6"]);
+        let (_, entries) = page(session(7), reader(), None, Some(10), 3);
+        assert!(entries.is_empty());
+
+        let (total, entries) = page(session(2), reader(), None, None, 3);
+        assert_eq!(total, 2);
+        assert_eq!(texts(&entries), ["prompt 0", "prompt 1"]);
+    }
+
+    #[test]
+    fn pages_search_results() {
+        let (total, entries) = page(session(12), reader(), Some("prompt 1"), None, 2);
+        // prompt 1, 10 and 11
+        assert_eq!(total, 3);
+        assert_eq!(texts(&entries), ["prompt 10", "prompt 11"]);
+        let (_, entries) = page(session(12), reader(), Some("prompt 1"), Some(0), 2);
+        assert_eq!(texts(&entries), ["prompt 1", "prompt 10"]);
+    }
+
+    #[test]
+    fn links_neighbouring_pages() {
+        let page = |offset, shown, total| TranscriptPage {
+            total,
+            offset,
+            limit: 3,
+            entries: vec![reader().entries(&line("user", json!("x"))).remove(0); shown],
+            ..Default::default()
+        };
+        assert_eq!(page(0, 3, 7).prev_offset(), None);
+        assert_eq!(page(0, 3, 7).next_offset(), Some(3));
+        assert_eq!(page(2, 3, 7).prev_offset(), Some(0));
+        assert_eq!(page(4, 3, 7).prev_offset(), Some(1));
+        assert_eq!(page(4, 3, 7).next_offset(), None);
+    }
+}
@@ -0,0 +1,170 @@
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Path, State, WebSocketUpgrade};
+use axum::response::Response;
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+
+use crate::audit::{AuditAction, Caller};
+use crate::state::{Sandbox, SharedState};
+
+/// How long to wait for a freshly started x11vnc to create its socket
+const X11VNC_STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// x11vnc servers started on demand for Xvfb displays, keyed by sandbox ID.
+///
+/// VMs need none of this: QEMU serves RFB itself on `Sandbox::vnc_socket`.
+#[derive(Default)]
+pub struct VncServers {
+    servers: Mutex<HashMap<String, Child>>,
+}
+
+impl VncServers {
+    /// Return the RFB socket for `sandbox`, starting x11vnc if needed
+    async fn socket_for(&self, runtime_dir: &FsPath, sandbox: &Sandbox) -> Result<PathBuf, String> {
+        if let Some(ref sock) = sandbox.vnc_socket {
+            return Ok(PathBuf::from(sock));
+        }
+        let Some(display_num) = sandbox.display_num else {
+            return Err("Sandbox has no display".into());
+        };
+
+        let socket = runtime_dir.join(format!("vnc-{}.sock", sandbox.short_id()));
+        let mut servers = self.servers.lock().await;
+        if let Some(child) = servers.get_mut(&sandbox.id) {
+            if matches!(child.try_wait(), Ok(None)) && socket.exists() {
+                return Ok(socket);
+            }
+            servers.remove(&sandbox.id);
+        }
+
+        let child = start_x11vnc(display_num, sandbox.xauthority.as_deref(), &socket).await?;
+        servers.insert(sandbox.id.clone(), child);
+        Ok(socket)
+    }
+
+    /// Stop the x11vnc server for a sandbox, if one was started
+    pub async fn stop(&self, id: &str) {
+        if let Some(mut child) = self.servers.lock().await.remove(id) {
+            let _ = child.kill().await;
+        }
+    }
+}
+
+async fn start_x11vnc(
+    display_num: u32,
+    xauthority: Option<&str>,
+    socket: &FsPath,
+) -> Result<Child, String> {
+    if let Some(dir) = socket.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let _ = std::fs::remove_file(socket);
+
+    let mut cmd = Command::new("x11vnc");
+    cmd.args(["-display", &format!(":{}", display_num)]);
+    if let Some(path) = xauthority {
+        cmd.args(["-auth", path]);
+    }
+    let mut child = cmd
+        .arg("-unixsock")
+        .arg(socket)
+        // No TCP listener: the socket is only reachable through the manager
+        .args(["-rfbport", "0", "-nopw", "-shared", "-forever", "-quiet"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to start x11vnc: {}", e))?;
+
+    let started = std::time::Instant::now();
+    while !socket.exists() {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("x11vnc exited: {}", status));
+        }
+        if started.elapsed() > X11VNC_STARTUP_TIMEOUT {
+            let _ = child.kill().await;
+            return Err("x11vnc did not create its socket in time".into());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(child)
+}
+
+pub async fn ws_vnc(
+    ws: WebSocketUpgrade,
+    State(state): State<SharedState>,
+    caller: Caller,
+    Path(id): Path<String>,
+) -> Response {
+    let sandbox = {
+        let manager = state.manager.read().await;
+        manager.sandboxes.get(&id).filter(|sb| sb.is_running()).cloned()
+    };
+    let Some(sandbox) = sandbox else {
+        return Response::builder()
+            .status(404)
+            .body("Sandbox not found or not running".into())
+            .unwrap();
+    };
+    if sandbox.display_num.is_none() && sandbox.vnc_socket.is_none() {
+        return Response::builder()
+            .status(409)
+            .body("Sandbox has no display".into())
+            .unwrap();
+    }
+
+    let connected = match state.vnc.socket_for(&state.runtime_dir, &sandbox).await {
+        Ok(socket) => UnixStream::connect(&socket)
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", socket.display(), e)),
+        Err(e) => Err(e),
+    };
+    state.audit.record(
+        &caller,
+        AuditAction::Attach,
+        Some(&id),
+        serde_json::json!({ "via": "vnc" }),
+        &connected,
+    );
+    match connected {
+        Ok(stream) => ws
+            .protocols(["binary"])
+            .on_upgrade(move |socket| handle_socket(socket, stream)),
+        Err(e) => Response::builder().status(502).body(e.into()).unwrap(),
+    }
+}
+
+/// Shuttle bytes both ways; the RFB protocol itself is spoken by the browser
+async fn handle_socket(mut socket: WebSocket, stream: UnixStream) {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        tokio::select! {
+            n = reader.read(&mut buf) => match n {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if socket.send(Message::Binary(buf[..n].to_vec())).await.is_err() {
+                        break; // Client disconnected
+                    }
+                }
+            },
+            msg = socket.recv() => {
+                let data = match msg {
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    _ => continue,
+                };
+                if writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    let _ = socket.close().await;
+}
@@ -0,0 +1,633 @@
+use axum::extract::{Query, State};
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::state::{Backend, Sandbox, SandboxStatus, SharedState};
+
+/// Header carrying `sha256=<hex HMAC>` of `<timestamp>.<body>`
+pub const SIGNATURE_HEADER: &str = "x-manager-signature";
+/// Header carrying the Unix time the signature was made at
+pub const TIMESTAMP_HEADER: &str = "x-manager-timestamp";
+pub const EVENT_HEADER: &str = "x-manager-event";
+/// Header carrying the delivery ID, the same for every attempt
+pub const DELIVERY_HEADER: &str = "x-manager-delivery";
+
+/// How long one attempt may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait between two attempts, however many there are
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum WebhookEvent {
+    #[serde(rename = "sandbox.created")]
+    Created,
+    #[serde(rename = "sandbox.started")]
+    Started,
+    #[serde(rename = "sandbox.stopped")]
+    Stopped,
+    #[serde(rename = "sandbox.died")]
+    Died,
+    #[serde(rename = "sandbox.deleted")]
+    Deleted,
+    #[serde(rename = "sandbox.attention")]
+    Attention,
+    #[serde(rename = "sandbox.budget_exceeded")]
+    BudgetExceeded,
+}
+
+impl std::fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookEvent::Created => write!(f, "sandbox.created"),
+            WebhookEvent::Started => write!(f, "sandbox.started"),
+            WebhookEvent::Stopped => write!(f, "sandbox.stopped"),
+            WebhookEvent::Died => write!(f, "sandbox.died"),
+            WebhookEvent::Deleted => write!(f, "sandbox.deleted"),
+            WebhookEvent::Attention => write!(f, "sandbox.attention"),
+            WebhookEvent::BudgetExceeded => write!(f, "sandbox.budget_exceeded"),
+        }
+    }
+}
+
+/// One endpoint and the events it wants
+#[derive(Debug, Clone, Deserialize)]
+pub struct Subscription {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature
+    pub secret: String,
+    /// Events to POST; every event if empty
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+impl Subscription {
+    fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub subscriptions: Vec<Subscription>,
+    /// Attempts per delivery, the first included
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for every further one up to
+    /// `MAX_BACKOFF`
+    pub backoff: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            subscriptions: Vec::new(),
+            max_attempts: 6,
+            backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// Subscriptions from the JSON file in `MANAGER_WEBHOOKS`, a list of
+    /// `{"url", "secret", "events"}`
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(attempts) = std::env::var("MANAGER_WEBHOOK_ATTEMPTS") {
+            match attempts.parse::<u32>() {
+                Ok(n) if n > 0 => config.max_attempts = n,
+                _ => tracing::warn!("MANAGER_WEBHOOK_ATTEMPTS must be a positive number, got {:?}", attempts),
+            }
+        }
+        let Some(path) = std::env::var("MANAGER_WEBHOOKS").ok().filter(|v| !v.is_empty()) else {
+            return config;
+        };
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_json::from_str::<Vec<Subscription>>(&s).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(subscriptions) => {
+                config.subscriptions = subscriptions
+                    .into_iter()
+                    .filter(|sub| {
+                        let ok = sub.url.starts_with("http://") || sub.url.starts_with("https://");
+                        if !ok {
+                            tracing::warn!("Ignoring webhook {:?}: not an http(s) URL", sub.url);
+                        }
+                        ok
+                    })
+                    .collect();
+                tracing::info!("Loaded {} webhook subscription(s) from {}", config.subscriptions.len(), path);
+            }
+            Err(e) => tracing::warn!("Failed to read webhooks {}: {}", path, e),
+        }
+        config
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Delivered,
+    /// Failed, another attempt follows
+    Retrying,
+    /// Failed for good
+    Failed,
+}
+
+/// What subscribers are told about a sandbox: none of the manager's own
+/// paths, sockets or units, which are nobody else's business and may change
+#[derive(Debug, Serialize)]
+pub struct SandboxInfo<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    pub backend: Backend,
+    pub project_dir: &'a str,
+    pub status: SandboxStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+impl<'a> From<&'a Sandbox> for SandboxInfo<'a> {
+    fn from(sb: &'a Sandbox) -> Self {
+        Self {
+            id: &sb.id,
+            name: &sb.name,
+            backend: sb.backend,
+            project_dir: &sb.project_dir,
+            status: sb.status,
+            created_at: sb.created_at,
+        }
+    }
+}
+
+/// One attempt at delivering an event to a subscription
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryAttempt {
+    pub timestamp: DateTime<Utc>,
+    pub delivery_id: String,
+    pub event: WebhookEvent,
+    pub sandbox_id: String,
+    pub url: String,
+    pub attempt: u32,
+    /// HTTP status of the response, if there was one
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub outcome: Outcome,
+}
+
+/// POSTs signed lifecycle events to the configured subscriptions, retrying
+/// with exponential backoff, and logs every attempt
+#[derive(Clone)]
+pub struct Webhooks {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: WebhookConfig,
+    client: reqwest::Client,
+    log_path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Webhooks {
+    pub fn new(config: WebhookConfig, log_path: PathBuf) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                client,
+                log_path,
+                lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Whether any subscription wants `event`
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.inner.config.subscriptions.iter().any(|sub| sub.wants(event))
+    }
+
+    /// Deliver `event` in the background
+    pub fn emit(&self, event: WebhookEvent, sandbox: &Sandbox, data: serde_json::Value) {
+        if !self.wants(event) {
+            return;
+        }
+        let webhooks = self.clone();
+        let sandbox = sandbox.clone();
+        tokio::spawn(async move { webhooks.send(event, &sandbox, data).await });
+    }
+
+    /// Deliver `event` to every subscription that wants it, returning once
+    /// each delivery succeeded or ran out of attempts
+    pub async fn send(&self, event: WebhookEvent, sandbox: &Sandbox, data: serde_json::Value) {
+        let mut deliveries = tokio::task::JoinSet::new();
+        for sub in self.inner.config.subscriptions.iter().filter(|sub| sub.wants(event)) {
+            let delivery_id = uuid::Uuid::new_v4().to_string();
+            let body = serde_json::json!({
+                "id": delivery_id,
+                "event": event,
+                "timestamp": Utc::now(),
+                "sandbox": SandboxInfo::from(sandbox),
+                "data": data,
+            })
+            .to_string();
+            let webhooks = self.clone();
+            let sub = sub.clone();
+            let sandbox_id = sandbox.id.clone();
+            deliveries.spawn(async move { webhooks.deliver(&sub, event, &sandbox_id, delivery_id, body).await });
+        }
+        while deliveries.join_next().await.is_some() {}
+    }
+
+    async fn deliver(&self, sub: &Subscription, event: WebhookEvent, sandbox_id: &str, delivery_id: String, body: String) {
+        let config = &self.inner.config;
+        for attempt in 1..=config.max_attempts {
+            let started = Instant::now();
+            let timestamp = Utc::now().timestamp();
+            let result = self
+                .inner
+                .client
+                .post(&sub.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event.to_string())
+                .header(DELIVERY_HEADER, &delivery_id)
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(SIGNATURE_HEADER, signature(&sub.secret, timestamp, &body))
+                .body(body.clone())
+                .send()
+                .await;
+            let (status, error, retryable) = match result {
+                Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None, false),
+                Ok(response) => {
+                    let status = response.status();
+                    // Other client errors won't go away by asking again
+                    let retryable = status.is_server_error()
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT;
+                    (Some(status.as_u16()), Some(format!("HTTP {}", status)), retryable)
+                }
+                Err(e) => (None, Some(error_chain(&e)), true),
+            };
+            let outcome = match error {
+                None => Outcome::Delivered,
+                Some(_) if retryable && attempt < config.max_attempts => Outcome::Retrying,
+                Some(_) => Outcome::Failed,
+            };
+            if outcome == Outcome::Failed {
+                tracing::warn!(
+                    "Webhook {} to {} failed after {} attempt(s): {}",
+                    event,
+                    sub.url,
+                    attempt,
+                    error.as_deref().unwrap_or_default()
+                );
+            }
+            self.record(&DeliveryAttempt {
+                timestamp: Utc::now(),
+                delivery_id: delivery_id.clone(),
+                event,
+                sandbox_id: sandbox_id.to_string(),
+                url: sub.url.clone(),
+                attempt,
+                status,
+                error,
+                duration_ms: started.elapsed().as_millis() as u64,
+                outcome,
+            });
+            if outcome != Outcome::Retrying {
+                return;
+            }
+            let backoff = config.backoff.saturating_mul(2u32.saturating_pow(attempt - 1));
+            tokio::time::sleep(backoff.min(MAX_BACKOFF)).await;
+        }
+    }
+
+    /// Append to the delivery log. Failures to write are logged, never
+    /// propagated.
+    fn record(&self, attempt: &DeliveryAttempt) {
+        let result = serde_json::to_string(attempt).map_err(std::io::Error::from).and_then(|mut line| {
+            line.push('\n');
+            let _guard = self.inner.lock.lock().unwrap_or_else(|e| e.into_inner());
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.inner.log_path)?
+                .write_all(line.as_bytes())
+        });
+        if let Err(e) = result {
+            tracing::warn!("Failed to write webhook delivery log: {}", e);
+        }
+    }
+
+    /// Read back logged attempts matching `filter`, oldest first
+    pub fn deliveries(&self, filter: &DeliveryFilter) -> Vec<DeliveryAttempt> {
+        let contents = match std::fs::read_to_string(&self.inner.log_path) {
+            Ok(c) => c,
+            Err(_) => return Vec::new(),
+        };
+        contents
+            .lines()
+            .filter_map(|line| serde_json::from_str::<DeliveryAttempt>(line).ok())
+            .filter(|a| filter.matches(a))
+            .collect()
+    }
+}
+
+/// An error with its causes, as reqwest's own message alone ("error sending
+/// request") doesn't say what went wrong
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `<timestamp>.<body>` keyed with
+/// `secret`
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    format!("sha256={}", hmac_hex(secret.as_bytes(), format!("{}.{}", timestamp, body).as_bytes()))
+}
+
+fn hmac_hex(key: &[u8], message: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Query parameters for `GET /api/webhooks/deliveries`
+#[derive(Debug, Default, Deserialize)]
+pub struct DeliveryFilter {
+    /// Full sandbox id or an id prefix
+    pub sandbox: Option<String>,
+    pub event: Option<WebhookEvent>,
+    pub outcome: Option<Outcome>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl DeliveryFilter {
+    fn matches(&self, attempt: &DeliveryAttempt) -> bool {
+        if self.sandbox.as_ref().is_some_and(|prefix| !attempt.sandbox_id.starts_with(prefix.as_str())) {
+            return false;
+        }
+        if self.event.is_some_and(|event| attempt.event != event) {
+            return false;
+        }
+        if self.outcome.is_some_and(|outcome| attempt.outcome != outcome) {
+            return false;
+        }
+        if self.since.is_some_and(|since| attempt.timestamp < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| attempt.timestamp > until) {
+            return false;
+        }
+        true
+    }
+}
+
+pub async fn get_deliveries(
+    State(state): State<SharedState>,
+    Query(filter): Query<DeliveryFilter>,
+) -> impl IntoResponse {
+    let webhooks = state.webhooks.clone();
+    let attempts = tokio::task::spawn_blocking(move || webhooks.deliveries(&filter))
+        .await
+        .unwrap_or_default();
+    Json(attempts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A request received by the stand-in
+    struct Received {
+        headers: HashMap<String, String>,
+        body: String,
+    }
+
+    /// Local HTTP stand-in answering with `statuses` in turn, the last one
+    /// repeated, and recording what it receives
+    async fn stand_in(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        tokio::spawn(async move {
+            for n in 0.. {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let mut stream = BufReader::new(stream);
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+                    }
+                }
+                let len = headers.get("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+                let mut body = vec![0; len];
+                stream.read_exact(&mut body).await.unwrap();
+                log.lock().unwrap().push(Received {
+                    headers,
+                    body: String::from_utf8(body).unwrap(),
+                });
+                let status = statuses[n.min(statuses.len() - 1)];
+                let response = format!("HTTP/1.1 {} Test\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                let _ = stream.get_mut().write_all(response.as_bytes()).await;
+            }
+        });
+        (url, received)
+    }
+
+    fn webhooks(name: &str, subscriptions: Vec<Subscription>) -> Webhooks {
+        let log = std::env::temp_dir().join(format!("webhooks-test-{}-{}.jsonl", std::process::id(), name));
+        let _ = std::fs::remove_file(&log);
+        let config = WebhookConfig {
+            subscriptions,
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+        };
+        Webhooks::new(config, log)
+    }
+
+    fn subscription(url: &str, events: Vec<WebhookEvent>) -> Subscription {
+        Subscription {
+            url: url.to_string(),
+            secret: "s3cret".to_string(),
+            events,
+        }
+    }
+
+    fn sandbox() -> Sandbox {
+        serde_json::from_value(serde_json::json!({
+            "id": "0123abcd-0000-0000-0000-000000000000",
+            "name": "test",
+            "backend": "bubblewrap",
+            "project_dir": "/tmp/project",
+            "status": "running",
+            "network": true,
+            "created_at": "2026-01-01T00:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    fn attempts(hooks: &Webhooks) -> Vec<(u32, Option<u16>, Outcome)> {
+        hooks
+            .deliveries(&DeliveryFilter::default())
+            .iter()
+            .map(|a| (a.attempt, a.status, a.outcome))
+            .collect()
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(
+            hmac_hex(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_payload() {
+        let (url, received) = stand_in(vec![204]).await;
+        let hooks = webhooks("signed", vec![subscription(&url, vec![])]);
+        hooks
+            .send(WebhookEvent::BudgetExceeded, &sandbox(), serde_json::json!({ "cost_usd": 2.5 }))
+            .await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let request = &received[0];
+        let timestamp: i64 = request.headers[TIMESTAMP_HEADER].parse().unwrap();
+        assert_eq!(request.headers[SIGNATURE_HEADER], signature("s3cret", timestamp, &request.body));
+        assert_eq!(request.headers[EVENT_HEADER], "sandbox.budget_exceeded");
+
+        let payload: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(payload["event"], "sandbox.budget_exceeded");
+        assert_eq!(payload["id"].as_str(), Some(request.headers[DELIVERY_HEADER].as_str()));
+        assert_eq!(payload["sandbox"]["name"], "test");
+        // Only the public view, none of the manager's internals
+        let fields: Vec<&str> = payload["sandbox"].as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(fields, vec!["backend", "created_at", "id", "name", "project_dir", "status"]);
+        assert_eq!(payload["data"]["cost_usd"], 2.5);
+        assert_eq!(attempts(&hooks), vec![(1, Some(204), Outcome::Delivered)]);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (url, received) = stand_in(vec![500, 503, 200]).await;
+        let hooks = webhooks("retry", vec![subscription(&url, vec![])]);
+        hooks.send(WebhookEvent::Stopped, &sandbox(), serde_json::json!({})).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        // Every attempt is the same delivery
+        assert!(received.iter().all(|r| r.headers[DELIVERY_HEADER] == received[0].headers[DELIVERY_HEADER]));
+        assert_eq!(
+            attempts(&hooks),
+            vec![
+                (1, Some(500), Outcome::Retrying),
+                (2, Some(503), Outcome::Retrying),
+                (3, Some(200), Outcome::Delivered),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let (url, received) = stand_in(vec![502]).await;
+        let hooks = webhooks("give-up", vec![subscription(&url, vec![])]);
+        hooks.send(WebhookEvent::Died, &sandbox(), serde_json::json!({})).await;
+
+        assert_eq!(received.lock().unwrap().len(), 3);
+        assert_eq!(attempts(&hooks).last(), Some(&(3, Some(502), Outcome::Failed)));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, received) = stand_in(vec![400, 200]).await;
+        let hooks = webhooks("client-error", vec![subscription(&url, vec![])]);
+        hooks.send(WebhookEvent::Created, &sandbox(), serde_json::json!({})).await;
+
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert_eq!(attempts(&hooks), vec![(1, Some(400), Outcome::Failed)]);
+    }
+
+    #[tokio::test]
+    async fn retries_unreachable_endpoints() {
+        // Bound and dropped, so nothing listens on the port
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let url = format!("http://127.0.0.1:{}/hook", port);
+        let hooks = webhooks("unreachable", vec![subscription(&url, vec![])]);
+        hooks.send(WebhookEvent::Deleted, &sandbox(), serde_json::json!({})).await;
+
+        let logged = hooks.deliveries(&DeliveryFilter::default());
+        assert_eq!(logged.len(), 3);
+        assert!(logged.iter().all(|a| a.status.is_none() && a.error.is_some()));
+        assert_eq!(logged[2].outcome, Outcome::Failed);
+    }
+
+    #[tokio::test]
+    async fn sends_only_subscribed_events() {
+        let (all_url, all) = stand_in(vec![200]).await;
+        let (some_url, some) = stand_in(vec![200]).await;
+        let hooks = webhooks(
+            "filter",
+            vec![
+                subscription(&all_url, vec![]),
+                subscription(&some_url, vec![WebhookEvent::Attention]),
+            ],
+        );
+        assert!(hooks.wants(WebhookEvent::Started));
+        hooks.send(WebhookEvent::Started, &sandbox(), serde_json::json!({})).await;
+        hooks.send(WebhookEvent::Attention, &sandbox(), serde_json::json!({})).await;
+
+        assert_eq!(all.lock().unwrap().len(), 2);
+        let some = some.lock().unwrap();
+        assert_eq!(some.len(), 1);
+        assert_eq!(some[0].headers[EVENT_HEADER], "sandbox.attention");
+
+        let filter = DeliveryFilter {
+            event: Some(WebhookEvent::Attention),
+            ..Default::default()
+        };
+        assert_eq!(hooks.deliveries(&filter).len(), 2);
+        let filter = DeliveryFilter {
+            sandbox: Some("0123abcd".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(hooks.deliveries(&filter).len(), 3);
+    }
+}
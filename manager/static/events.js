@@ -0,0 +1,99 @@
+(function () {
+  "use strict";
+
+  var activity = document.getElementById("claude-activity");
+  if (!activity) return;
+
+  var sandboxId = activity.dataset.sandboxId;
+  if (!sandboxId) return;
+
+  var retryDelay = 1000;
+  var maxRetryDelay = 30000;
+  // Tool calls without a result yet, by call ID, in start order
+  var running = {};
+  var order = [];
+
+  function showActivity() {
+    order = order.filter(function (id) { return running[id]; });
+    var last = running[order[order.length - 1]];
+    activity.textContent = "";
+    if (!last) {
+      activity.textContent = "idle";
+      activity.style.color = "var(--muted)";
+      return;
+    }
+    activity.style.color = "var(--yellow)";
+    activity.appendChild(document.createTextNode("running: " + last.tool + " "));
+    if (last.summary) {
+      var code = document.createElement("code");
+      code.textContent = last.summary.split("\n")[0];
+      activity.appendChild(code);
+    }
+    if (order.length > 1) {
+      activity.appendChild(document.createTextNode(" +" + (order.length - 1)));
+    }
+  }
+
+  function refreshFragments() {
+    if (!window.htmx) return;
+    htmx.trigger("#claude-metrics", "refresh");
+    htmx.trigger("#tools", "refresh");
+  }
+
+  function handle(event) {
+    switch (event.type) {
+      case "snapshot":
+        running = {};
+        order = [];
+        (event.running || []).forEach(function (t) {
+          running[t.id] = t;
+          order.push(t.id);
+        });
+        showActivity();
+        refreshFragments();
+        break;
+      case "tool_started":
+        running[event.id] = event;
+        order.push(event.id);
+        showActivity();
+        break;
+      case "tool_finished":
+        delete running[event.id];
+        showActivity();
+        break;
+      case "metrics":
+        refreshFragments();
+        break;
+    }
+  }
+
+  function connect() {
+    var proto = location.protocol === "https:" ? "wss:" : "ws:";
+    var url = proto + "//" + location.host + "/ws/sandboxes/" + sandboxId + "/events";
+    var ws = new WebSocket(url);
+
+    ws.onopen = function () {
+      retryDelay = 1000;
+    };
+
+    ws.onmessage = function (e) {
+      try {
+        handle(JSON.parse(e.data));
+      } catch (err) {
+        // Ignore malformed events
+      }
+    };
+
+    ws.onclose = function () {
+      activity.textContent = "";
+      setTimeout(connect, retryDelay);
+      retryDelay = Math.min(retryDelay * 2, maxRetryDelay);
+    };
+
+    ws.onerror = function () {
+      ws.close();
+    };
+  }
+
+  connect();
+})();
@@ -0,0 +1,83 @@
+// Read-only live view of the sandbox's tmux pane.
+//
+// The server pushes {"rows": n, "lines": [[index, html], ...]} with only
+// the lines that changed; each line is self-contained HTML, so it can be
+// swapped in place. Text frames starting with "[" are status messages.
+(function () {
+  "use strict";
+
+  var output = document.getElementById("screen-output");
+  var status = document.getElementById("screen-status");
+  if (!output) return;
+
+  var sandboxId = output.dataset.sandboxId;
+  if (!sandboxId) return;
+
+  var ws = null;
+  var retryDelay = 1000;
+  var maxRetryDelay = 30000;
+  var ended = false;
+  var rows = [];
+
+  function setStatus(text, color) {
+    if (status) {
+      status.textContent = text;
+      status.style.color = color || "inherit";
+    }
+  }
+
+  function resize(n) {
+    while (rows.length < n) {
+      var div = document.createElement("div");
+      div.className = "screen-line";
+      output.appendChild(div);
+      rows.push(div);
+    }
+    while (rows.length > n) {
+      output.removeChild(rows.pop());
+    }
+  }
+
+  function apply(update) {
+    resize(update.rows);
+    update.lines.forEach(function (entry) {
+      var row = rows[entry[0]];
+      if (row) row.innerHTML = entry[1] || " ";
+    });
+  }
+
+  function connect() {
+    var proto = location.protocol === "https:" ? "wss:" : "ws:";
+    var url = proto + "//" + location.host + "/ws/sandboxes/" + sandboxId + "/screen";
+
+    setStatus("connecting...", "var(--yellow)");
+    ws = new WebSocket(url);
+
+    ws.onopen = function () {
+      setStatus("live", "var(--green)");
+      retryDelay = 1000;
+    };
+
+    ws.onmessage = function (e) {
+      if (e.data.charAt(0) === "[") {
+        setStatus(e.data.slice(1, -1), "var(--muted)");
+        ended = true;
+        return;
+      }
+      apply(JSON.parse(e.data));
+    };
+
+    ws.onclose = function () {
+      if (ended) return;
+      setStatus("disconnected — retrying in " + (retryDelay / 1000) + "s", "var(--muted)");
+      setTimeout(connect, retryDelay);
+      retryDelay = Math.min(retryDelay * 2, maxRetryDelay);
+    };
+
+    ws.onerror = function () {
+      ws.close();
+    };
+  }
+
+  connect();
+})();
@@ -0,0 +1,668 @@
+// Interactive terminal attached to the sandbox's tmux session.
+//
+// A small VT100/xterm emulator: enough of the escape-sequence set that tmux
+// emits for TERM=xterm-256color (cursor movement, scroll regions, erase,
+// insert/delete, SGR colours, alternate screen). Output arrives as binary
+// WebSocket frames; keystrokes go back as binary frames and size changes as
+// {"type":"resize"} text frames.
+(function () {
+  "use strict";
+
+  var root = document.getElementById("terminal");
+  var status = document.getElementById("terminal-status");
+  var toggle = document.getElementById("terminal-toggle");
+  if (!root || !toggle) return;
+
+  var sandboxId = root.dataset.sandboxId;
+  if (!sandboxId) return;
+
+  // ---------------------------------------------------------------------
+  // Colours
+  // ---------------------------------------------------------------------
+
+  var BASE16 = [
+    "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+    "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+  ];
+
+  function paletteColor(n) {
+    if (n < 16) return BASE16[n];
+    if (n < 232) {
+      n -= 16;
+      var steps = [0, 95, 135, 175, 215, 255];
+      return "rgb(" + steps[Math.floor(n / 36)] + "," + steps[Math.floor(n / 6) % 6] + "," + steps[n % 6] + ")";
+    }
+    var v = 8 + (n - 232) * 10;
+    return "rgb(" + v + "," + v + "," + v + ")";
+  }
+
+  // DEC special graphics, selected by ESC ( 0 — tmux uses it for borders
+  var DEC_GRAPHICS = {
+    "j": "┘", "k": "┐", "l": "┌", "m": "└", "n": "┼", "q": "─", "t": "├",
+    "u": "┤", "v": "┴", "w": "┬", "x": "│", "a": "▒", "~": "·", "`": "◆",
+  };
+
+  // ---------------------------------------------------------------------
+  // Screen model
+  // ---------------------------------------------------------------------
+
+  function defaultAttr() {
+    return { fg: null, bg: null, bold: false, dim: false, italic: false, underline: false, inverse: false };
+  }
+
+  function blankCell(attr) {
+    return { ch: " ", attr: attr || defaultAttr() };
+  }
+
+  function Screen(cols, rows) {
+    this.cols = cols;
+    this.rows = rows;
+    this.lines = [];
+    for (var i = 0; i < rows; i++) this.lines.push(this.blankLine());
+  }
+
+  Screen.prototype.blankLine = function (attr) {
+    var line = [];
+    for (var i = 0; i < this.cols; i++) line.push(blankCell(attr));
+    return line;
+  };
+
+  Screen.prototype.resize = function (cols, rows) {
+    var lines = [];
+    // Keep the bottom of the screen, where the prompt usually is
+    var start = Math.max(0, this.lines.length - rows);
+    for (var y = 0; y < rows; y++) {
+      var src = this.lines[start + y];
+      var line = [];
+      for (var x = 0; x < cols; x++) line.push(src && src[x] ? src[x] : blankCell());
+      lines.push(line);
+    }
+    this.cols = cols;
+    this.rows = rows;
+    this.lines = lines;
+  };
+
+  var term = {
+    cols: 80,
+    rows: 24,
+    main: null,
+    alt: null,
+    screen: null,
+    x: 0,
+    y: 0,
+    wrapPending: false,
+    attr: defaultAttr(),
+    saved: null,
+    top: 0,
+    bottom: 23,
+    cursorVisible: true,
+    appCursor: false,
+    bracketedPaste: false,
+    autowrap: true,
+    charsetG0: "B",
+    dirty: true,
+  };
+
+  function resetTerminal(cols, rows) {
+    term.cols = cols;
+    term.rows = rows;
+    term.main = new Screen(cols, rows);
+    term.alt = new Screen(cols, rows);
+    term.screen = term.main;
+    term.x = 0;
+    term.y = 0;
+    term.wrapPending = false;
+    term.attr = defaultAttr();
+    term.saved = null;
+    term.top = 0;
+    term.bottom = rows - 1;
+    term.cursorVisible = true;
+    term.appCursor = false;
+    term.bracketedPaste = false;
+    term.autowrap = true;
+    term.charsetG0 = "B";
+    term.dirty = true;
+  }
+
+  function resizeTerminal(cols, rows) {
+    term.main.resize(cols, rows);
+    term.alt.resize(cols, rows);
+    term.cols = cols;
+    term.rows = rows;
+    term.top = 0;
+    term.bottom = rows - 1;
+    term.x = Math.min(term.x, cols - 1);
+    term.y = Math.min(term.y, rows - 1);
+    term.dirty = true;
+  }
+
+  function cloneAttr(a) {
+    return {
+      fg: a.fg, bg: a.bg, bold: a.bold, dim: a.dim,
+      italic: a.italic, underline: a.underline, inverse: a.inverse,
+    };
+  }
+
+  function clamp(v, lo, hi) {
+    return Math.max(lo, Math.min(hi, v));
+  }
+
+  function scrollUp(n) {
+    var lines = term.screen.lines;
+    for (var i = 0; i < n; i++) {
+      lines.splice(term.top, 1);
+      lines.splice(term.bottom, 0, term.screen.blankLine(eraseAttr()));
+    }
+  }
+
+  function scrollDown(n) {
+    var lines = term.screen.lines;
+    for (var i = 0; i < n; i++) {
+      lines.splice(term.bottom, 1);
+      lines.splice(term.top, 0, term.screen.blankLine(eraseAttr()));
+    }
+  }
+
+  // Erased cells keep the current background colour (xterm behaviour)
+  function eraseAttr() {
+    var a = defaultAttr();
+    a.bg = term.attr.bg;
+    return a;
+  }
+
+  function lineFeed() {
+    if (term.y === term.bottom) {
+      scrollUp(1);
+    } else if (term.y < term.rows - 1) {
+      term.y++;
+    }
+  }
+
+  function reverseIndex() {
+    if (term.y === term.top) {
+      scrollDown(1);
+    } else if (term.y > 0) {
+      term.y--;
+    }
+  }
+
+  function putChar(ch) {
+    if (term.charsetG0 === "0" && DEC_GRAPHICS[ch]) ch = DEC_GRAPHICS[ch];
+    if (term.wrapPending) {
+      term.x = 0;
+      lineFeed();
+      term.wrapPending = false;
+    }
+    term.screen.lines[term.y][term.x] = { ch: ch, attr: cloneAttr(term.attr) };
+    if (term.x === term.cols - 1) {
+      if (term.autowrap) term.wrapPending = true;
+    } else {
+      term.x++;
+    }
+  }
+
+  function eraseCells(y, from, to) {
+    var line = term.screen.lines[y];
+    for (var x = from; x < to && x < term.cols; x++) line[x] = blankCell(eraseAttr());
+  }
+
+  function eraseInDisplay(mode) {
+    var y;
+    if (mode === 0) {
+      eraseCells(term.y, term.x, term.cols);
+      for (y = term.y + 1; y < term.rows; y++) eraseCells(y, 0, term.cols);
+    } else if (mode === 1) {
+      for (y = 0; y < term.y; y++) eraseCells(y, 0, term.cols);
+      eraseCells(term.y, 0, term.x + 1);
+    } else if (mode === 2 || mode === 3) {
+      for (y = 0; y < term.rows; y++) eraseCells(y, 0, term.cols);
+    }
+  }
+
+  function eraseInLine(mode) {
+    if (mode === 0) eraseCells(term.y, term.x, term.cols);
+    else if (mode === 1) eraseCells(term.y, 0, term.x + 1);
+    else if (mode === 2) eraseCells(term.y, 0, term.cols);
+  }
+
+  function insertLines(n) {
+    if (term.y < term.top || term.y > term.bottom) return;
+    var lines = term.screen.lines;
+    for (var i = 0; i < n; i++) {
+      lines.splice(term.bottom, 1);
+      lines.splice(term.y, 0, term.screen.blankLine(eraseAttr()));
+    }
+  }
+
+  function deleteLines(n) {
+    if (term.y < term.top || term.y > term.bottom) return;
+    var lines = term.screen.lines;
+    for (var i = 0; i < n; i++) {
+      lines.splice(term.y, 1);
+      lines.splice(term.bottom, 0, term.screen.blankLine(eraseAttr()));
+    }
+  }
+
+  function insertChars(n) {
+    var line = term.screen.lines[term.y];
+    for (var i = 0; i < n; i++) {
+      line.splice(term.x, 0, blankCell(eraseAttr()));
+      line.pop();
+    }
+  }
+
+  function deleteChars(n) {
+    var line = term.screen.lines[term.y];
+    for (var i = 0; i < n; i++) {
+      line.splice(term.x, 1);
+      line.push(blankCell(eraseAttr()));
+    }
+  }
+
+  function saveCursor() {
+    term.saved = { x: term.x, y: term.y, attr: cloneAttr(term.attr), charsetG0: term.charsetG0 };
+  }
+
+  function restoreCursor() {
+    if (!term.saved) return;
+    term.x = term.saved.x;
+    term.y = term.saved.y;
+    term.attr = cloneAttr(term.saved.attr);
+    term.charsetG0 = term.saved.charsetG0;
+    term.wrapPending = false;
+  }
+
+  function setAltScreen(on) {
+    if (on && term.screen !== term.alt) {
+      saveCursor();
+      term.alt = new Screen(term.cols, term.rows);
+      term.screen = term.alt;
+    } else if (!on && term.screen === term.alt) {
+      term.screen = term.main;
+      restoreCursor();
+    }
+  }
+
+  function setMode(priv, params, on) {
+    params.forEach(function (p) {
+      if (!priv) return; // ANSI modes (insert, etc.) are not used by tmux here
+      switch (p) {
+        case 1: term.appCursor = on; break;
+        case 7: term.autowrap = on; break;
+        case 25: term.cursorVisible = on; break;
+        case 47: case 1047: case 1049: setAltScreen(on); break;
+        case 2004: term.bracketedPaste = on; break;
+      }
+    });
+  }
+
+  function sgr(params) {
+    if (params.length === 0) params = [0];
+    for (var i = 0; i < params.length; i++) {
+      var p = params[i];
+      var a = term.attr;
+      if (p === 0) term.attr = defaultAttr();
+      else if (p === 1) a.bold = true;
+      else if (p === 2) a.dim = true;
+      else if (p === 3) a.italic = true;
+      else if (p === 4) a.underline = true;
+      else if (p === 7) a.inverse = true;
+      else if (p === 22) { a.bold = false; a.dim = false; }
+      else if (p === 23) a.italic = false;
+      else if (p === 24) a.underline = false;
+      else if (p === 27) a.inverse = false;
+      else if (p >= 30 && p <= 37) a.fg = paletteColor(p - 30);
+      else if (p === 39) a.fg = null;
+      else if (p >= 40 && p <= 47) a.bg = paletteColor(p - 40);
+      else if (p === 49) a.bg = null;
+      else if (p >= 90 && p <= 97) a.fg = paletteColor(p - 90 + 8);
+      else if (p >= 100 && p <= 107) a.bg = paletteColor(p - 100 + 8);
+      else if (p === 38 || p === 48) {
+        var color = null;
+        if (params[i + 1] === 5) {
+          color = paletteColor(params[i + 2] || 0);
+          i += 2;
+        } else if (params[i + 1] === 2) {
+          color = "rgb(" + (params[i + 2] || 0) + "," + (params[i + 3] || 0) + "," + (params[i + 4] || 0) + ")";
+          i += 4;
+        }
+        if (p === 38) a.fg = color;
+        else a.bg = color;
+      }
+    }
+  }
+
+  function csi(priv, params, final) {
+    var n = params[0] || 1;
+    switch (final) {
+      case "@": insertChars(n); break;
+      case "A": term.y = clamp(term.y - n, 0, term.rows - 1); break;
+      case "B": case "e": term.y = clamp(term.y + n, 0, term.rows - 1); break;
+      case "C": case "a": term.x = clamp(term.x + n, 0, term.cols - 1); break;
+      case "D": term.x = clamp(term.x - n, 0, term.cols - 1); break;
+      case "E": term.x = 0; term.y = clamp(term.y + n, 0, term.rows - 1); break;
+      case "F": term.x = 0; term.y = clamp(term.y - n, 0, term.rows - 1); break;
+      case "G": case "`": term.x = clamp(n - 1, 0, term.cols - 1); break;
+      case "H": case "f":
+        term.y = clamp((params[0] || 1) - 1, 0, term.rows - 1);
+        term.x = clamp((params[1] || 1) - 1, 0, term.cols - 1);
+        break;
+      case "J": eraseInDisplay(params[0] || 0); break;
+      case "K": eraseInLine(params[0] || 0); break;
+      case "L": insertLines(n); break;
+      case "M": deleteLines(n); break;
+      case "P": deleteChars(n); break;
+      case "S": scrollUp(n); break;
+      case "T": scrollDown(n); break;
+      case "X": eraseCells(term.y, term.x, term.x + n); break;
+      case "b": {
+        var prev = term.x > 0 ? term.screen.lines[term.y][term.x - 1].ch : " ";
+        for (var i = 0; i < n; i++) putChar(prev);
+        break;
+      }
+      case "d": term.y = clamp(n - 1, 0, term.rows - 1); break;
+      case "h": setMode(priv, params, true); break;
+      case "l": setMode(priv, params, false); break;
+      case "m": if (!priv) sgr(params); break;
+      case "n":
+        if (params[0] === 6) send("\x1b[" + (term.y + 1) + ";" + (term.x + 1) + "R");
+        break;
+      case "r":
+        term.top = clamp((params[0] || 1) - 1, 0, term.rows - 1);
+        term.bottom = clamp((params[1] || term.rows) - 1, term.top, term.rows - 1);
+        term.x = 0;
+        term.y = 0;
+        break;
+      case "s": saveCursor(); break;
+      case "u": restoreCursor(); break;
+    }
+    if (final !== "m") term.wrapPending = false;
+  }
+
+  // ---------------------------------------------------------------------
+  // Escape-sequence parser
+  // ---------------------------------------------------------------------
+
+  var parser = { state: "ground", buf: "" };
+
+  function feed(text) {
+    for (var i = 0; i < text.length; i++) {
+      var ch = text[i];
+      var code = text.charCodeAt(i);
+      switch (parser.state) {
+        case "ground":
+          if (code === 0x1b) {
+            parser.state = "escape";
+          } else if (code === 0x0d) {
+            term.x = 0;
+            term.wrapPending = false;
+          } else if (code === 0x0a || code === 0x0b || code === 0x0c) {
+            lineFeed();
+            term.wrapPending = false;
+          } else if (code === 0x08) {
+            if (term.x > 0) term.x--;
+            term.wrapPending = false;
+          } else if (code === 0x09) {
+            term.x = Math.min(term.cols - 1, (Math.floor(term.x / 8) + 1) * 8);
+          } else if (code >= 0x20 && code !== 0x7f) {
+            putChar(ch);
+          }
+          break;
+        case "escape":
+          parser.state = "ground";
+          if (ch === "[") { parser.state = "csi"; parser.buf = ""; }
+          else if (ch === "]") { parser.state = "osc"; }
+          else if (ch === "P" || ch === "_" || ch === "^") { parser.state = "string"; }
+          else if (ch === "(") { parser.state = "charset"; }
+          else if (ch === ")" || ch === "*" || ch === "+") { parser.state = "skip1"; }
+          else if (ch === "7") saveCursor();
+          else if (ch === "8") restoreCursor();
+          else if (ch === "D") lineFeed();
+          else if (ch === "E") { term.x = 0; lineFeed(); }
+          else if (ch === "M") reverseIndex();
+          else if (ch === "c") resetTerminal(term.cols, term.rows);
+          break;
+        case "csi":
+          if (code >= 0x40 && code <= 0x7e) {
+            var priv = /^[?>=<]/.test(parser.buf);
+            var body = priv ? parser.buf.slice(1) : parser.buf;
+            // Intermediate bytes (e.g. the space in "CSI 2 SP q") make it
+            // a sequence we don't model
+            if (!/[ -\/]/.test(body)) {
+              var params = body.length
+                ? body.split(";").map(function (p) { return parseInt(p.split(":")[0], 10) || 0; })
+                : [];
+              if (priv && parser.buf[0] !== "?") {
+                // Secondary DA and friends: nothing to do
+              } else {
+                csi(priv, params, ch);
+              }
+            }
+            parser.state = "ground";
+          } else {
+            parser.buf += ch;
+          }
+          break;
+        case "osc":
+          // Terminated by BEL or ST (ESC \)
+          if (code === 0x07) parser.state = "ground";
+          else if (code === 0x1b) parser.state = "string-esc";
+          break;
+        case "string":
+          if (code === 0x1b) parser.state = "string-esc";
+          break;
+        case "string-esc":
+          parser.state = ch === "\\" ? "ground" : "string";
+          break;
+        case "charset":
+          term.charsetG0 = ch;
+          parser.state = "ground";
+          break;
+        case "skip1":
+          parser.state = "ground";
+          break;
+      }
+    }
+    term.dirty = true;
+  }
+
+  // ---------------------------------------------------------------------
+  // Rendering
+  // ---------------------------------------------------------------------
+
+  function escapeHtml(s) {
+    return s.replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;");
+  }
+
+  function styleFor(attr, cursor) {
+    var fg = attr.fg;
+    var bg = attr.bg;
+    if (attr.inverse !== cursor) {
+      var t = fg || "var(--term-fg)";
+      fg = bg || "var(--term-bg)";
+      bg = t;
+    }
+    var css = "";
+    if (fg) css += "color:" + fg + ";";
+    if (bg) css += "background:" + bg + ";";
+    if (attr.bold) css += "font-weight:bold;";
+    if (attr.dim) css += "opacity:0.7;";
+    if (attr.italic) css += "font-style:italic;";
+    if (attr.underline) css += "text-decoration:underline;";
+    return css;
+  }
+
+  function render() {
+    if (!term.dirty) return;
+    term.dirty = false;
+    var focused = document.activeElement === root;
+    var html = "";
+    for (var y = 0; y < term.rows; y++) {
+      var line = term.screen.lines[y];
+      var run = "";
+      var runStyle = null;
+      for (var x = 0; x < term.cols; x++) {
+        var cell = line[x];
+        var isCursor = term.cursorVisible && focused && x === term.x && y === term.y;
+        var style = styleFor(cell.attr, isCursor);
+        if (style !== runStyle) {
+          if (run) html += runStyle ? '<span style="' + runStyle + '">' + escapeHtml(run) + "</span>" : escapeHtml(run);
+          run = "";
+          runStyle = style;
+        }
+        run += cell.ch;
+      }
+      if (run) html += runStyle ? '<span style="' + runStyle + '">' + escapeHtml(run) + "</span>" : escapeHtml(run);
+      html += "\n";
+    }
+    root.innerHTML = html;
+  }
+
+  function frame() {
+    render();
+    window.requestAnimationFrame(frame);
+  }
+
+  // ---------------------------------------------------------------------
+  // Sizing
+  // ---------------------------------------------------------------------
+
+  function measure() {
+    var probe = document.createElement("span");
+    probe.textContent = "WWWWWWWWWW";
+    probe.style.visibility = "hidden";
+    root.appendChild(probe);
+    var rect = probe.getBoundingClientRect();
+    root.removeChild(probe);
+    var cellWidth = rect.width / 10 || 8;
+    var cellHeight = rect.height || 16;
+    var style = getComputedStyle(root);
+    var width = root.clientWidth - parseFloat(style.paddingLeft) - parseFloat(style.paddingRight);
+    var height = root.clientHeight - parseFloat(style.paddingTop) - parseFloat(style.paddingBottom);
+    return {
+      cols: Math.max(20, Math.floor(width / cellWidth)),
+      rows: Math.max(5, Math.floor(height / cellHeight)),
+    };
+  }
+
+  function fit() {
+    var size = measure();
+    if (size.cols !== term.cols || size.rows !== term.rows) {
+      resizeTerminal(size.cols, size.rows);
+    }
+    if (ws && ws.readyState === WebSocket.OPEN) {
+      ws.send(JSON.stringify({ type: "resize", cols: term.cols, rows: term.rows }));
+    }
+  }
+
+  // ---------------------------------------------------------------------
+  // Input
+  // ---------------------------------------------------------------------
+
+  var encoder = new TextEncoder();
+
+  function send(data) {
+    if (ws && ws.readyState === WebSocket.OPEN) ws.send(encoder.encode(data));
+  }
+
+  function keySequence(e) {
+    var arrows = { ArrowUp: "A", ArrowDown: "B", ArrowRight: "C", ArrowLeft: "D", Home: "H", End: "F" };
+    if (arrows[e.key]) return (term.appCursor ? "\x1bO" : "\x1b[") + arrows[e.key];
+    var tilde = { Insert: 2, Delete: 3, PageUp: 5, PageDown: 6, F5: 15, F6: 17, F7: 18, F8: 19, F9: 20, F10: 21, F11: 23, F12: 24 };
+    if (tilde[e.key]) return "\x1b[" + tilde[e.key] + "~";
+    var ss3 = { F1: "P", F2: "Q", F3: "R", F4: "S" };
+    if (ss3[e.key]) return "\x1bO" + ss3[e.key];
+    switch (e.key) {
+      case "Enter": return "\r";
+      case "Backspace": return e.ctrlKey ? "\x08" : "\x7f";
+      case "Tab": return e.shiftKey ? "\x1b[Z" : "\t";
+      case "Escape": return "\x1b";
+    }
+    if (e.key.length !== 1) return null;
+    if (e.ctrlKey && !e.altKey) {
+      var c = e.key.toUpperCase().charCodeAt(0);
+      if (c >= 0x40 && c <= 0x5f) return String.fromCharCode(c - 0x40);
+      if (e.key === " ") return "\x00";
+      return null;
+    }
+    return e.altKey ? "\x1b" + e.key : e.key;
+  }
+
+  root.addEventListener("keydown", function (e) {
+    // Leave Ctrl+Shift combos (copy/paste) and Meta to the browser
+    if ((e.ctrlKey && e.shiftKey) || e.metaKey) return;
+    var seq = keySequence(e);
+    if (seq === null) return;
+    e.preventDefault();
+    send(seq);
+  });
+
+  root.addEventListener("paste", function (e) {
+    var text = (e.clipboardData || window.clipboardData).getData("text");
+    e.preventDefault();
+    if (term.bracketedPaste) text = "\x1b[200~" + text + "\x1b[201~";
+    send(text);
+  });
+
+  root.addEventListener("focus", function () { term.dirty = true; });
+  root.addEventListener("blur", function () { term.dirty = true; });
+
+  // ---------------------------------------------------------------------
+  // Connection
+  // ---------------------------------------------------------------------
+
+  var ws = null;
+  var decoder = null;
+
+  function setStatus(text, color) {
+    if (status) {
+      status.textContent = text;
+      status.style.color = color || "inherit";
+    }
+  }
+
+  function connect() {
+    var proto = location.protocol === "https:" ? "wss:" : "ws:";
+    var url = proto + "//" + location.host + "/ws/sandboxes/" + sandboxId + "/terminal";
+
+    var size = measure();
+    resetTerminal(size.cols, size.rows);
+    decoder = new TextDecoder("utf-8");
+    setStatus("connecting...", "var(--yellow)");
+    ws = new WebSocket(url);
+    ws.binaryType = "arraybuffer";
+
+    ws.onopen = function () {
+      setStatus("attached", "var(--green)");
+      toggle.textContent = "Detach";
+      fit();
+      root.focus();
+    };
+
+    ws.onmessage = function (e) {
+      if (typeof e.data === "string") {
+        setStatus(e.data, "var(--muted)");
+        return;
+      }
+      feed(decoder.decode(new Uint8Array(e.data), { stream: true }));
+    };
+
+    ws.onclose = function () {
+      setStatus("detached", "var(--muted)");
+      toggle.textContent = "Attach";
+      ws = null;
+    };
+  }
+
+  toggle.addEventListener("click", function () {
+    if (ws) ws.close();
+    else connect();
+  });
+
+  window.addEventListener("resize", function () {
+    if (ws) fit();
+  });
+
+  resetTerminal(term.cols, term.rows);
+  window.requestAnimationFrame(frame);
+})();
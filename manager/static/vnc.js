@@ -0,0 +1,433 @@
+// Live view of the sandbox display over VNC.
+//
+// A minimal RFB 3.3/3.7/3.8 client (RFC 6143) drawing into a <canvas>. The
+// WebSocket at /ws/sandboxes/<id>/vnc is a plain byte pipe to x11vnc (Xvfb
+// sandboxes) or QEMU's VNC server (VMs), so the whole protocol lives here.
+// Supports "None" security, Raw and CopyRect encodings and DesktopSize;
+// keyboard, mouse, wheel and paste (as ClientCutText) are forwarded.
+(function () {
+  "use strict";
+
+  var canvas = document.getElementById("vnc-canvas");
+  var status = document.getElementById("vnc-status");
+  var toggle = document.getElementById("vnc-toggle");
+  if (!canvas || !toggle) return;
+
+  var sandboxId = canvas.dataset.sandboxId;
+  if (!sandboxId) return;
+
+  var ctx = canvas.getContext("2d");
+  var ws = null;
+
+  function setStatus(text, color) {
+    if (status) {
+      status.textContent = text;
+      status.style.color = color || "inherit";
+    }
+  }
+
+  // ---------------------------------------------------------------------
+  // Receive buffer: a queue of chunks read from the front
+  // ---------------------------------------------------------------------
+
+  var chunks = [];
+  var available = 0;
+
+  function push(data) {
+    chunks.push(new Uint8Array(data));
+    available += data.byteLength;
+  }
+
+  function take(n) {
+    var out = new Uint8Array(n);
+    var filled = 0;
+    while (filled < n) {
+      var head = chunks[0];
+      var count = Math.min(head.length, n - filled);
+      out.set(head.subarray(0, count), filled);
+      filled += count;
+      if (count === head.length) chunks.shift();
+      else chunks[0] = head.subarray(count);
+    }
+    available -= n;
+    return out;
+  }
+
+  function peek(n) {
+    var out = take(n);
+    chunks.unshift(out);
+    available += n;
+    return out;
+  }
+
+  function u16(b, o) { return (b[o] << 8) | b[o + 1]; }
+  function u32(b, o) { return ((b[o] << 24) >>> 0) + ((b[o + 1] << 16) | (b[o + 2] << 8) | b[o + 3]); }
+  function s32(b, o) { return u32(b, o) | 0; }
+
+  function send(bytes) {
+    if (ws && ws.readyState === WebSocket.OPEN) ws.send(new Uint8Array(bytes));
+  }
+
+  function be16(n) { return [(n >> 8) & 0xff, n & 0xff]; }
+  function be32(n) { return [(n >>> 24) & 0xff, (n >> 16) & 0xff, (n >> 8) & 0xff, n & 0xff]; }
+
+  function latin1(bytes) {
+    var s = "";
+    for (var i = 0; i < bytes.length; i++) s += String.fromCharCode(bytes[i]);
+    return s;
+  }
+
+  // ---------------------------------------------------------------------
+  // Protocol state machine. Each step returns false until enough bytes
+  // have arrived, and is re-run on the next message.
+  // ---------------------------------------------------------------------
+
+  var ENCODING_RAW = 0;
+  var ENCODING_COPYRECT = 1;
+  var ENCODING_DESKTOP_SIZE = -223;
+
+  var step = null;
+  var minor = 8;
+  var rects = 0; // Rectangles left in the current FramebufferUpdate
+  var rect = null; // Header of the rectangle being read
+
+  function fail(message) {
+    setStatus(message, "var(--red)");
+    step = null;
+    if (ws) ws.close();
+    return false;
+  }
+
+  function readVersion() {
+    if (available < 12) return false;
+    var version = latin1(take(12));
+    var m = /^RFB (\d{3})\.(\d{3})\n$/.exec(version);
+    if (!m) return fail("not an RFB server");
+    var serverMinor = parseInt(m[1], 10) > 3 ? 8 : parseInt(m[2], 10);
+    minor = serverMinor >= 8 ? 8 : serverMinor >= 7 ? 7 : 3;
+    send(Array.prototype.map.call("RFB 003.00" + minor + "\n", function (c) { return c.charCodeAt(0); }));
+    step = minor === 3 ? readSecurityType33 : readSecurityTypes;
+    return true;
+  }
+
+  function readSecurityTypes() {
+    if (available < 1) return false;
+    var n = peek(1)[0];
+    if (n === 0) {
+      step = readFailureReason;
+      take(1);
+      return true;
+    }
+    if (available < 1 + n) return false;
+    var types = take(1 + n).subarray(1);
+    if (Array.prototype.indexOf.call(types, 1) < 0) {
+      return fail("server requires authentication (types " + Array.prototype.join.call(types, ",") + ")");
+    }
+    send([1]);
+    step = minor === 8 ? readSecurityResult : sendClientInit;
+    return true;
+  }
+
+  function readSecurityType33() {
+    if (available < 4) return false;
+    var type = u32(take(4), 0);
+    if (type === 0) step = readFailureReason;
+    else if (type === 1) step = sendClientInit;
+    else return fail("server requires authentication (type " + type + ")");
+    return true;
+  }
+
+  function readSecurityResult() {
+    if (available < 4) return false;
+    if (u32(take(4), 0) !== 0) {
+      step = readFailureReason;
+      return true;
+    }
+    step = sendClientInit;
+    return true;
+  }
+
+  function readFailureReason() {
+    if (available < 4) return false;
+    var len = u32(peek(4), 0);
+    if (available < 4 + len) return false;
+    return fail("refused: " + latin1(take(4 + len).subarray(4)));
+  }
+
+  function sendClientInit() {
+    send([1]); // Shared: don't disconnect other viewers
+    step = readServerInit;
+    return true;
+  }
+
+  function readServerInit() {
+    if (available < 24) return false;
+    var nameLen = u32(peek(24), 20);
+    if (available < 24 + nameLen) return false;
+    var init = take(24 + nameLen);
+    resizeCanvas(u16(init, 0), u16(init, 2));
+
+    // 32bpp little-endian true colour with red in the low byte: the bytes
+    // then land in canvas RGBA order and only alpha needs filling in
+    send([0, 0, 0, 0, 32, 24, 0, 1].concat(be16(255), be16(255), be16(255), [0, 8, 16, 0, 0, 0]));
+    var encodings = [ENCODING_COPYRECT, ENCODING_RAW, ENCODING_DESKTOP_SIZE];
+    var msg = [2, 0].concat(be16(encodings.length));
+    encodings.forEach(function (e) { msg = msg.concat(be32(e)); });
+    send(msg);
+    requestUpdate(false);
+
+    setStatus("connected", "var(--green)");
+    step = readMessage;
+    return true;
+  }
+
+  function readMessage() {
+    if (available < 1) return false;
+    var type = peek(1)[0];
+    switch (type) {
+      case 0: // FramebufferUpdate
+        if (available < 4) return false;
+        rects = u16(take(4), 2);
+        step = readRect;
+        return true;
+      case 1: // SetColourMapEntries (unused with true colour)
+        if (available < 6) return false;
+        var count = u16(peek(6), 4);
+        if (available < 6 + count * 6) return false;
+        take(6 + count * 6);
+        return true;
+      case 2: // Bell
+        take(1);
+        return true;
+      case 3: // ServerCutText
+        if (available < 8) return false;
+        var len = u32(peek(8), 4);
+        if (available < 8 + len) return false;
+        take(8 + len);
+        return true;
+      default:
+        return fail("unexpected server message " + type);
+    }
+  }
+
+  function readRect() {
+    if (rects === 0) {
+      step = readMessage;
+      scheduleUpdate();
+      return true;
+    }
+    if (!rect) {
+      if (available < 12) return false;
+      var h = take(12);
+      rect = { x: u16(h, 0), y: u16(h, 2), w: u16(h, 4), h: u16(h, 6), encoding: s32(h, 8) };
+    }
+    switch (rect.encoding) {
+      case ENCODING_RAW:
+        var size = rect.w * rect.h * 4;
+        if (available < size) return false;
+        if (size > 0) {
+          var pixels = take(size);
+          for (var i = 3; i < size; i += 4) pixels[i] = 255;
+          ctx.putImageData(new ImageData(new Uint8ClampedArray(pixels.buffer), rect.w, rect.h), rect.x, rect.y);
+        }
+        break;
+      case ENCODING_COPYRECT:
+        if (available < 4) return false;
+        var src = take(4);
+        if (rect.w > 0 && rect.h > 0) {
+          ctx.drawImage(canvas, u16(src, 0), u16(src, 2), rect.w, rect.h, rect.x, rect.y, rect.w, rect.h);
+        }
+        break;
+      case ENCODING_DESKTOP_SIZE:
+        resizeCanvas(rect.w, rect.h);
+        break;
+      default:
+        return fail("unsupported encoding " + rect.encoding);
+    }
+    rect = null;
+    rects--;
+    return true;
+  }
+
+  function resizeCanvas(w, h) {
+    canvas.width = w;
+    canvas.height = h;
+    ctx.fillStyle = "#000";
+    ctx.fillRect(0, 0, w, h);
+  }
+
+  function requestUpdate(incremental) {
+    send([3, incremental ? 1 : 0].concat(be16(0), be16(0), be16(canvas.width), be16(canvas.height)));
+  }
+
+  // Ask for the next frame once the browser has painted this one, so a
+  // slow connection doesn't queue up frames it can't keep up with
+  function scheduleUpdate() {
+    requestAnimationFrame(function () { requestUpdate(true); });
+  }
+
+  function pump() {
+    while (step && step()) {
+      /* keep going while steps make progress */
+    }
+  }
+
+  // ---------------------------------------------------------------------
+  // Input
+  // ---------------------------------------------------------------------
+
+  var KEYSYMS = {
+    Backspace: 0xff08, Tab: 0xff09, Enter: 0xff0d, Escape: 0xff1b, Delete: 0xffff,
+    Home: 0xff50, ArrowLeft: 0xff51, ArrowUp: 0xff52, ArrowRight: 0xff53, ArrowDown: 0xff54,
+    PageUp: 0xff55, PageDown: 0xff56, End: 0xff57, Insert: 0xff63,
+    Shift: 0xffe1, Control: 0xffe3, Alt: 0xffe9, AltGraph: 0xfe03, Meta: 0xffe7, OS: 0xffeb,
+    CapsLock: 0xffe5, ContextMenu: 0xff67,
+  };
+  for (var f = 1; f <= 12; f++) KEYSYMS["F" + f] = 0xffbd + f;
+
+  function keysym(e) {
+    if (KEYSYMS[e.key] !== undefined) {
+      // Right-hand modifiers have their own keysyms
+      if (e.location === 2 && e.key !== "AltGraph" && KEYSYMS[e.key] >= 0xffe1) return KEYSYMS[e.key] + 1;
+      return KEYSYMS[e.key];
+    }
+    if (e.key.length === 1 || (e.key.length === 2 && e.key.codePointAt(0) > 0xffff)) {
+      var cp = e.key.codePointAt(0);
+      return cp < 0x100 ? cp : 0x01000000 + cp;
+    }
+    return null;
+  }
+
+  // Remember what each physical key sent, so the release matches the press
+  // even if modifiers changed in between
+  var pressed = {};
+
+  function sendKey(down, sym) {
+    send([4, down ? 1 : 0, 0, 0].concat(be32(sym)));
+  }
+
+  canvas.addEventListener("keydown", function (e) {
+    if (step !== readMessage && step !== readRect) return;
+    // Let the browser turn Ctrl/Cmd+V into a paste event
+    if ((e.ctrlKey || e.metaKey) && e.key === "v") return;
+    var sym = keysym(e);
+    if (sym === null) return;
+    e.preventDefault();
+    pressed[e.code] = sym;
+    sendKey(true, sym);
+  });
+
+  canvas.addEventListener("keyup", function (e) {
+    var sym = pressed[e.code];
+    if (sym === undefined) sym = keysym(e);
+    if (sym === null) return;
+    delete pressed[e.code];
+    e.preventDefault();
+    sendKey(false, sym);
+  });
+
+  canvas.addEventListener("blur", function () {
+    Object.keys(pressed).forEach(function (code) { sendKey(false, pressed[code]); });
+    pressed = {};
+  });
+
+  canvas.addEventListener("paste", function (e) {
+    var text = (e.clipboardData || window.clipboardData).getData("text");
+    if (!text) return;
+    e.preventDefault();
+    var bytes = [];
+    for (var i = 0; i < text.length; i++) {
+      var c = text.charCodeAt(i);
+      bytes.push(c < 0x100 ? c : 0x3f); // RFB cut text is Latin-1
+    }
+    send([6, 0, 0, 0].concat(be32(bytes.length), bytes));
+  });
+
+  var buttons = 0;
+
+  function pointer(e, mask) {
+    var box = canvas.getBoundingClientRect();
+    var x = Math.round((e.clientX - box.left) * canvas.width / box.width);
+    var y = Math.round((e.clientY - box.top) * canvas.height / box.height);
+    x = Math.max(0, Math.min(canvas.width - 1, x));
+    y = Math.max(0, Math.min(canvas.height - 1, y));
+    send([5, mask].concat(be16(x), be16(y)));
+  }
+
+  // DOM button numbers → RFB mask bits (left, middle, right)
+  var BUTTON_BITS = [1, 2, 4];
+
+  canvas.addEventListener("mousedown", function (e) {
+    canvas.focus();
+    buttons |= BUTTON_BITS[e.button] || 0;
+    pointer(e, buttons);
+    e.preventDefault();
+  });
+  canvas.addEventListener("mouseup", function (e) {
+    buttons &= ~(BUTTON_BITS[e.button] || 0);
+    pointer(e, buttons);
+    e.preventDefault();
+  });
+  canvas.addEventListener("mousemove", function (e) {
+    pointer(e, buttons);
+  });
+  canvas.addEventListener("contextmenu", function (e) {
+    e.preventDefault();
+  });
+  canvas.addEventListener("wheel", function (e) {
+    if (e.deltaY === 0) return;
+    var bit = e.deltaY < 0 ? 8 : 16;
+    pointer(e, buttons | bit);
+    pointer(e, buttons);
+    e.preventDefault();
+  }, { passive: false });
+
+  // ---------------------------------------------------------------------
+  // Connection
+  // ---------------------------------------------------------------------
+
+  function connect() {
+    var proto = location.protocol === "https:" ? "wss:" : "ws:";
+    var url = proto + "//" + location.host + "/ws/sandboxes/" + sandboxId + "/vnc";
+
+    chunks = [];
+    available = 0;
+    rects = 0;
+    rect = null;
+    step = readVersion;
+
+    setStatus("connecting...", "var(--yellow)");
+    ws = new WebSocket(url);
+    ws.binaryType = "arraybuffer";
+
+    ws.onopen = function () {
+      canvas.hidden = false;
+      canvas.focus();
+      toggle.textContent = "Disconnect";
+    };
+
+    ws.onmessage = function (e) {
+      push(e.data);
+      pump();
+    };
+
+    ws.onclose = function () {
+      if (step) setStatus("disconnected", "var(--muted)");
+      step = null;
+      ws = null;
+      toggle.textContent = "Connect";
+    };
+
+    ws.onerror = function () {
+      setStatus("connection failed", "var(--red)");
+    };
+  }
+
+  toggle.addEventListener("click", function () {
+    if (ws) {
+      ws.close();
+    } else {
+      connect();
+    }
+  });
+})();
@@ -131,6 +131,7 @@ writeShellApplication {
     gh_token=false
     project_dir="."
     claude_args=()
+    property_args=()
 
     usage() {
       echo "Usage: claude-sandbox-container [OPTIONS] [project-dir] [-- claude args...]" >&2
@@ -142,6 +143,9 @@ writeShellApplication {
       echo "" >&2
       echo "  --shell     Drop into bash instead of launching claude" >&2
       echo "  --gh-token  Forward GH_TOKEN/GITHUB_TOKEN env vars into container" >&2
+      echo "  --property KEY=VALUE" >&2
+      echo "              Resource limit for the container's scope; one of MemoryMax," >&2
+      echo "              CPUQuota, TasksMax, IOWeight (repeatable)" >&2
       echo "  --enter     Open a shell INSIDE this project's running container" >&2
       echo "  --stop      Terminate this project's container" >&2
       echo "  bind        Bind-mount a host directory into a running container" >&2
@@ -151,6 +155,13 @@ writeShellApplication {
       case "$1" in
         --shell)    shell_mode=true; shift ;;
         --gh-token) gh_token=true; shift ;;
+        --property)
+          # Only limits: this runs as root on behalf of whoever may sudo it
+          if [[ ! "''${2:-}" =~ ^(MemoryMax|CPUQuota|TasksMax|IOWeight)=[0-9A-Za-z.%]+$ ]]; then
+            echo "Error: unsupported property: ''${2:-}" >&2
+            exit 1
+          fi
+          property_args+=(--property="$2"); shift 2 ;;
         --help|-h)  usage; exit 0 ;;
         --)         shift; claude_args=("$@"); break ;;
         -*)         echo "Unknown option: $1 (pass claude args after '--')" >&2; exit 1 ;;
@@ -460,6 +471,7 @@ writeShellApplication {
       "''${locale_args[@]}" \
       "''${entrypoint_args[@]}" \
       "''${console_args[@]}" \
+      "''${property_args[@]}" \
       --setenv=HOME="$real_home" \
       --setenv=CHROMIUM_USER_DATA_DIR="$chromium_profile" \
       --setenv=NIX_LD="${spec.realLoader}" \
@@ -402,6 +402,17 @@ writeShellApplication {
     rm -f "$console_sock"
     qemu_extra+=(-serial "unix:$console_sock,server,nowait")
 
+    # Control sockets requested by the sandbox manager: QMP for screendumps
+    # and a VNC server for its live display view. Unset when run by hand.
+    if [[ -n "''${CLAUDE_SANDBOX_QMP_SOCKET:-}" ]]; then
+      rm -f "$CLAUDE_SANDBOX_QMP_SOCKET"
+      qemu_extra+=(-qmp "unix:$CLAUDE_SANDBOX_QMP_SOCKET,server,nowait")
+    fi
+    if [[ -n "''${CLAUDE_SANDBOX_VNC_SOCKET:-}" ]]; then
+      rm -f "$CLAUDE_SANDBOX_VNC_SOCKET"
+      qemu_extra+=(-vnc "unix:$CLAUDE_SANDBOX_VNC_SOCKET")
+    fi
+
     host_claude_dir="''${HOME}/.claude"
     if [[ -d "$host_claude_dir" ]]; then
       qemu_extra+=(-virtfs "local,path=$host_claude_dir,mount_tag=claude_auth,security_model=none,id=claude_auth")
@@ -3,9 +3,9 @@
   lib,
   rustPlatform,
   makeWrapper,
-  imagemagick,
   socat,
   tmux,
+  x11vnc,
   xorg,
   pkg-config,
   openssl,
@@ -30,7 +30,7 @@ rustPlatform.buildRustPackage {
 
     # Wrap binary with runtime dependencies on PATH and default static dir
     wrapProgram $out/bin/claude-sandbox-manager \
-      --prefix PATH : ${lib.makeBinPath ([ imagemagick socat tmux xorg.xorgserver ] ++ sandboxPackages)} \
+      --prefix PATH : ${lib.makeBinPath ([ socat tmux x11vnc xorg.xorgserver ] ++ sandboxPackages)} \
       --set-default MANAGER_STATIC_DIR $out/share/claude-sandbox-manager/static
   '';
 
@@ -26,6 +26,16 @@ in
       description = "System user to run the manager as.";
     };
 
+    uid = lib.mkOption {
+      type = lib.types.int;
+      default = 917;
+      description = ''
+        UID of the manager user. Fixed, so the controller delegation needed
+        by sandbox scopes can be given to this user's `user@<uid>.service`
+        alone; change it if another user already has it.
+      '';
+    };
+
     group = lib.mkOption {
       type = lib.types.str;
       default = "claude-manager";
@@ -38,6 +48,101 @@ in
       description = "Sandbox backend packages to put on the manager's PATH.";
     };
 
+    scopes = lib.mkOption {
+      type = lib.types.bool;
+      default = true;
+      description = "Run each sandbox in a transient systemd scope of the manager user, with resource limits.";
+    };
+
+    defaultLimits = {
+      memoryMax = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        example = "8G";
+        description = "Default MemoryMax= of sandbox scopes.";
+      };
+      cpuQuota = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        example = "400%";
+        description = "Default CPUQuota= of sandbox scopes.";
+      };
+      tasksMax = lib.mkOption {
+        type = lib.types.nullOr lib.types.ints.positive;
+        default = null;
+        description = "Default TasksMax= of sandbox scopes.";
+      };
+      ioWeight = lib.mkOption {
+        type = lib.types.nullOr (lib.types.ints.between 1 10000);
+        default = null;
+        description = "Default IOWeight= of sandbox scopes.";
+      };
+    };
+
+    prices = lib.mkOption {
+      type = lib.types.attrsOf (lib.types.attrsOf lib.types.number);
+      default = { };
+      example = lib.literalExpression ''
+        {
+          claude-opus-4 = { input = 15; output = 75; };
+          claude-sonnet-4 = { input = 3; output = 15; };
+        }
+      '';
+      description = ''
+        USD per million tokens by model name or prefix, for cost estimates:
+        `input`, `output` and optionally `cache_creation` and `cache_read`.
+        Empty disables cost estimates and budgets.
+      '';
+    };
+
+    defaultBudget = lib.mkOption {
+      type = lib.types.nullOr lib.types.number;
+      default = null;
+      example = 20;
+      description = "Estimated cost in USD at which sandboxes created without a budget of their own are stopped.";
+    };
+
+    attentionIdle = lib.mkOption {
+      type = lib.types.ints.unsigned;
+      default = 120;
+      description = "Seconds after Claude's last reply before a sandbox counts as waiting for a prompt; 0 only flags permission prompts.";
+    };
+
+    notify = {
+      after = lib.mkOption {
+        type = lib.types.ints.unsigned;
+        default = 60;
+        description = "Seconds a sandbox must be waiting for someone before notifications go out.";
+      };
+      email = lib.mkOption {
+        type = lib.types.listOf lib.types.str;
+        default = [ ];
+        example = [ "ops@example.com" ];
+        description = "Addresses to mail a notification to when a sandbox waits for someone.";
+      };
+      smtp = lib.mkOption {
+        type = lib.types.str;
+        default = "127.0.0.1:25";
+        description = "SMTP server to send mail through, without TLS or authentication.";
+      };
+      from = lib.mkOption {
+        type = lib.types.str;
+        default = "claude-manager@localhost";
+        description = "Sender address of notification mail.";
+      };
+    };
+
+    webhooksFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "/run/secrets/claude-manager-webhooks.json";
+      description = ''
+        JSON file of webhook subscriptions (`url`, `secret` and optionally
+        `events`) that lifecycle events are POSTed to. Given as a path
+        string so the secrets stay out of the Nix store.
+      '';
+    };
+
     containerSudoers = lib.mkOption {
       type = lib.types.bool;
       default = false;
@@ -48,8 +153,11 @@ in
   config = lib.mkIf cfg.enable {
     users.users.${cfg.user} = {
       isSystemUser = true;
+      uid = cfg.uid;
       group = cfg.group;
       home = cfg.stateDir;
+      # Sandbox scopes live in the user's own systemd instance
+      linger = cfg.scopes;
     };
     users.groups.${cfg.group} = { };
 
@@ -61,6 +169,22 @@ in
       environment = {
         MANAGER_LISTEN = cfg.listenAddress;
         MANAGER_STATE_DIR = cfg.stateDir;
+        MANAGER_RUNTIME_DIR = "/run/claude-manager";
+        MANAGER_SCOPES = if cfg.scopes then "1" else "0";
+        MANAGER_ATTENTION_IDLE = toString cfg.attentionIdle;
+        MANAGER_NOTIFY_AFTER = toString cfg.notify.after;
+        MANAGER_SMTP = cfg.notify.smtp;
+        MANAGER_NOTIFY_FROM = cfg.notify.from;
+      } // lib.filterAttrs (_: v: v != null) {
+        MANAGER_SANDBOX_MEMORY_MAX = cfg.defaultLimits.memoryMax;
+        MANAGER_SANDBOX_CPU_QUOTA = cfg.defaultLimits.cpuQuota;
+        MANAGER_SANDBOX_TASKS_MAX = lib.mapNullable toString cfg.defaultLimits.tasksMax;
+        MANAGER_SANDBOX_IO_WEIGHT = lib.mapNullable toString cfg.defaultLimits.ioWeight;
+        MANAGER_PRICES =
+          if cfg.prices != { } then pkgs.writeText "claude-prices.json" (builtins.toJSON cfg.prices) else null;
+        MANAGER_SANDBOX_BUDGET = lib.mapNullable toString cfg.defaultBudget;
+        MANAGER_NOTIFY_EMAIL = if cfg.notify.email != [ ] then lib.concatStringsSep "," cfg.notify.email else null;
+        MANAGER_WEBHOOKS = cfg.webhooksFile;
       };
 
       path = cfg.sandboxPackages;
@@ -70,11 +194,18 @@ in
         User = cfg.user;
         Group = cfg.group;
         StateDirectory = "claude-manager";
+        # QMP/VNC sockets of running VMs; keep them across manager restarts
+        RuntimeDirectory = "claude-manager";
+        RuntimeDirectoryPreserve = "yes";
         Restart = "on-failure";
         RestartSec = 5;
       };
     };
 
+    # systemd only delegates memory, cpu and pids to user instances; IOWeight=
+    # needs io as well. Only the manager user's instance gets it.
+    systemd.services."user@${toString cfg.uid}".serviceConfig.Delegate = lib.mkIf cfg.scopes "cpu cpuset io memory pids";
+
     security.sudo.extraRules = lib.mkIf cfg.containerSudoers [
       {
         users = [ cfg.user ];
@@ -54,6 +54,8 @@ writeShellApplication {
       shift 2
       local cmd
       cmd="curl -s -X $(printf '%q' "$method") $(printf '%q' "localhost:$PORT$path")"
+      # Identify the local user to the manager's audit log
+      cmd+=" -H $(printf '%q' "X-Remote-User: ''${USER:-unknown}")"
       for arg in "$@"; do
         cmd+=" $(printf '%q' "$arg")"
       done
@@ -81,16 +83,31 @@ writeShellApplication {
     case "$cmd" in
       create)
         if [[ $# -lt 3 ]]; then
-          echo "Usage: claude-remote create <name> <backend> <project-dir> [--no-network] [--sync]" >&2
+          echo "Usage: claude-remote create <name> <backend> <project-dir> [--no-network] [--sync] [--resolution WxH] [--depth N]" >&2
+          echo "         [--memory-max SIZE] [--cpu-quota PCT] [--tasks-max N] [--io-weight N] [--budget USD]" >&2
           exit 1
         fi
         name="$1"; backend="$2"; project_dir="$3"; shift 3
         network=true
         do_sync=false
+        resolution=""
+        depth=""
+        memory_max=""
+        cpu_quota=""
+        tasks_max=""
+        io_weight=""
+        budget=""
         while [[ $# -gt 0 ]]; do
           case "$1" in
             --no-network) network=false ;;
             --sync) do_sync=true ;;
+            --resolution) resolution="$2"; shift ;;
+            --depth) depth="$2"; shift ;;
+            --memory-max) memory_max="$2"; shift ;;
+            --cpu-quota) cpu_quota="$2"; shift ;;
+            --tasks-max) tasks_max="$2"; shift ;;
+            --io-weight) io_weight="$2"; shift ;;
+            --budget) budget="$2"; shift ;;
           esac
           shift
         done
@@ -104,14 +121,28 @@ writeShellApplication {
           --arg backend "$backend" \
           --arg project_dir "$project_dir" \
           --argjson network "$network" \
-          '{name: $name, backend: $backend, project_dir: $project_dir, network: $network}')
+          --arg resolution "$resolution" \
+          --arg depth "$depth" \
+          --arg memory_max "$memory_max" \
+          --arg cpu_quota "$cpu_quota" \
+          --arg tasks_max "$tasks_max" \
+          --arg io_weight "$io_weight" \
+          --arg budget "$budget" \
+          '{name: $name, backend: $backend, project_dir: $project_dir, network: $network}
+           + (if $resolution != "" then {resolution: $resolution} else {} end)
+           + (if $depth != "" then {depth: ($depth | tonumber)} else {} end)
+           + (if $memory_max != "" then {memory_max: $memory_max} else {} end)
+           + (if $cpu_quota != "" then {cpu_quota: $cpu_quota} else {} end)
+           + (if $tasks_max != "" then {tasks_max: ($tasks_max | tonumber)} else {} end)
+           + (if $io_weight != "" then {io_weight: ($io_weight | tonumber)} else {} end)
+           + (if $budget != "" then {budget_usd: ($budget | tonumber)} else {} end)')
         remote_api POST /api/sandboxes \
           -H 'Content-Type: application/json' \
           -d "$payload" | jq .
         ;;
 
       list|ls)
-        remote_api GET /api/sandboxes | jq '.[] | {id: .id[0:8], name, backend, status, project_dir}'
+        remote_api GET /api/sandboxes | jq '.[] | {id: .id[0:8], name, backend, status, waiting: .attention.reason, project_dir}'
         ;;
 
       attach)
@@ -130,6 +161,38 @@ writeShellApplication {
         ssh $SSH_OPTS -t "$HOST" tmux attach -t "$full"
         ;;
 
+      send)
+        if [[ $# -lt 2 ]]; then
+          echo "Usage: claude-remote send <id-prefix> <text> [--no-enter] [--wait]" >&2
+          exit 1
+        fi
+        id_prefix="$1"; text="$2"; shift 2
+        enter=true
+        wait=false
+        while [[ $# -gt 0 ]]; do
+          case "$1" in
+            --no-enter) enter=false ;;
+            --wait) wait=true ;;
+          esac
+          shift
+        done
+        full_id=$(remote_api GET /api/sandboxes | jq -r ".[] | select(.id | startswith(\"$id_prefix\")) | .id")
+        if [[ -z "$full_id" ]]; then
+          echo "Error: no sandbox found" >&2; exit 1
+        fi
+        payload=$(jq -n \
+          --arg text "$text" \
+          --argjson enter "$enter" \
+          --argjson wait "$wait" \
+          '{text: $text, enter: $enter, wait: $wait}')
+        result=$(remote_api POST "/api/sandboxes/$full_id/input" \
+          -H 'Content-Type: application/json' \
+          -d "$payload")
+        if [[ "$wait" == "true" ]]; then
+          echo "$result" | jq -r '.output[]?'
+        fi
+        ;;
+
       stop)
         if [[ $# -lt 1 ]]; then
           echo "Usage: claude-remote stop <id-prefix>" >&2
@@ -167,11 +230,49 @@ writeShellApplication {
           fi
           echo "=== Sandbox metrics ==="
           remote_api GET "/api/sandboxes/$full_id/metrics" | jq .
+          echo "=== Sessions ==="
+          remote_api GET "/api/sandboxes/$full_id/sessions" \
+            | jq -r '.[] | "\(.id)  \(.started // "-") → \(.ended // "-")  in \(.input_tokens)  out \(.output_tokens)  tools \(.tool_use_count)"'
         fi
         echo "=== System metrics ==="
         remote_api GET /api/metrics/system | jq .
         ;;
 
+      transcript)
+        if [[ $# -lt 1 ]]; then
+          echo "Usage: claude-remote transcript <id-prefix> [search]" >&2
+          exit 1
+        fi
+        id_prefix="$1"
+        full_id=$(remote_api GET /api/sandboxes | jq -r ".[] | select(.id | startswith(\"$id_prefix\")) | .id")
+        if [[ -z "$full_id" ]]; then
+          echo "Error: no sandbox found" >&2; exit 1
+        fi
+        query="?limit=500"
+        if [[ $# -ge 2 ]]; then
+          query="$query&q=$(jq -rn --arg q "$2" '$q | @uri')"
+        fi
+        remote_api GET "/api/sandboxes/$full_id/transcript$query" \
+          | jq -r '.entries[] | "--- \(.kind)\(if .tool then " \(.tool)" else "" end)\(if .is_error then " (error)" else "" end)  \(.timestamp // "")\n\(.text)"'
+        ;;
+
+      audit)
+        query=""
+        if [[ $# -ge 1 ]]; then
+          query="?sandbox=$1"
+        fi
+        remote_api GET "/api/audit$query" | jq .
+        ;;
+
+      webhooks)
+        query=""
+        if [[ $# -ge 1 ]]; then
+          query="?sandbox=$1"
+        fi
+        remote_api GET "/api/webhooks/deliveries$query" \
+          | jq -r '.[] | "\(.timestamp)  \(.event)  \(.sandbox_id[:8])  #\(.attempt) \(.outcome)  \(.status // "-")  \(.url)\(if .error then "  " + .error else "" end)"'
+        ;;
+
       ui)
         echo "Forwarding localhost:$PORT to $HOST:$PORT"
         echo "Open http://localhost:$PORT in your browser"
@@ -251,12 +352,19 @@ writeShellApplication {
         echo "  ssh_opts = -i ~/.ssh/mykey"
         echo ""
         echo "Commands:"
-        echo "  create <name> <backend> <dir> [--no-network] [--sync]"
+        echo "  create <name> <backend> <dir> [--no-network] [--sync] [--resolution WxH] [--depth N]"
+        echo "         [--memory-max SIZE] [--cpu-quota PCT] [--tasks-max N] [--io-weight N] [--budget USD]"
         echo "  list                  List sandboxes"
         echo "  attach <id>           Attach to sandbox tmux session"
+        echo "  send <id> <text> [--no-enter] [--wait]"
+        echo "                        Type text into the sandbox (and print new output)"
         echo "  stop <id>             Stop a sandbox"
         echo "  delete <id>           Delete a sandbox"
         echo "  metrics [id]          Show system (and sandbox) metrics"
+        echo "  transcript <id> [search]"
+        echo "                        Show the latest Claude session's conversation"
+        echo "  audit [id]            Show the audit log (optionally for one sandbox)"
+        echo "  webhooks [id]         Show webhook delivery attempts (optionally for one sandbox)"
         echo "  sync <dir> [remote]   One-shot rsync local→remote"
         echo "  watch <dir> [remote]  Continuous bidirectional sync"
         echo "  ui                    Forward web dashboard via SSH tunnel"
@@ -1,6 +1,7 @@
 # NixOS VM test for the remote sandbox manager
 #
-# Exercises the full API lifecycle: list, create, stop, delete.
+# Exercises the full API lifecycle: list, create, stop, delete, with the
+# sandbox limited by a systemd scope in the manager user's own instance.
 # Uses a stub claude-sandbox (sleep) to avoid needing the real backend.
 { self }:
 
@@ -32,6 +33,13 @@
 
     server.wait_for_unit("claude-sandbox-manager")
     server.wait_for_open_port(3000)
+    # Sandbox scopes live in the lingering user's systemd instance
+    server.wait_for_unit("user@917.service")
+
+    def scope_show(unit, prop):
+        return server.succeed(
+            f"systemctl --user -M claude-manager@ show {unit} -p {prop} --value"
+        ).strip()
 
     # 1. Empty sandbox list
     result = server.succeed("curl -sf http://localhost:3000/api/sandboxes")
@@ -40,11 +48,12 @@
     # 2. System metrics returns valid JSON
     server.succeed("curl -sf http://localhost:3000/api/metrics/system | jq .")
 
-    # 3. Create a sandbox
+    # 3. Create a sandbox with limits
     server.succeed("mkdir -p /tmp/test-project")
     result = server.succeed(
         "curl -sf -X POST -H 'Content-Type: application/json' "
-        "-d '{\"name\":\"test\",\"backend\":\"bubblewrap\",\"project_dir\":\"/tmp/test-project\"}' "
+        "-d '{\"name\":\"test\",\"backend\":\"bubblewrap\",\"project_dir\":\"/tmp/test-project\","
+        "\"memory_max\":\"256M\",\"tasks_max\":64}' "
         "http://localhost:3000/api/sandboxes"
     )
     sandbox = json.loads(result)
@@ -52,6 +61,28 @@
     assert sandbox["name"] == "test"
     assert sandbox["backend"] == "bubblewrap"
     assert sandbox["status"] == "running"
+    unit = f"claude-sandbox-{sandbox_id[:8]}.scope"
+    assert sandbox["scope"] == unit, f"Expected scope {unit}, got: {sandbox['scope']}"
+
+    # 3b. The backend runs in its scope, with the limits applied; tmux
+    # starts it after the create returns
+    server.wait_until_succeeds(f"systemctl --user -M claude-manager@ is-active {unit}")
+    memory_max = scope_show(unit, "MemoryMax")
+    assert memory_max == str(256 * 1024 * 1024), f"Expected MemoryMax of 256M, got: {memory_max}"
+    tasks_max = scope_show(unit, "TasksMax")
+    assert tasks_max == "64", f"Expected TasksMax of 64, got: {tasks_max}"
+    server.succeed(
+        f"systemctl --user -M claude-manager@ show {unit} -p ControlGroup --value "
+        "| xargs -I{} sh -c 'cat /sys/fs/cgroup{}/cgroup.procs' | grep -q ."
+    )
+
+    # 3c. Invalid limits are refused without starting anything
+    server.succeed(
+        "test $(curl -s -o /dev/null -w '%{http_code}' -X POST -H 'Content-Type: application/json' "
+        "-d '{\"name\":\"bad\",\"backend\":\"bubblewrap\",\"project_dir\":\"/tmp/test-project\","
+        "\"memory_max\":\"lots\"}' "
+        "http://localhost:3000/api/sandboxes) = 400"
+    )
 
     # 4. List should have one entry
     result = server.succeed("curl -sf http://localhost:3000/api/sandboxes")
@@ -78,6 +109,10 @@
     sandbox = json.loads(result)
     assert sandbox["status"] == "stopped", f"Expected stopped, got: {sandbox['status']}"
 
+    # 6b. Stopping tore the scope down
+    server.fail(f"systemctl --user -M claude-manager@ is-active {unit}")
+    server.fail("pgrep -u claude-manager -f 'sleep 300'")
+
     # 7. Delete the sandbox (returns 204)
     server.succeed(
         f"curl -sf -X DELETE http://localhost:3000/api/sandboxes/{sandbox_id}"